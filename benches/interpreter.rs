@@ -0,0 +1,35 @@
+// Runs the programs in benches/scheme with the interpreter binary, on each
+// back end. Each run includes process startup, which is small next to the
+// programs themselves.
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use std::path::Path;
+use std::process::Command;
+
+const PROGRAMS: &[&str] = &["fib", "tak", "nqueens"];
+const BACKENDS: &[(&str, &[&str])] = &[("tree", &[]), ("vm", &["--vm"])];
+
+fn run(options: &[&str], script: &Path) {
+    let output = Command::new(env!("CARGO_BIN_EXE_rust-async"))
+        .args(options)
+        .arg(script)
+        .output()
+        .expect("failed to run the interpreter");
+    assert!(output.status.success(), "{}: {}", script.display(), String::from_utf8_lossy(&output.stderr));
+}
+
+fn programs(c: &mut Criterion) {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("benches/scheme");
+    let mut group = c.benchmark_group("programs");
+    group.sample_size(10);
+    for name in PROGRAMS {
+        let script = dir.join(format!("{}.scm", name));
+        for (backend, options) in BACKENDS {
+            group.bench_function(format!("{}/{}", name, backend), |b| b.iter(|| run(options, &script)));
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, programs);
+criterion_main!(benches);
@@ -0,0 +1,27 @@
+;; Counts the solutions to the eight queens problem: list building,
+;; closures and mutual recursion.
+(define (not x) (if x #f #t))
+
+(define (append front back)
+  (if (null? front) back (cons (car front) (append (cdr front) back))))
+
+(define (queens board-size)
+  (define (ok? row dist placed)
+    (or (null? placed)
+        (and (not (= (car placed) (+ row dist)))
+             (not (= (car placed) (- row dist)))
+             (not (= (car placed) row))
+             (ok? row (+ dist 1) (cdr placed)))))
+  (define (try candidates rest placed)
+    (if (null? candidates)
+        (if (null? rest) 1 0)
+        (+ (if (ok? (car candidates) 1 placed)
+               (try (append (cdr candidates) rest) '() (cons (car candidates) placed))
+               0)
+           (try (cdr candidates) (cons (car candidates) rest) placed))))
+  (define (count-up from to)
+    (if (> from to) '() (cons from (count-up (+ from 1) to))))
+  (try (count-up 1 board-size) '() '()))
+
+(display (queens 8))
+(newline)
@@ -0,0 +1,387 @@
+use crate::error::{Result, SchemeError};
+use crate::eval::grow_stack;
+use crate::expand;
+use crate::symbol::Symbol;
+use crate::value::Value;
+use std::rc::Rc;
+
+// The analysis pass: converts a parsed expression once into a tree of
+// `Node`s for `eval` to run. Special forms are recognized and checked here,
+// derived forms are expanded, and constants are taken out of their quotes,
+// so running a node does no name matching or copying of source code.
+//
+// Syntax errors do not fail the analysis: they become `Error` nodes, raised
+// when evaluation reaches them, as if the form had been checked on the way.
+//
+// For the tree-walker, `resolve` then gives each procedure call a frame of
+// slots for its parameters and internal defines, and turns each reference
+// to one of them into the slot's position: how many frames up, and which
+// slot. Other variables are globals, still looked up by name.
+
+pub enum Node {
+    Const(Value),
+    Var(Symbol), // Looked up by name
+    Local(Slot),
+    If(Box<Node>, Box<Node>, Option<Box<Node>>),
+    Define(String, Box<Node>),
+    DefineLocal(usize, Box<Node>), // Slot of the current frame
+    Set(String, Box<Node>),
+    SetLocal(Slot, Box<Node>),
+    Lambda(Rc<LambdaCode>),
+    Begin(Vec<Node>), // Never empty
+    Case(Box<Node>, Vec<CaseClause>),
+    Guard {
+        var: String,
+        clauses: Vec<GuardClause>,
+        body: Vec<Node>,
+        frame: Rc<Vec<String>>, // The clauses' slots: `var`, then their defines
+    },
+    Call {
+        op: Box<Node>,
+        args: Vec<Node>,
+        name: Option<Value>, // The operator when it is a name; for backtraces
+    },
+    Error(SchemeError),
+}
+
+// A lambda expression; every closure made from it shares this.
+pub struct LambdaCode {
+    pub params: Rc<Vec<String>>,
+    pub source: Rc<Value>, // The body as written, as one expression
+    pub body: Node,
+    pub frame: Rc<Vec<String>>, // A call's slots: the parameters, then the body's defines
+}
+
+// Where a local variable is: `depth` frames up from the current one, at `index`.
+#[derive(Clone, Copy)]
+pub struct Slot {
+    pub depth: usize,
+    pub index: usize,
+}
+
+pub struct CaseClause {
+    pub data: Option<Vec<Value>>, // None for `else`
+    pub body: Vec<Node>,
+}
+
+pub struct GuardClause {
+    pub test: Option<Node>, // None for `else`
+    pub body: Vec<Node>,
+}
+
+// Nodes nest as deep as the code they came from, so freeing one frees its
+// children on a grown stack, like the analysis that built them.
+impl Drop for Node {
+    fn drop(&mut self) {
+        fn take(node: &mut Node) -> Node {
+            std::mem::replace(node, Node::Const(Value::Nil))
+        }
+        let mut children = Vec::new();
+        match self {
+            Node::If(test, then, otherwise) => {
+                children.extend([take(test), take(then)]);
+                children.extend(otherwise.as_deref_mut().map(take));
+            }
+            Node::Define(_, value) | Node::DefineLocal(_, value) | Node::Set(_, value) | Node::SetLocal(_, value) => {
+                children.push(take(value))
+            }
+            Node::Begin(body) => children.append(body),
+            Node::Case(key, clauses) => {
+                children.push(take(key));
+                clauses.iter_mut().for_each(|clause| children.append(&mut clause.body));
+            }
+            Node::Guard { clauses, body, .. } => {
+                children.append(body);
+                for clause in clauses {
+                    children.extend(clause.test.take());
+                    children.append(&mut clause.body);
+                }
+            }
+            Node::Call { op, args, .. } => {
+                children.push(take(op));
+                children.append(args);
+            }
+            Node::Const(_) | Node::Var(_) | Node::Local(_) | Node::Lambda(_) | Node::Error(_) => return,
+        }
+        grow_stack(|| drop(children));
+    }
+}
+
+// Analyzes `expr`, which is evaluated as code from then on.
+pub fn analyze(expr: &Value) -> Node {
+    grow_stack(|| match expr {
+        Value::Symbol(s) if s.is_empty() => Node::Const(Value::Nil), // The parser's value for empty input
+        Value::Symbol(s) => Node::Var(s.clone()),
+        Value::List(items) => match items.split_first() {
+            None => Node::Const(Value::Nil),
+            Some((Value::Symbol(op), args)) => special_form(op.as_str(), args).unwrap_or_else(|| call(&items[0], args)),
+            Some((op, args)) => call(op, args),
+        },
+        _ => Node::Const(expr.clone()), // Self-evaluating
+    })
+}
+
+fn call(op: &Value, args: &[Value]) -> Node {
+    Node::Call {
+        op: Box::new(analyze(op)),
+        args: args.iter().map(analyze).collect(),
+        name: matches!(op, Value::Symbol(_)).then(|| op.clone()),
+    }
+}
+
+fn arity(expected: &str, got: usize) -> Node {
+    Node::Error(SchemeError::Arity { expected: expected.to_string(), got })
+}
+
+fn invalid(message: &str) -> Node {
+    Node::Error(SchemeError::Eval(message.to_string()))
+}
+
+fn body(exprs: &[Value]) -> Vec<Node> {
+    exprs.iter().map(analyze).collect()
+}
+
+// The node for a special or derived form, or `None` if `op` names neither.
+fn special_form(op: &str, args: &[Value]) -> Option<Node> {
+    let node = match op {
+        "quote" => match args {
+            [datum] => Node::Const(datum.clone()),
+            _ => arity("1", args.len()),
+        },
+        "if" => match args {
+            [test, then] => Node::If(Box::new(analyze(test)), Box::new(analyze(then)), None),
+            [test, then, otherwise] => Node::If(Box::new(analyze(test)), Box::new(analyze(then)), Some(Box::new(analyze(otherwise)))),
+            _ => arity("2 or 3", args.len()),
+        },
+        "define" => match expand::expand_once(op, args) {
+            Some(expanded) => expansion(expanded), // (define (name param ...) body ...)
+            None => assignment(op, args),
+        },
+        "set!" => assignment(op, args),
+        "lambda" => lambda(args),
+        "begin" => match args {
+            [] => Node::Const(Value::Nil),
+            _ => Node::Begin(body(args)),
+        },
+        "case" => case(args),
+        "guard" => guard(args),
+        derived => return expand::expand_once(derived, args).map(expansion),
+    };
+    Some(node)
+}
+
+// The node for a derived form's expansion.
+fn expansion(expanded: Result<Value>) -> Node {
+    match expanded {
+        Ok(expr) => analyze(&expr),
+        Err(err) => Node::Error(err),
+    }
+}
+
+// (define name value) or (set! name value)
+fn assignment(op: &str, args: &[Value]) -> Node {
+    match args {
+        [Value::Symbol(name), value] if op == "define" => Node::Define(name.to_string(), Box::new(analyze(value))),
+        [Value::Symbol(name), value] => Node::Set(name.to_string(), Box::new(analyze(value))),
+        [var, _] => Node::Error(SchemeError::Type { expected: "symbol".to_string(), found: var.type_name() }),
+        _ => arity("2", args.len()),
+    }
+}
+
+fn lambda(args: &[Value]) -> Node {
+    let Some((params_expr, body_exprs)) = args.split_first() else {
+        return invalid("Invalid lambda syntax: requires parameters and body");
+    };
+    let Value::List(param_list) = params_expr else {
+        return invalid("Lambda parameters must be a list of symbols");
+    };
+    let mut params = Vec::new();
+    for p in param_list {
+        match p {
+            Value::Symbol(s) => params.push(s.to_string()),
+            _ => return invalid("Lambda parameters must be symbols"),
+        }
+    }
+    let source = match body_exprs {
+        [only] => only.clone(),
+        _ => Value::List(std::iter::once(Value::symbol("begin")).chain(body_exprs.iter().cloned()).collect()),
+    };
+    let body = analyze(&source);
+    let params = Rc::new(params);
+    Node::Lambda(Rc::new(LambdaCode { frame: Rc::clone(&params), params, source: Rc::new(source), body }))
+}
+
+// (case key ((datum ...) expr ...) ... (else expr ...)); data are matched with eqv?
+fn case(args: &[Value]) -> Node {
+    let Some((key, clause_exprs)) = args.split_first() else {
+        return arity("at least 1", 0);
+    };
+    let mut clauses = Vec::new();
+    for clause in clause_exprs {
+        let (data, exprs) = match clause {
+            Value::List(parts) if !parts.is_empty() => (&parts[0], &parts[1..]),
+            _ => return invalid("Invalid case clause: expected ((datum ...) expr ...)"),
+        };
+        let data = match data {
+            Value::Symbol(s) if s.as_str() == "else" => None,
+            Value::List(datums) => Some(datums.clone()),
+            _ => return invalid("Invalid case clause: data must be a list"),
+        };
+        clauses.push(CaseClause { data, body: body(exprs) });
+    }
+    Node::Case(Box::new(analyze(key)), clauses)
+}
+
+// (guard (var clause ...) body ...), whose clauses are (test expr ...) as in cond.
+fn guard(args: &[Value]) -> Node {
+    let usage = "Invalid guard: expected (guard (var clause ...) body ...)";
+    let Some((Value::List(spec), body_exprs)) = args.split_first() else {
+        return invalid(usage);
+    };
+    let Some((Value::Symbol(var), clause_exprs)) = spec.split_first() else {
+        return invalid(usage);
+    };
+    let mut clauses = Vec::new();
+    for clause in clause_exprs {
+        let Some((test, exprs)) = (match clause {
+            Value::List(parts) => parts.split_first(),
+            _ => None,
+        }) else {
+            return invalid("Invalid guard clause: expected (test expr ...)");
+        };
+        let test = match test {
+            Value::Symbol(s) if s.as_str() == "else" => None,
+            _ => Some(analyze(test)),
+        };
+        clauses.push(GuardClause { test, body: body(exprs) });
+    }
+    Node::Guard { var: var.to_string(), frame: Rc::new(vec![var.to_string()]), clauses, body: body(body_exprs) }
+}
+
+// The frames code will run in, by the names of their slots, innermost last.
+// The global environment's frames have no slots.
+type Scope = Vec<Rc<Vec<String>>>;
+
+// Resolves the local variables of `node`, to run in the global environment:
+// each reference to a slot of the frames `node` creates becomes its position.
+pub fn resolve(node: &mut Node) {
+    let mut scope = vec![Rc::default()];
+    resolve_in(node, &mut scope);
+}
+
+fn find(scope: &Scope, name: &str) -> Option<Slot> {
+    scope.iter().rev().enumerate().find_map(|(depth, frame)| {
+        let index = frame.iter().rposition(|slot| slot == name)?; // The last of repeated parameters wins
+        Some(Slot { depth, index })
+    })
+}
+
+fn resolve_in(node: &mut Node, scope: &mut Scope) {
+    grow_stack(|| match node {
+        Node::Const(_) | Node::Local(_) | Node::Error(_) => {}
+        Node::Var(name) => {
+            if let Some(slot) = find(scope, name) {
+                *node = Node::Local(slot);
+            }
+        }
+        Node::If(test, then, otherwise) => {
+            resolve_in(test, scope);
+            resolve_in(then, scope);
+            if let Some(otherwise) = otherwise {
+                resolve_in(otherwise, scope);
+            }
+        }
+        Node::Define(name, value) => {
+            resolve_in(value, scope);
+            let frame = scope.last().expect("the scope has the current frame");
+            if let Some(index) = frame.iter().rposition(|slot| slot == name) {
+                let value = std::mem::replace(&mut **value, Node::Const(Value::Nil));
+                *node = Node::DefineLocal(index, Box::new(value));
+            } // Else a global
+        }
+        Node::Set(name, value) => {
+            resolve_in(value, scope);
+            if let Some(slot) = find(scope, name) {
+                let value = std::mem::replace(&mut **value, Node::Const(Value::Nil));
+                *node = Node::SetLocal(slot, Box::new(value));
+            }
+        }
+        Node::DefineLocal(_, value) | Node::SetLocal(_, value) => resolve_in(value, scope),
+        Node::Lambda(code) => {
+            let code = Rc::get_mut(code).expect("code is not shared before it is resolved");
+            let mut frame = code.params.to_vec();
+            definitions(&code.body, &mut frame);
+            code.frame = Rc::new(frame);
+            scope.push(Rc::clone(&code.frame));
+            resolve_in(&mut code.body, scope);
+            scope.pop();
+        }
+        Node::Begin(body) => body.iter_mut().for_each(|expr| resolve_in(expr, scope)),
+        Node::Case(key, clauses) => {
+            resolve_in(key, scope);
+            clauses.iter_mut().flat_map(|clause| &mut clause.body).for_each(|expr| resolve_in(expr, scope));
+        }
+        Node::Guard { var, clauses, body, frame } => {
+            body.iter_mut().for_each(|expr| resolve_in(expr, scope));
+            let mut names = vec![var.clone()];
+            for clause in clauses.iter() {
+                clause.test.iter().chain(&clause.body).for_each(|expr| definitions(expr, &mut names));
+            }
+            *frame = Rc::new(names);
+            scope.push(Rc::clone(frame));
+            for clause in clauses {
+                clause.test.iter_mut().chain(&mut clause.body).for_each(|expr| resolve_in(expr, scope));
+            }
+            scope.pop();
+        }
+        Node::Call { op, args, .. } => {
+            resolve_in(op, scope);
+            args.iter_mut().for_each(|arg| resolve_in(arg, scope));
+        }
+    })
+}
+
+// Adds the names `node` defines in the frame it runs in: not counting nested
+// lambdas, or `guard` clauses, which run in frames of their own.
+pub fn definitions(node: &Node, names: &mut Vec<String>) {
+    match node {
+        Node::Define(name, _) if !names.contains(name) => names.push(name.clone()),
+        Node::Lambda(_) => return,
+        Node::Guard { body, .. } => return body.iter().for_each(|expr| grow_stack(|| definitions(expr, names))),
+        _ => {}
+    }
+    for_each_child(node, &mut |child| grow_stack(|| definitions(child, names)));
+}
+
+// Calls `f` on each subexpression of `node`, except a lambda's body.
+pub fn for_each_child(node: &Node, f: &mut impl FnMut(&Node)) {
+    match node {
+        Node::Const(_) | Node::Var(_) | Node::Local(_) | Node::Lambda(_) | Node::Error(_) => {}
+        Node::If(test, then, otherwise) => {
+            f(test);
+            f(then);
+            if let Some(otherwise) = otherwise {
+                f(otherwise);
+            }
+        }
+        Node::Define(_, value) | Node::DefineLocal(_, value) | Node::Set(_, value) | Node::SetLocal(_, value) => f(value),
+        Node::Begin(body) => body.iter().for_each(f),
+        Node::Case(key, clauses) => {
+            f(key);
+            clauses.iter().flat_map(|clause| &clause.body).for_each(f);
+        }
+        Node::Guard { clauses, body, .. } => {
+            body.iter().for_each(&mut *f);
+            for clause in clauses {
+                if let Some(test) = &clause.test {
+                    f(test);
+                }
+                clause.body.iter().for_each(&mut *f);
+            }
+        }
+        Node::Call { op, args, .. } => {
+            f(op);
+            args.iter().for_each(f);
+        }
+    }
+}
@@ -1,26 +1,44 @@
 use crate::env::Environment;
 use crate::error::{Result, SchemeError};
-use crate::eval::evaluate; // Needed for `eval` builtin
-use crate::value::{Value, BuiltinFn};
+use crate::eval::{apply, evaluate}; // Needed for `eval` and higher-order builtins
+use crate::hashtable::{self, HashTable};
+use crate::interrupt;
+use crate::limits;
+use crate::format;
+use crate::mapdata::MapData;
+use crate::port::{self, Port, PortRef};
+use crate::pretty;
+use crate::printer::{self, Style};
+use crate::value::{eqv, Value, BuiltinFn, MapKey, SetData};
 use std::cell::RefCell;
-use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::sync::OnceLock;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
 
 // Macro to simplify arity checks
 macro_rules! check_arity {
     ($args:expr, $expected:expr, $name:expr) => {
         if $args.len() != $expected {
-            return Err(SchemeError::Arity { expected: format!("{}", $expected), got: $args.len() });
+            return Err(SchemeError::Arity { expected: format!("{} for {}", $expected, $name), got: $args.len() });
         }
     };
      ($args:expr, $min:expr, $max:expr, $name:expr) => {
          if $args.len() < $min || $args.len() > $max {
-            return Err(SchemeError::Arity { expected: format!("between {} and {}", $min, $max), got: $args.len() });
+            return Err(SchemeError::Arity { expected: format!("between {} and {} for {}", $min, $max, $name), got: $args.len() });
          }
     };
     ($args:expr, >= $min:expr, $name:expr) => {
         if $args.len() < $min {
-            return Err(SchemeError::Arity { expected: format!("at least {}", $min), got: $args.len() });
+            return Err(SchemeError::Arity { expected: format!("at least {} for {}", $min, $name), got: $args.len() });
+        }
+    };
+    ($args:expr, <= $max:expr, $name:expr) => {
+        if $args.len() > $max {
+            return Err(SchemeError::Arity { expected: format!("at most {} for {}", $max, $name), got: $args.len() });
         }
     };
 }
@@ -76,7 +94,7 @@ fn divide(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
          }
          // Scheme often defines (/ x) as 1/x. Requires floats.
          // For integers, maybe error or return 0? Let's error.
-         return Err(SchemeError::Arity { expected: "at least 2 for integer division".to_string(), got: 1 });
+         Err(SchemeError::Arity { expected: "at least 2 for integer division".to_string(), got: 1 })
      } else {
         let mut result = first;
         for val in &args[1..] {
@@ -106,7 +124,7 @@ fn less_than(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
      let mut prev = extract_int!(&args[0], "<");
      for val in &args[1..] {
          let current = extract_int!(val, "<");
-         if !(prev < current) {
+         if prev >= current {
              return Ok(Value::Bool(false));
          }
          prev = current;
@@ -118,7 +136,7 @@ fn greater_than(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value>
     let mut prev = extract_int!(&args[0], ">");
     for val in &args[1..] {
         let current = extract_int!(val, ">");
-        if !(prev > current) {
+        if prev <= current {
             return Ok(Value::Bool(false));
         }
         prev = current;
@@ -132,13 +150,13 @@ fn greater_than(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value>
 fn cons(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
     check_arity!(args, 2, "cons");
     let car = args[0].clone();
-    let cdr = args[1].clone(); // cdr can be any value for improper lists, but usually a list or Nil
+    let mut cdr = args[1].clone(); // cdr can be any value for improper lists, but usually a list or Nil
 
     // Ensure cdr is list-like if we only want proper lists easily representable
     match cdr {
-        Value::List(mut list) => {
+        Value::List(ref mut list) => {
              list.insert(0, car);
-             Ok(Value::List(list))
+             Ok(Value::List(std::mem::take(list)))
         }
          Value::Nil => {
              Ok(Value::List(vec![car]))
@@ -179,7 +197,7 @@ fn list(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
 // --- Type Predicates ---
 fn is_null(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
     check_arity!(args, 1, "null?");
-    Ok(Value::Bool(matches!(args[0], Value::Nil)))
+    Ok(Value::Bool(args[0].is_null()))
 }
 fn is_boolean(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
     check_arity!(args, 1, "boolean?");
@@ -199,11 +217,11 @@ fn is_string(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
 }
 fn is_list(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
      check_arity!(args, 1, "list?"); // Or pair? depending on definition
-    Ok(Value::Bool(matches!(args[0], Value::List(_))))
+    Ok(Value::Bool(matches!(args[0], Value::List(_) | Value::Nil)))
 }
 fn is_procedure(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
     check_arity!(args, 1, "procedure?");
-    Ok(Value::Bool(matches!(args[0], Value::Lambda{..} | Value::Builtin(..))))
+    Ok(Value::Bool(matches!(args[0], Value::Lambda{..} | Value::Builtin(..) | Value::Closure(_))))
 }
 fn is_array(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
     check_arity!(args, 1, "array?");
@@ -213,42 +231,89 @@ fn is_map(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
     check_arity!(args, 1, "map?");
     Ok(Value::Bool(matches!(args[0], Value::Map(_))))
 }
+fn is_hash_table(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
+    check_arity!(args, 1, "hash-table?");
+    Ok(Value::Bool(matches!(args[0], Value::HashTable(_))))
+}
+fn is_set(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
+    check_arity!(args, 1, "set?");
+    Ok(Value::Bool(matches!(args[0], Value::Set(_))))
+}
+fn is_char(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
+    check_arity!(args, 1, "char?");
+    Ok(Value::Bool(matches!(args[0], Value::Char(_))))
+}
+
+
+// --- Index Validation ---
+// Shared by every sequence builtin so bad indices and sizes surface as the
+// same `SchemeError::Range` instead of wrapping through an `as usize` cast.
+
+// Upper bound on a single sequence allocation. Anything larger is almost
+// certainly a bug (e.g. a negative size) and would abort the process.
+pub const MAX_SEQUENCE_LENGTH: usize = 1 << 24;
+
+// Validates a non-negative index into a sequence of length `len`.
+fn check_index(index: i64, len: usize, name: &str) -> Result<usize> {
+    if index < 0 || index as u64 >= len as u64 {
+        return Err(SchemeError::Range(format!("{}: index {} out of range for length {}", name, index, len)));
+    }
+    Ok(index as usize)
+}
+
+// Like `check_index`, but negative indices count back from the end (-1 is the last element).
+fn check_index_from_end(index: i64, len: usize, name: &str) -> Result<usize> {
+    let resolved = if index < 0 { index + len as i64 } else { index };
+    if resolved < 0 || resolved as u64 >= len as u64 {
+        return Err(SchemeError::Range(format!("{}: index {} out of range for length {}", name, index, len)));
+    }
+    Ok(resolved as usize)
+}
 
+// Validates a requested sequence length before anything is allocated.
+fn check_length(k: i64, name: &str) -> Result<usize> {
+    if k < 0 {
+        return Err(SchemeError::Range(format!("{}: length must be non-negative, got {}", name, k)));
+    }
+    if k as u64 > MAX_SEQUENCE_LENGTH as u64 {
+        return Err(SchemeError::Range(format!("{}: length {} exceeds the maximum of {}", name, k, MAX_SEQUENCE_LENGTH)));
+    }
+    limits::reserve(k as usize)?;
+    Ok(k as usize)
+}
 
 // --- Array Functions ---
 fn make_array(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
      // (make-array k) or (make-array k fill)
     check_arity!(args, 1, 2, "make-array");
-     let k = extract_int!(&args[0], "make-array") as usize;
+     let k = check_length(extract_int!(&args[0], "make-array"), "make-array")?;
      let fill = if args.len() == 2 { args[1].clone() } else { Value::Nil }; // Default fill
+     limits::allocate(k)?;
      let vec = vec![fill; k];
      Ok(Value::Array(Rc::new(RefCell::new(vec))))
 }
 
-fn array_ref(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
-     check_arity!(args, 2, "array-ref");
-     let index = extract_int!(&args[1], "array-ref") as usize;
+fn array_ref_with(args: &[Value], name: &str, resolve: fn(i64, usize, &str) -> Result<usize>) -> Result<Value> {
+     check_arity!(args, 2, name);
+     let index = extract_int!(&args[1], name);
      match &args[0] {
         Value::Array(arr_rc) => {
              let arr = arr_rc.borrow();
-            arr.get(index)
-               .cloned()
-               .ok_or_else(|| SchemeError::Runtime(format!("Array index out of bounds: {}", index)))
+             let index = resolve(index, arr.len(), name)?;
+             Ok(arr[index].clone())
         }
         _ => Err(Value::type_error("array", &args[0]))
      }
 }
 
-fn array_set(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
-    check_arity!(args, 3, "array-set!");
-    let index = extract_int!(&args[1], "array-set!") as usize;
+fn array_set_with(args: &[Value], name: &str, resolve: fn(i64, usize, &str) -> Result<usize>) -> Result<Value> {
+    check_arity!(args, 3, name);
+    let index = extract_int!(&args[1], name);
     let value = args[2].clone();
      match &args[0] {
         Value::Array(arr_rc) => {
             let mut arr = arr_rc.borrow_mut(); // Mutable borrow
-             if index >= arr.len() {
-                return Err(SchemeError::Runtime(format!("Array index out of bounds: {}", index)));
-             }
+            let index = resolve(index, arr.len(), name)?;
             arr[index] = value;
              Ok(Value::Nil) // Side-effecting functions often return Nil or unspecified
         }
@@ -256,6 +321,23 @@ fn array_set(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
      }
 }
 
+fn array_ref(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
+    array_ref_with(args, "array-ref", check_index)
+}
+
+fn array_set(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
+    array_set_with(args, "array-set!", check_index)
+}
+
+// Python-style variants: (array-ref/from-end arr -1) is the last element.
+fn array_ref_from_end(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
+    array_ref_with(args, "array-ref/from-end", check_index_from_end)
+}
+
+fn array_set_from_end(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
+    array_set_with(args, "array-set!/from-end", check_index_from_end)
+}
+
 fn array_length(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
     check_arity!(args, 1, "array-length");
     match &args[0] {
@@ -268,78 +350,1433 @@ fn array_length(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value>
 }
 
 // --- Map Functions ---
-fn make_map(_args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
-    // Could add initialization later e.g. (make-map '( (k1 v1) (k2 v2) ))
-    //check_arity!(args, 0, "make-map"); // For now, just creates empty
-    Ok(Value::Map(Rc::new(RefCell::new(HashMap::new()))))
-}
-
-fn map_ref(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
-    check_arity!(args, 2, "map-ref");
-    let key = match &args[1] {
-        Value::Symbol(s) => s.clone(),
-        Value::String(s) => s.clone(), // Allow string keys too?
-        _ => return Err(Value::type_error("symbol or string", &args[1])),
+
+// Any value can be a map key; 'a and "a" are distinct keys.
+fn map_key(val: &Value) -> MapKey {
+    MapKey(val.clone())
+}
+
+fn extract_map(val: &Value) -> Result<Rc<RefCell<MapData>>> {
+    match val {
+        Value::Map(map_rc) => Ok(Rc::clone(map_rc)),
+        _ => Err(Value::type_error("map", val)),
+    }
+}
+
+fn new_map(map: MapData) -> Result<Value> {
+    limits::allocate(map.len())?;
+    Ok(Value::Map(Rc::new(RefCell::new(map))))
+}
+
+// Snapshot of a map's entries, so procedures called while iterating may mutate the map.
+fn map_entries(map_rc: &Rc<RefCell<MapData>>) -> Vec<(Value, Value)> {
+    map_rc.borrow().iter().map(|(k, v)| (k.0.clone(), v.clone())).collect()
+}
+
+// Adds the entries of an association list of `(key value)` lists to `map`.
+fn alist_entries(alist: &Value, mut map: MapData) -> Result<MapData> {
+    let items = match alist {
+        Value::List(items) => items.as_slice(),
+        Value::Nil => &[],
+        _ => return Err(Value::type_error("association list", alist)),
     };
-    match &args[0] {
-        Value::Map(map_rc) => {
-            let map = map_rc.borrow();
-            Ok(map.get(&key)
-               .cloned()
-               .unwrap_or(Value::Nil)) // Return Nil if key not found? Or error? Nil is safer.
-                                      // Could add a third argument for default value.
+    for item in items {
+        match item {
+            Value::List(pair) if pair.len() == 2 => {
+                map.insert(map_key(&pair[0]), pair[1].clone());
+            }
+            _ => return Err(Value::type_error("(key value) list", item)),
         }
-         _ => Err(Value::type_error("map", &args[0]))
+    }
+    Ok(map)
+}
+
+// Shared by make-map and make-sorted-map: no arguments, one alist, or key/value pairs.
+fn fill_map(args: &[Value], mut map: MapData) -> Result<Value> {
+    if args.len() == 1 {
+        return new_map(alist_entries(&args[0], map)?);
+    }
+    if !args.len().is_multiple_of(2) {
+        return Err(SchemeError::Arity { expected: "an even number of key/value arguments".to_string(), got: args.len() });
+    }
+    for pair in args.chunks(2) {
+        map.insert(map_key(&pair[0]), pair[1].clone());
+    }
+    new_map(map)
+}
+
+fn make_map(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
+    // (make-map), (make-map '((k1 v1) (k2 v2))) or (make-map 'k1 v1 'k2 v2)
+    fill_map(args, MapData::new())
+}
+
+fn make_sorted_map(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
+    // Same arguments as make-map; iterates and prints in key order
+    fill_map(args, MapData::new_sorted())
+}
+
+fn is_sorted_map(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
+    check_arity!(args, 1, "sorted-map?");
+    Ok(Value::Bool(matches!(&args[0], Value::Map(m) if m.borrow().is_sorted())))
+}
+
+fn map_ref(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value> {
+    // (map-ref map key) or (map-ref map key fallback), where fallback may be a thunk
+    check_arity!(args, 2, 3, "map-ref");
+    let key = map_key(&args[1]);
+    let found = extract_map(&args[0])?.borrow().get(&key).cloned();
+    match (found, args.get(2)) {
+        (Some(value), _) => Ok(value),
+        (None, Some(fallback @ (Value::Lambda { .. } | Value::Builtin(..) | Value::Closure(_)))) => apply(fallback, vec![], env),
+        (None, Some(fallback)) => Ok(fallback.clone()),
+        (None, None) => Ok(Value::Nil), // Missing keys read as Nil; use map-has? to tell them apart
     }
 }
 
 fn map_set(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
      check_arity!(args, 3, "map-set!");
-     let key = match &args[1] {
-        Value::Symbol(s) => s.clone(),
-        Value::String(s) => s.clone(),
-        _ => return Err(Value::type_error("symbol or string", &args[1])),
-    };
-    let value = args[2].clone();
-     match &args[0] {
-        Value::Map(map_rc) => {
-             let mut map = map_rc.borrow_mut();
-            map.insert(key, value);
-             Ok(Value::Nil)
-        }
-        _ => Err(Value::type_error("map", &args[0]))
+     let key = map_key(&args[1]);
+     if extract_map(&args[0])?.borrow_mut().insert(key, args[2].clone()).is_none() {
+         limits::allocate(1)?; // A new entry
      }
+     Ok(Value::Nil)
+}
+
+fn map_has(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
+    check_arity!(args, 2, "map-has?");
+    let key = map_key(&args[1]);
+    Ok(Value::Bool(extract_map(&args[0])?.borrow().contains_key(&key)))
+}
+
+fn map_delete(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
+    check_arity!(args, 2, "map-delete!");
+    let key = map_key(&args[1]);
+    extract_map(&args[0])?.borrow_mut().remove(&key);
+    Ok(Value::Nil)
 }
 
 fn map_keys(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
      check_arity!(args, 1, "map-keys");
-     match &args[0] {
-        Value::Map(map_rc) => {
-             let map = map_rc.borrow();
-             let keys: Vec<Value> = map.keys().map(|k| Value::Symbol(k.clone())).collect(); // Return keys as symbols
-            Ok(Value::List(keys))
+     let map_rc = extract_map(&args[0])?;
+     let keys: Vec<Value> = map_rc.borrow().keys().map(|k| k.0.clone()).collect();
+     Ok(Value::List(keys))
+}
+
+fn map_values(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
+    check_arity!(args, 1, "map-values");
+    let map_rc = extract_map(&args[0])?;
+    let values: Vec<Value> = map_rc.borrow().values().cloned().collect();
+    Ok(Value::List(values))
+}
+
+fn map_to_alist(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
+    check_arity!(args, 1, "map->alist");
+    let pairs = map_entries(&extract_map(&args[0])?)
+        .into_iter()
+        .map(|(k, v)| Value::List(vec![k, v]))
+        .collect();
+    Ok(Value::List(pairs))
+}
+
+fn alist_to_map(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
+    check_arity!(args, 1, "alist->map");
+    new_map(alist_entries(&args[0], MapData::new())?)
+}
+
+fn map_count(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
+    check_arity!(args, 1, "map-count");
+    Ok(Value::Integer(extract_map(&args[0])?.borrow().len() as i64))
+}
+
+fn map_for_each(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value> {
+    // (map-for-each proc map): calls (proc key value) for every entry
+    check_arity!(args, 2, "map-for-each");
+    for (k, v) in map_entries(&extract_map(&args[1])?) {
+        apply(&args[0], vec![k, v], Rc::clone(&env))?;
+    }
+    Ok(Value::Nil)
+}
+
+fn map_map(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value> {
+    // (map-map proc map): new map with each value replaced by (proc key value)
+    check_arity!(args, 2, "map-map");
+    let map_rc = extract_map(&args[1])?;
+    let mut result = map_rc.borrow().empty_like();
+    for (k, v) in map_entries(&map_rc) {
+        let mapped = apply(&args[0], vec![k.clone(), v], Rc::clone(&env))?;
+        result.insert(MapKey(k), mapped);
+    }
+    new_map(result)
+}
+
+fn map_filter(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value> {
+    // (map-filter pred map): new map with the entries for which (pred key value) is true
+    check_arity!(args, 2, "map-filter");
+    let map_rc = extract_map(&args[1])?;
+    let mut result = map_rc.borrow().empty_like();
+    for (k, v) in map_entries(&map_rc) {
+        let keep = apply(&args[0], vec![k.clone(), v.clone()], Rc::clone(&env))?;
+        if !matches!(keep, Value::Bool(false)) {
+            result.insert(MapKey(k), v);
         }
-        _ => Err(Value::type_error("map", &args[0]))
-     }
+    }
+    new_map(result)
+}
+
+fn map_merge(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
+    // (map-merge map ...): new map of the first map's kind; on duplicate keys the later map wins
+    let mut result = match args.first() {
+        Some(first) => extract_map(first)?.borrow().empty_like(),
+        None => MapData::new(),
+    };
+    for arg in args {
+        result.extend(map_entries(&extract_map(arg)?).into_iter().map(|(k, v)| (MapKey(k), v)));
+    }
+    new_map(result)
+}
+
+fn map_update(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value> {
+    // (map-update! map key proc [default]): stores (proc current), using default when key is missing
+    check_arity!(args, 3, 4, "map-update!");
+    let map_rc = extract_map(&args[0])?;
+    let key = map_key(&args[1]);
+    let current = map_rc.borrow().get(&key).cloned();
+    let current = match (current, args.get(3)) {
+        (Some(value), _) => value,
+        (None, Some(default)) => default.clone(),
+        (None, None) => return Err(SchemeError::Runtime(format!("map-update!: key not found: {:?}", key.0))),
+    };
+    let updated = apply(&args[2], vec![current], env)?;
+    if map_rc.borrow_mut().insert(key, updated).is_none() {
+        limits::allocate(1)?; // A new entry
+    }
+    Ok(Value::Nil)
+}
+
+// Key-ordered queries; these work on any map, but sorted maps avoid a scan.
+fn map_range(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
+    // (map-range map lo hi): new sorted map of the entries with lo <= key < hi; () leaves a bound open
+    check_arity!(args, 3, "map-range");
+    let bound = |v: &Value| match v {
+        Value::Nil => None,
+        Value::List(items) if items.is_empty() => None,
+        _ => Some(map_key(v)),
+    };
+    let (lo, hi) = (bound(&args[1]), bound(&args[2]));
+    let mut result = MapData::new_sorted();
+    result.extend(extract_map(&args[0])?.borrow().range(lo.as_ref(), hi.as_ref()));
+    new_map(result)
+}
+
+fn map_min(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
+    // (map-min map): the (key value) entry with the smallest key, or () when empty
+    check_arity!(args, 1, "map-min");
+    let map_rc = extract_map(&args[0])?;
+    let entry = map_rc.borrow().min().map(|(k, v)| Value::List(vec![k.0.clone(), v.clone()]));
+    Ok(entry.unwrap_or(Value::Nil))
+}
+
+fn map_max(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
+    // (map-max map): the (key value) entry with the largest key, or () when empty
+    check_arity!(args, 1, "map-max");
+    let map_rc = extract_map(&args[0])?;
+    let entry = map_rc.borrow().max().map(|(k, v)| Value::List(vec![k.0.clone(), v.clone()]));
+    Ok(entry.unwrap_or(Value::Nil))
+}
+
+// --- Hash Tables (SRFI-69) ---
+fn extract_table(val: &Value) -> Result<Rc<RefCell<HashTable>>> {
+    match val {
+        Value::HashTable(table) => Ok(Rc::clone(table)),
+        _ => Err(Value::type_error("hash-table", val)),
+    }
+}
+
+fn make_hash_table(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
+    // (make-hash-table [equal-proc [hash-proc]]); defaults to equal? and hash
+    check_arity!(args, <= 2, "make-hash-table");
+    for proc in args {
+        if !matches!(proc, Value::Lambda { .. } | Value::Builtin(..) | Value::Closure(_)) {
+            return Err(Value::type_error("procedure", proc));
+        }
+    }
+    let equal = args.first().cloned().unwrap_or_else(|| Value::Builtin(equal_q, "equal?".to_string()));
+    let hash_proc = args.get(1).cloned().unwrap_or_else(|| Value::Builtin(hash, "hash".to_string()));
+    Ok(Value::HashTable(Rc::new(RefCell::new(HashTable::new(equal, hash_proc)))))
+}
+
+fn hash(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
+    // (hash obj [bound]): structural hash consistent with equal?
+    check_arity!(args, 1, 2, "hash");
+    let h = hashtable::structural_hash(&args[0]);
+    match args.get(1) {
+        Some(bound) => {
+            let bound = extract_int!(bound, "hash");
+            if bound <= 0 {
+                return Err(SchemeError::Range(format!("hash: bound must be positive, got {}", bound)));
+            }
+            Ok(Value::Integer(h.rem_euclid(bound)))
+        }
+        None => Ok(Value::Integer(h)),
+    }
+}
+
+fn hash_table_set(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value> {
+    check_arity!(args, 3, "hash-table-set!");
+    hashtable::insert(&extract_table(&args[0])?, args[1].clone(), args[2].clone(), &env)?;
+    Ok(Value::Nil)
+}
+
+fn hash_table_ref(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value> {
+    // (hash-table-ref table key [thunk]): calls thunk, or errors, when key is missing
+    check_arity!(args, 2, 3, "hash-table-ref");
+    match (hashtable::get(&extract_table(&args[0])?, &args[1], &env)?, args.get(2)) {
+        (Some(value), _) => Ok(value),
+        (None, Some(thunk)) => apply(thunk, vec![], env),
+        (None, None) => Err(SchemeError::Runtime(format!("hash-table-ref: key not found: {:?}", args[1]))),
+    }
+}
+
+fn hash_table_ref_default(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value> {
+    check_arity!(args, 3, "hash-table-ref/default");
+    Ok(hashtable::get(&extract_table(&args[0])?, &args[1], &env)?.unwrap_or_else(|| args[2].clone()))
+}
+
+fn hash_table_delete(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value> {
+    check_arity!(args, 2, "hash-table-delete!");
+    hashtable::remove(&extract_table(&args[0])?, &args[1], &env)?;
+    Ok(Value::Nil)
+}
+
+fn hash_table_contains(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value> {
+    check_arity!(args, 2, "hash-table-contains?");
+    Ok(Value::Bool(hashtable::get(&extract_table(&args[0])?, &args[1], &env)?.is_some()))
+}
+
+fn hash_table_size(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
+    check_arity!(args, 1, "hash-table-size");
+    Ok(Value::Integer(extract_table(&args[0])?.borrow().len() as i64))
+}
+
+fn hash_table_keys(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
+    check_arity!(args, 1, "hash-table-keys");
+    let entries = extract_table(&args[0])?.borrow().entries();
+    Ok(Value::List(entries.into_iter().map(|(k, _)| k).collect()))
+}
+
+fn hash_table_values(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
+    check_arity!(args, 1, "hash-table-values");
+    let entries = extract_table(&args[0])?.borrow().entries();
+    Ok(Value::List(entries.into_iter().map(|(_, v)| v).collect()))
+}
+
+fn hash_table_to_alist(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
+    check_arity!(args, 1, "hash-table->alist");
+    let entries = extract_table(&args[0])?.borrow().entries();
+    Ok(Value::List(entries.into_iter().map(|(k, v)| Value::List(vec![k, v])).collect()))
+}
+
+fn hash_table_walk(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value> {
+    // (hash-table-walk table proc): calls (proc key value) for every entry
+    check_arity!(args, 2, "hash-table-walk");
+    let entries = extract_table(&args[0])?.borrow().entries();
+    for (k, v) in entries {
+        apply(&args[1], vec![k, v], Rc::clone(&env))?;
+    }
+    Ok(Value::Nil)
+}
+
+fn hash_table_update(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value> {
+    // (hash-table-update! table key proc [thunk])
+    check_arity!(args, 3, 4, "hash-table-update!");
+    let table = extract_table(&args[0])?;
+    let current = match (hashtable::get(&table, &args[1], &env)?, args.get(3)) {
+        (Some(value), _) => value,
+        (None, Some(thunk)) => apply(thunk, vec![], Rc::clone(&env))?,
+        (None, None) => return Err(SchemeError::Runtime(format!("hash-table-update!: key not found: {:?}", args[1]))),
+    };
+    let updated = apply(&args[2], vec![current], Rc::clone(&env))?;
+    hashtable::insert(&table, args[1].clone(), updated, &env)?;
+    Ok(Value::Nil)
+}
+
+fn hash_table_update_default(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value> {
+    // (hash-table-update!/default table key proc default)
+    check_arity!(args, 4, "hash-table-update!/default");
+    let table = extract_table(&args[0])?;
+    let current = hashtable::get(&table, &args[1], &env)?.unwrap_or_else(|| args[3].clone());
+    let updated = apply(&args[2], vec![current], Rc::clone(&env))?;
+    hashtable::insert(&table, args[1].clone(), updated, &env)?;
+    Ok(Value::Nil)
+}
+
+fn hash_table_equivalence_function(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
+    check_arity!(args, 1, "hash-table-equivalence-function");
+    Ok(extract_table(&args[0])?.borrow().equal_proc().clone())
+}
+
+fn hash_table_hash_function(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
+    check_arity!(args, 1, "hash-table-hash-function");
+    Ok(extract_table(&args[0])?.borrow().hash_proc().clone())
+}
+
+// --- Sets ---
+fn extract_set(val: &Value) -> Result<Rc<RefCell<SetData>>> {
+    match val {
+        Value::Set(set) => Ok(Rc::clone(set)),
+        _ => Err(Value::type_error("set", val)),
+    }
+}
+
+fn new_set(set: SetData) -> Result<Value> {
+    limits::allocate(set.len())?;
+    Ok(Value::Set(Rc::new(RefCell::new(set))))
+}
+
+fn make_set(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
+    // (make-set elem ...)
+    new_set(args.iter().map(map_key).collect())
+}
+
+fn list_to_set(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
+    check_arity!(args, 1, "list->set");
+    match &args[0] {
+        Value::List(items) => new_set(items.iter().map(map_key).collect()),
+        Value::Nil => new_set(SetData::new()),
+        other => Err(Value::type_error("list", other)),
+    }
+}
+
+fn set_to_list(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
+    check_arity!(args, 1, "set->list");
+    let set = extract_set(&args[0])?;
+    let elems: Vec<Value> = set.borrow().iter().map(|k| k.0.clone()).collect();
+    Ok(Value::List(elems))
+}
+
+fn set_add(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
+    // (set-add! set elem ...)
+    check_arity!(args, >= 1, "set-add!");
+    let set = extract_set(&args[0])?;
+    let before = set.borrow().len();
+    set.borrow_mut().extend(args[1..].iter().map(map_key));
+    limits::allocate(set.borrow().len() - before)?;
+    Ok(Value::Nil)
+}
+
+fn set_remove(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
+    // (set-remove! set elem ...)
+    check_arity!(args, >= 1, "set-remove!");
+    let set = extract_set(&args[0])?;
+    for elem in &args[1..] {
+        set.borrow_mut().shift_remove(&map_key(elem));
+    }
+    Ok(Value::Nil)
+}
+
+fn set_contains(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
+    check_arity!(args, 2, "set-contains?");
+    Ok(Value::Bool(extract_set(&args[0])?.borrow().contains(&map_key(&args[1]))))
+}
+
+fn set_size(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
+    check_arity!(args, 1, "set-size");
+    Ok(Value::Integer(extract_set(&args[0])?.borrow().len() as i64))
+}
+
+// The set algebra builtins take one or more sets and return a new set,
+// keeping the element order of the first argument.
+fn set_union(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
+    check_arity!(args, >= 1, "set-union");
+    let mut result = SetData::new();
+    for arg in args {
+        result.extend(extract_set(arg)?.borrow().iter().cloned());
+    }
+    new_set(result)
+}
+
+fn set_intersection(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
+    check_arity!(args, >= 1, "set-intersection");
+    let mut result = extract_set(&args[0])?.borrow().clone();
+    for arg in &args[1..] {
+        let other = extract_set(arg)?;
+        let other = other.borrow();
+        result.retain(|k| other.contains(k));
+    }
+    new_set(result)
+}
+
+fn set_difference(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
+    check_arity!(args, >= 1, "set-difference");
+    let mut result = extract_set(&args[0])?.borrow().clone();
+    for arg in &args[1..] {
+        let other = extract_set(arg)?;
+        let other = other.borrow();
+        result.retain(|k| !other.contains(k));
+    }
+    new_set(result)
+}
+
+// --- Characters and Strings ---
+fn char_to_integer(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
+    check_arity!(args, 1, "char->integer");
+    match &args[0] {
+        Value::Char(c) => Ok(Value::Integer(*c as i64)),
+        other => Err(Value::type_error("char", other)),
+    }
+}
+
+fn integer_to_char(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
+    check_arity!(args, 1, "integer->char");
+    let n = extract_int!(&args[0], "integer->char");
+    u32::try_from(n)
+        .ok()
+        .and_then(char::from_u32)
+        .map(Value::Char)
+        .ok_or_else(|| SchemeError::Range(format!("integer->char: {} is not a Unicode scalar value", n)))
+}
+
+fn string_equal(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
+    check_arity!(args, >= 1, "string=?");
+    let mut strs = Vec::with_capacity(args.len());
+    for arg in args {
+        match arg {
+            Value::String(s) => strs.push(s),
+            other => return Err(Value::type_error("string", other)),
+        }
+    }
+    Ok(Value::Bool(strs.windows(2).all(|w| w[0] == w[1])))
+}
+
+fn symbol_to_string(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
+    check_arity!(args, 1, "symbol->string");
+    match &args[0] {
+        Value::Symbol(s) => Ok(Value::String(s.to_string())),
+        other => Err(Value::type_error("symbol", other)),
+    }
+}
+
+fn string_to_symbol(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
+    check_arity!(args, 1, "string->symbol");
+    match &args[0] {
+        Value::String(s) => Ok(Value::symbol(s)),
+        other => Err(Value::type_error("string", other)),
+    }
+}
+
+// --- Bytevectors ---
+fn extract_bytevector(value: &Value) -> Result<Rc<RefCell<Vec<u8>>>> {
+    match value {
+        Value::Bytevector(bytes) => Ok(Rc::clone(bytes)),
+        other => Err(Value::type_error("bytevector", other)),
+    }
+}
+
+fn extract_byte(value: &Value, name: &str) -> Result<u8> {
+    let n = extract_int!(value, name);
+    u8::try_from(n).map_err(|_| SchemeError::Range(format!("{}: {} is not a byte (0 to 255)", name, n)))
+}
+
+// The optional `start` and `end` arguments at `args[first..]`, defaulting to
+// the whole sequence of length `len`.
+fn check_range(args: &[Value], first: usize, len: usize, name: &str) -> Result<(usize, usize)> {
+    let bound = |i: usize, default: usize| -> Result<usize> {
+        let Some(arg) = args.get(i) else {
+            return Ok(default);
+        };
+        let n = extract_int!(arg, name);
+        if n < 0 || n as u64 > len as u64 {
+            return Err(SchemeError::Range(format!("{}: index {} out of range for length {}", name, n, len)));
+        }
+        Ok(n as usize)
+    };
+    let (start, end) = (bound(first, 0)?, bound(first + 1, len)?);
+    if start > end {
+        return Err(SchemeError::Range(format!("{}: start {} is after end {}", name, start, end)));
+    }
+    Ok((start, end))
+}
+
+fn new_bytevector(bytes: Vec<u8>) -> Result<Value> {
+    limits::allocate(bytes.len())?;
+    Ok(Value::Bytevector(Rc::new(RefCell::new(bytes))))
+}
+
+fn is_bytevector(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
+    check_arity!(args, 1, "bytevector?");
+    Ok(Value::Bool(matches!(args[0], Value::Bytevector(_))))
+}
+
+// (make-bytevector k [fill]): fill defaults to 0.
+fn make_bytevector(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
+    check_arity!(args, 1, 2, "make-bytevector");
+    let k = check_length(extract_int!(&args[0], "make-bytevector"), "make-bytevector")?;
+    let fill = match args.get(1) {
+        Some(arg) => extract_byte(arg, "make-bytevector")?,
+        None => 0,
+    };
+    new_bytevector(vec![fill; k])
+}
+
+fn bytevector(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
+    let bytes = args.iter().map(|arg| extract_byte(arg, "bytevector")).collect::<Result<_>>()?;
+    new_bytevector(bytes)
+}
+
+fn bytevector_length(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
+    check_arity!(args, 1, "bytevector-length");
+    Ok(Value::Integer(extract_bytevector(&args[0])?.borrow().len() as i64))
+}
+
+fn bytevector_u8_ref(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
+    check_arity!(args, 2, "bytevector-u8-ref");
+    let bytes = extract_bytevector(&args[0])?;
+    let bytes = bytes.borrow();
+    let index = check_index(extract_int!(&args[1], "bytevector-u8-ref"), bytes.len(), "bytevector-u8-ref")?;
+    Ok(Value::Integer(bytes[index] as i64))
+}
+
+fn bytevector_u8_set(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
+    check_arity!(args, 3, "bytevector-u8-set!");
+    let bytes = extract_bytevector(&args[0])?;
+    let mut bytes = bytes.borrow_mut();
+    let index = check_index(extract_int!(&args[1], "bytevector-u8-set!"), bytes.len(), "bytevector-u8-set!")?;
+    bytes[index] = extract_byte(&args[2], "bytevector-u8-set!")?;
+    Ok(Value::Nil)
+}
+
+// (bytevector-copy bv [start [end]]): a new bytevector.
+fn bytevector_copy(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
+    check_arity!(args, 1, 3, "bytevector-copy");
+    let bytes = extract_bytevector(&args[0])?;
+    let bytes = bytes.borrow();
+    let (start, end) = check_range(args, 1, bytes.len(), "bytevector-copy")?;
+    new_bytevector(bytes[start..end].to_vec())
+}
+
+// (bytevector-copy! to at from [start [end]]): copies into `to` starting at
+// index `at`. `to` and `from` may be the same bytevector.
+fn bytevector_copy_into(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
+    check_arity!(args, 3, 5, "bytevector-copy!");
+    let to = extract_bytevector(&args[0])?;
+    let source = extract_bytevector(&args[2])?.borrow().clone();
+    let (start, end) = check_range(args, 3, source.len(), "bytevector-copy!")?;
+    let mut to = to.borrow_mut();
+    let at = extract_int!(&args[1], "bytevector-copy!");
+    if at < 0 || at as u64 + (end - start) as u64 > to.len() as u64 {
+        return Err(SchemeError::Range(format!("bytevector-copy!: {} bytes do not fit at index {} of length {}", end - start, at, to.len())));
+    }
+    let at = at as usize;
+    to[at..at + (end - start)].copy_from_slice(&source[start..end]);
+    Ok(Value::Nil)
+}
+
+fn bytevector_append(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
+    let mut out = Vec::new();
+    for arg in args {
+        out.extend_from_slice(&extract_bytevector(arg)?.borrow());
+    }
+    new_bytevector(out)
+}
+
+// (utf8->string bv [start [end]]): decoding fails on invalid UTF-8.
+fn utf8_to_string(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
+    check_arity!(args, 1, 3, "utf8->string");
+    let bytes = extract_bytevector(&args[0])?;
+    let bytes = bytes.borrow();
+    let (start, end) = check_range(args, 1, bytes.len(), "utf8->string")?;
+    match std::str::from_utf8(&bytes[start..end]) {
+        Ok(text) => Ok(Value::String(text.to_string())),
+        Err(e) => Err(SchemeError::Runtime(format!("utf8->string: invalid UTF-8 at byte {}", start + e.valid_up_to()))),
+    }
+}
+
+// (string->utf8 str [start [end]]): `start` and `end` count chars.
+fn string_to_utf8(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
+    check_arity!(args, 1, 3, "string->utf8");
+    let text = match &args[0] {
+        Value::String(s) => s,
+        other => return Err(Value::type_error("string", other)),
+    };
+    let (start, end) = check_range(args, 1, text.chars().count(), "string->utf8")?;
+    let slice: String = text.chars().skip(start).take(end - start).collect();
+    new_bytevector(slice.into_bytes())
+}
+
+// Fixed-width integer accessors: (bytevector-u32-le-ref bv k) reads the
+// little-endian unsigned 32-bit integer at byte index k, and
+// (bytevector-u32-le-set! bv k n) stores n there. Values that do not fit the
+// type (or, for u64, an i64) raise a range error.
+macro_rules! bytevector_int_accessors {
+    ($($ty:ty, $ref_fn:ident, $ref_name:literal, $set_fn:ident, $set_name:literal, $from_bytes:ident, $to_bytes:ident;)*) => {$(
+        fn $ref_fn(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
+            check_arity!(args, 2, $ref_name);
+            let bytes = extract_bytevector(&args[0])?;
+            let bytes = bytes.borrow();
+            let at = check_width_at(extract_int!(&args[1], $ref_name), size_of::<$ty>(), bytes.len(), $ref_name)?;
+            let n = <$ty>::$from_bytes(bytes[at..at + size_of::<$ty>()].try_into().expect("slice has the type's width"));
+            i64::try_from(n)
+                .map(Value::Integer)
+                .map_err(|_| SchemeError::Range(format!("{}: {} does not fit in an integer", $ref_name, n)))
+        }
+
+        fn $set_fn(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
+            check_arity!(args, 3, $set_name);
+            let bytes = extract_bytevector(&args[0])?;
+            let mut bytes = bytes.borrow_mut();
+            let at = check_width_at(extract_int!(&args[1], $set_name), size_of::<$ty>(), bytes.len(), $set_name)?;
+            let n = extract_int!(&args[2], $set_name);
+            let n = <$ty>::try_from(n)
+                .map_err(|_| SchemeError::Range(format!("{}: {} out of range for {}", $set_name, n, stringify!($ty))))?;
+            bytes[at..at + size_of::<$ty>()].copy_from_slice(&n.$to_bytes());
+            Ok(Value::Nil)
+        }
+    )*};
+}
+
+// Checks that `width` bytes starting at `index` lie inside a bytevector of length `len`.
+fn check_width_at(index: i64, width: usize, len: usize, name: &str) -> Result<usize> {
+    if index < 0 || index as u64 + width as u64 > len as u64 {
+        return Err(SchemeError::Range(format!("{}: index {} out of range for {} bytes in length {}", name, index, width, len)));
+    }
+    Ok(index as usize)
 }
 
-// --- Other ---
+bytevector_int_accessors! {
+    u16, bytevector_u16_le_ref, "bytevector-u16-le-ref", bytevector_u16_le_set, "bytevector-u16-le-set!", from_le_bytes, to_le_bytes;
+    u16, bytevector_u16_be_ref, "bytevector-u16-be-ref", bytevector_u16_be_set, "bytevector-u16-be-set!", from_be_bytes, to_be_bytes;
+    i16, bytevector_s16_le_ref, "bytevector-s16-le-ref", bytevector_s16_le_set, "bytevector-s16-le-set!", from_le_bytes, to_le_bytes;
+    i16, bytevector_s16_be_ref, "bytevector-s16-be-ref", bytevector_s16_be_set, "bytevector-s16-be-set!", from_be_bytes, to_be_bytes;
+    u32, bytevector_u32_le_ref, "bytevector-u32-le-ref", bytevector_u32_le_set, "bytevector-u32-le-set!", from_le_bytes, to_le_bytes;
+    u32, bytevector_u32_be_ref, "bytevector-u32-be-ref", bytevector_u32_be_set, "bytevector-u32-be-set!", from_be_bytes, to_be_bytes;
+    i32, bytevector_s32_le_ref, "bytevector-s32-le-ref", bytevector_s32_le_set, "bytevector-s32-le-set!", from_le_bytes, to_le_bytes;
+    i32, bytevector_s32_be_ref, "bytevector-s32-be-ref", bytevector_s32_be_set, "bytevector-s32-be-set!", from_be_bytes, to_be_bytes;
+    u64, bytevector_u64_le_ref, "bytevector-u64-le-ref", bytevector_u64_le_set, "bytevector-u64-le-set!", from_le_bytes, to_le_bytes;
+    u64, bytevector_u64_be_ref, "bytevector-u64-be-ref", bytevector_u64_be_set, "bytevector-u64-be-set!", from_be_bytes, to_be_bytes;
+    i64, bytevector_s64_le_ref, "bytevector-s64-le-ref", bytevector_s64_le_set, "bytevector-s64-le-set!", from_le_bytes, to_le_bytes;
+    i64, bytevector_s64_be_ref, "bytevector-s64-be-ref", bytevector_s64_be_set, "bytevector-s64-be-set!", from_be_bytes, to_be_bytes;
+}
+
+// --- Ports ---
+fn extract_port(value: &Value) -> Result<PortRef> {
+    match value {
+        Value::Port(port) => Ok(Rc::clone(port)),
+        other => Err(Value::type_error("port", other)),
+    }
+}
+
+// The optional port argument of an output procedure, defaulting to the current output port.
+fn output_port_arg(arg: Option<&Value>) -> Result<PortRef> {
+    arg.map_or_else(|| Ok(port::current_output()), extract_port)
+}
+
+fn input_port_arg(arg: Option<&Value>) -> Result<PortRef> {
+    arg.map_or_else(|| Ok(port::current_input()), extract_port)
+}
+
+fn current_input_port(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
+    check_arity!(args, 0, "current-input-port");
+    Ok(Value::Port(port::current_input()))
+}
+
+fn current_output_port(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
+    check_arity!(args, 0, "current-output-port");
+    Ok(Value::Port(port::current_output()))
+}
+
+fn current_error_port(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
+    check_arity!(args, 0, "current-error-port");
+    Ok(Value::Port(port::current_error()))
+}
+
+fn open_input_string(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
+    check_arity!(args, 1, "open-input-string");
+    match &args[0] {
+        Value::String(s) => Ok(Value::Port(Port::input_string(s))),
+        other => Err(Value::type_error("string", other)),
+    }
+}
+
+fn open_output_string(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
+    check_arity!(args, 0, "open-output-string");
+    Ok(Value::Port(Port::output_string()))
+}
+
+fn get_output_string(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
+    check_arity!(args, 1, "get-output-string");
+    let port = extract_port(&args[0])?;
+    let port = port.borrow();
+    match port.output_string_contents() {
+        Some(text) => Ok(Value::String(text.to_string())),
+        None => Err(Value::type_error("string output port", &args[0])),
+    }
+}
+
+// (with-output-to-string thunk): calls `thunk` with output going to a fresh
+// string port and returns what it wrote.
+fn with_output_to_string(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value> {
+    check_arity!(args, 1, "with-output-to-string");
+    let out = Port::output_string();
+    port::with_current_output(Rc::clone(&out), || apply(&args[0], Vec::new(), env))?;
+    let text = out.borrow().output_string_contents().unwrap_or_default().to_string();
+    Ok(Value::String(text))
+}
+
+fn extract_path(value: &Value) -> Result<&str> {
+    match value {
+        Value::String(path) => Ok(path),
+        other => Err(Value::type_error("string", other)),
+    }
+}
+
+fn open_input_file(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
+    check_arity!(args, 1, "open-input-file");
+    let path = extract_path(&args[0])?;
+    Port::open_input_file(path)
+        .map(Value::Port)
+        .map_err(|e| SchemeError::io("open-input-file", path, &e))
+}
+
+fn open_output_file(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
+    check_arity!(args, 1, "open-output-file");
+    let path = extract_path(&args[0])?;
+    Port::open_output_file(path)
+        .map(Value::Port)
+        .map_err(|e| SchemeError::io("open-output-file", path, &e))
+}
+
+fn close_port(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
+    check_arity!(args, 1, "close-port");
+    extract_port(&args[0])?.borrow_mut().close()?;
+    Ok(Value::Nil)
+}
+
+// (call-with-port port proc): calls `proc` with `port`, then closes the port
+// and returns what `proc` returned. The port stays open if `proc` fails.
+fn call_with_port(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value> {
+    check_arity!(args, 2, "call-with-port");
+    let port = extract_port(&args[0])?;
+    let result = apply(&args[1], vec![args[0].clone()], env)?;
+    port.borrow_mut().close()?;
+    Ok(result)
+}
+
+fn is_port(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
+    check_arity!(args, 1, "port?");
+    Ok(Value::Bool(matches!(args[0], Value::Port(_))))
+}
+
+fn is_input_port(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
+    check_arity!(args, 1, "input-port?");
+    Ok(Value::Bool(matches!(&args[0], Value::Port(port) if port.borrow().is_input())))
+}
+
+fn is_output_port(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
+    check_arity!(args, 1, "output-port?");
+    Ok(Value::Bool(matches!(&args[0], Value::Port(port) if !port.borrow().is_input())))
+}
+
+fn is_port_open(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
+    check_arity!(args, 1, "port-open?");
+    Ok(Value::Bool(extract_port(&args[0])?.borrow().is_open()))
+}
+
+// (read-char [port]) and (peek-char [port]) return the eof object at end of input.
+fn read_char(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
+    check_arity!(args, <= 1, "read-char");
+    let c = input_port_arg(args.first())?.borrow_mut().read_char()?;
+    Ok(c.map_or(Value::Eof, Value::Char))
+}
+
+fn peek_char(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
+    check_arity!(args, <= 1, "peek-char");
+    let c = input_port_arg(args.first())?.borrow_mut().peek_char()?;
+    Ok(c.map_or(Value::Eof, Value::Char))
+}
+
+// (read [port]): the next datum, parsed like source code.
+fn read(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
+    check_arity!(args, <= 1, "read");
+    let datum = input_port_arg(args.first())?.borrow_mut().read_datum()?;
+    Ok(datum.unwrap_or(Value::Eof))
+}
+
+// (read-line [port]): the next line without its line ending.
+fn read_line(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
+    check_arity!(args, <= 1, "read-line");
+    let line = input_port_arg(args.first())?.borrow_mut().read_line()?;
+    Ok(line.map_or(Value::Eof, Value::String))
+}
+
+// (read-string k [port]): up to `k` chars.
+fn read_string(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
+    check_arity!(args, 1, 2, "read-string");
+    let k = extract_int!(&args[0], "read-string");
+    let k = check_length(k, "read-string")?;
+    let text = input_port_arg(args.get(1))?.borrow_mut().read_string(k)?;
+    Ok(text.map_or(Value::Eof, Value::String))
+}
+
+fn char_ready(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
+    check_arity!(args, <= 1, "char-ready?");
+    Ok(Value::Bool(input_port_arg(args.first())?.borrow().char_ready()?))
+}
+
+fn eof_object(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
+    check_arity!(args, 0, "eof-object");
+    Ok(Value::Eof)
+}
+
+fn is_eof_object(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
+    check_arity!(args, 1, "eof-object?");
+    Ok(Value::Bool(matches!(args[0], Value::Eof)))
+}
+
+// --- Binary Ports ---
+// The binary procedures take an explicit port, or default to the current
+// port like their textual counterparts (which fails unless it is binary).
+fn open_input_bytevector(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
+    check_arity!(args, 1, "open-input-bytevector");
+    Ok(Value::Port(Port::input_bytevector(&extract_bytevector(&args[0])?.borrow())))
+}
+
+fn open_output_bytevector(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
+    check_arity!(args, 0, "open-output-bytevector");
+    Ok(Value::Port(Port::output_bytevector()))
+}
+
+fn get_output_bytevector(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
+    check_arity!(args, 1, "get-output-bytevector");
+    let port = extract_port(&args[0])?;
+    let port = port.borrow();
+    match port.output_bytevector_contents() {
+        Some(bytes) => new_bytevector(bytes.to_vec()),
+        None => Err(Value::type_error("bytevector output port", &args[0])),
+    }
+}
+
+fn open_binary_input_file(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
+    check_arity!(args, 1, "open-binary-input-file");
+    let path = extract_path(&args[0])?;
+    Port::open_binary_input_file(path)
+        .map(Value::Port)
+        .map_err(|e| SchemeError::io("open-binary-input-file", path, &e))
+}
+
+fn open_binary_output_file(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
+    check_arity!(args, 1, "open-binary-output-file");
+    let path = extract_path(&args[0])?;
+    Port::open_binary_output_file(path)
+        .map(Value::Port)
+        .map_err(|e| SchemeError::io("open-binary-output-file", path, &e))
+}
+
+fn is_binary_port(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
+    check_arity!(args, 1, "binary-port?");
+    Ok(Value::Bool(matches!(&args[0], Value::Port(port) if port.borrow().is_binary())))
+}
+
+fn is_textual_port(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
+    check_arity!(args, 1, "textual-port?");
+    Ok(Value::Bool(matches!(&args[0], Value::Port(port) if !port.borrow().is_binary())))
+}
+
+// (read-u8 [port]) and (peek-u8 [port]) return the eof object at end of input.
+fn read_u8(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
+    check_arity!(args, <= 1, "read-u8");
+    let byte = input_port_arg(args.first())?.borrow_mut().read_u8()?;
+    Ok(byte.map_or(Value::Eof, |b| Value::Integer(b as i64)))
+}
+
+fn peek_u8(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
+    check_arity!(args, <= 1, "peek-u8");
+    let byte = input_port_arg(args.first())?.borrow().peek_u8()?;
+    Ok(byte.map_or(Value::Eof, |b| Value::Integer(b as i64)))
+}
+
+fn u8_ready(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
+    check_arity!(args, <= 1, "u8-ready?");
+    Ok(Value::Bool(input_port_arg(args.first())?.borrow().u8_ready()?))
+}
+
+// (read-bytevector k [port]): up to `k` bytes.
+fn read_bytevector(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
+    check_arity!(args, 1, 2, "read-bytevector");
+    let k = check_length(extract_int!(&args[0], "read-bytevector"), "read-bytevector")?;
+    let bytes = input_port_arg(args.get(1))?.borrow_mut().read_bytes(k)?;
+    bytes.map_or(Ok(Value::Eof), new_bytevector)
+}
+
+// (read-bytevector! bv [port [start [end]]]): reads into `bv` and returns
+// the number of bytes read.
+fn read_bytevector_into(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
+    check_arity!(args, 1, 4, "read-bytevector!");
+    let target = extract_bytevector(&args[0])?;
+    let (start, end) = check_range(args, 2, target.borrow().len(), "read-bytevector!")?;
+    let Some(bytes) = input_port_arg(args.get(1))?.borrow_mut().read_bytes(end - start)? else {
+        return Ok(Value::Eof);
+    };
+    target.borrow_mut()[start..start + bytes.len()].copy_from_slice(&bytes);
+    Ok(Value::Integer(bytes.len() as i64))
+}
+
+fn write_u8(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
+    check_arity!(args, 1, 2, "write-u8");
+    let byte = extract_byte(&args[0], "write-u8")?;
+    output_port_arg(args.get(1))?.borrow_mut().write_bytes(&[byte])?;
+    Ok(Value::Nil)
+}
+
+// (write-bytevector bv [port [start [end]]])
+fn write_bytevector(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
+    check_arity!(args, 1, 4, "write-bytevector");
+    let bytes = extract_bytevector(&args[0])?;
+    let bytes = bytes.borrow();
+    let (start, end) = check_range(args, 2, bytes.len(), "write-bytevector")?;
+    output_port_arg(args.get(1))?.borrow_mut().write_bytes(&bytes[start..end])?;
+    Ok(Value::Nil)
+}
+
+// --- File System ---
+// Failures raise `SchemeError::Io`, which `guard` can catch and `file-error?`,
+// `io-error-kind` and friends can inspect.
+fn file_exists(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
+    check_arity!(args, 1, "file-exists?");
+    Ok(Value::Bool(Path::new(extract_path(&args[0])?).exists()))
+}
+
+fn file_is_directory(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
+    check_arity!(args, 1, "file-directory?");
+    Ok(Value::Bool(Path::new(extract_path(&args[0])?).is_dir()))
+}
+
+fn delete_file(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
+    check_arity!(args, 1, "delete-file");
+    let path = extract_path(&args[0])?;
+    fs::remove_file(path).map_err(|e| SchemeError::io("delete-file", path, &e))?;
+    Ok(Value::Nil)
+}
+
+// (delete-directory path): removes an empty directory.
+fn delete_directory(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
+    check_arity!(args, 1, "delete-directory");
+    let path = extract_path(&args[0])?;
+    fs::remove_dir(path).map_err(|e| SchemeError::io("delete-directory", path, &e))?;
+    Ok(Value::Nil)
+}
+
+fn rename_file(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
+    check_arity!(args, 2, "rename-file");
+    let (from, to) = (extract_path(&args[0])?, extract_path(&args[1])?);
+    fs::rename(from, to).map_err(|e| SchemeError::io("rename-file", from, &e))?;
+    Ok(Value::Nil)
+}
+
+// (make-directory path [parents?]): with #t also creates missing parents.
+fn make_directory(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
+    check_arity!(args, 1, 2, "make-directory");
+    let path = extract_path(&args[0])?;
+    let created = match args.get(1) {
+        Some(Value::Bool(true)) => fs::create_dir_all(path),
+        _ => fs::create_dir(path),
+    };
+    created.map_err(|e| SchemeError::io("make-directory", path, &e))?;
+    Ok(Value::Nil)
+}
+
+// (directory-list path): entry names, sorted, without "." and "..".
+fn directory_list(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
+    check_arity!(args, 1, "directory-list");
+    let path = extract_path(&args[0])?;
+    let io_error = |e| SchemeError::io("directory-list", path, &e);
+    let mut names = Vec::new();
+    for entry in fs::read_dir(path).map_err(io_error)? {
+        names.push(entry.map_err(io_error)?.file_name().to_string_lossy().into_owned());
+    }
+    names.sort();
+    Ok(Value::List(names.into_iter().map(Value::String).collect()))
+}
+
+fn metadata(path: &str, operation: &str) -> Result<fs::Metadata> {
+    fs::metadata(path).map_err(|e| SchemeError::io(operation, path, &e))
+}
+
+fn file_size(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
+    check_arity!(args, 1, "file-size");
+    let size = metadata(extract_path(&args[0])?, "file-size")?.len();
+    Ok(Value::Integer(i64::try_from(size).unwrap_or(i64::MAX)))
+}
+
+// (file-modification-time path): seconds since the Unix epoch.
+fn file_modification_time(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
+    check_arity!(args, 1, "file-modification-time");
+    let path = extract_path(&args[0])?;
+    let modified = metadata(path, "file-modification-time")?
+        .modified()
+        .map_err(|e| SchemeError::io("file-modification-time", path, &e))?;
+    let seconds = match modified.duration_since(UNIX_EPOCH) {
+        Ok(since) => since.as_secs() as i64,
+        Err(before) => -(before.duration().as_secs() as i64),
+    };
+    Ok(Value::Integer(seconds))
+}
+
+// (path-join part ...): joins with the platform separator; an absolute part starts over.
+fn path_join(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
+    check_arity!(args, >= 1, "path-join");
+    let mut path = PathBuf::new();
+    for part in args {
+        path.push(extract_path(part)?);
+    }
+    Ok(Value::String(path.to_string_lossy().into_owned()))
+}
+
+// (path-extension path): the extension without the dot, or #f if there is none.
+fn path_extension(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
+    check_arity!(args, 1, "path-extension");
+    Ok(Path::new(extract_path(&args[0])?)
+        .extension()
+        .map_or(Value::Bool(false), |ext| Value::String(ext.to_string_lossy().into_owned())))
+}
+
+// (path-dirname path): everything before the last component, "." for a bare name.
+fn path_dirname(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
+    check_arity!(args, 1, "path-dirname");
+    let path = extract_path(&args[0])?;
+    let dirname = match Path::new(path).parent() {
+        Some(parent) if parent.as_os_str().is_empty() => ".".to_string(),
+        Some(parent) => parent.to_string_lossy().into_owned(),
+        None => path.to_string(), // A root like "/" is its own dirname
+    };
+    Ok(Value::String(dirname))
+}
+
+// (path-basename path): the last component, or "" if there is none (e.g. "/").
+fn path_basename(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
+    check_arity!(args, 1, "path-basename");
+    let name = Path::new(extract_path(&args[0])?).file_name().map(|n| n.to_string_lossy().into_owned());
+    Ok(Value::String(name.unwrap_or_default()))
+}
+
+// (make-temporary-file [prefix]): creates a new empty file in the system
+// temp directory and returns its path. The caller deletes it when done.
+fn make_temporary_file(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
+    check_arity!(args, <= 1, "make-temporary-file");
+    let prefix = match args.first() {
+        Some(arg) => extract_path(arg)?,
+        None => "rs-scheme",
+    };
+    let dir = std::env::temp_dir();
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.subsec_nanos());
+    let mut last_error = None;
+    for attempt in 0..100 {
+        let path = dir.join(format!("{}-{}-{}-{}.tmp", prefix, std::process::id(), nanos, attempt));
+        match fs::OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(_) => return Ok(Value::String(path.to_string_lossy().into_owned())),
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => last_error = Some((path, e)),
+            Err(e) => return Err(SchemeError::io("make-temporary-file", &path.to_string_lossy(), &e)),
+        }
+    }
+    // Every name was taken; report the last collision
+    let (path, e) = last_error.expect("the loop makes 100 attempts");
+    Err(SchemeError::io("make-temporary-file", &path.to_string_lossy(), &e))
+}
+
+// --- Process and OS ---
+// The script path followed by its arguments, or just the interpreter's own
+// name in the REPL. Set by `main` once it has read its own options.
+static COMMAND_LINE: OnceLock<Vec<String>> = OnceLock::new();
+
+pub fn set_command_line(args: Vec<String>) {
+    let _ = COMMAND_LINE.set(args);
+}
+
+// (command-line): the strings above, as a list.
+fn command_line(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
+    check_arity!(args, 0, "command-line");
+    let argv = COMMAND_LINE.get().cloned().unwrap_or_default();
+    Ok(Value::List(argv.into_iter().map(Value::String).collect()))
+}
+
+// (get-environment-variable name): the value, or #f if it is unset.
+fn get_environment_variable(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
+    check_arity!(args, 1, "get-environment-variable");
+    let name = extract_path(&args[0])?;
+    Ok(std::env::var(name).map_or(Value::Bool(false), Value::String))
+}
+
+// (get-environment-variables): an alist of (name value) lists, sorted by name.
+fn get_environment_variables(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
+    check_arity!(args, 0, "get-environment-variables");
+    let mut vars: Vec<(String, String)> = std::env::vars_os()
+        .map(|(k, v)| (k.to_string_lossy().into_owned(), v.to_string_lossy().into_owned()))
+        .collect();
+    vars.sort();
+    Ok(Value::List(vars.into_iter().map(|(k, v)| Value::List(vec![Value::String(k), Value::String(v)])).collect()))
+}
+
+// The status for `exit`: none or #t is success, #f failure, an integer itself.
+fn exit_status(args: &[Value], name: &str) -> Result<i32> {
+    check_arity!(args, <= 1, name);
+    match args.first() {
+        None | Some(Value::Bool(true)) => Ok(0),
+        Some(Value::Bool(false)) => Ok(1),
+        Some(Value::Integer(n)) => i32::try_from(*n).map_err(|_| SchemeError::Range(format!("{}: status {} out of range", name, n))),
+        Some(other) => Err(Value::type_error("integer or boolean", other)),
+    }
+}
+
+// (exit [status]): unwinds to the top level, which exits the process.
+fn exit(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
+    Err(SchemeError::Exit(exit_status(args, "exit")?))
+}
+
+// (emergency-exit [status]): exits immediately, without unwinding.
+fn emergency_exit(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
+    std::process::exit(exit_status(args, "emergency-exit")?)
+}
+
+// (current-second): whole seconds since the Unix epoch.
+fn current_second(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
+    check_arity!(args, 0, "current-second");
+    let since = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    Ok(Value::Integer(since.as_secs() as i64))
+}
+
+// Jiffies are microseconds since the interpreter started.
+const JIFFIES_PER_SECOND: i64 = 1_000_000;
+static START: OnceLock<Instant> = OnceLock::new();
+
+fn current_jiffy(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
+    check_arity!(args, 0, "current-jiffy");
+    Ok(Value::Integer(START.get_or_init(Instant::now).elapsed().as_micros() as i64))
+}
+
+fn jiffies_per_second(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
+    check_arity!(args, 0, "jiffies-per-second");
+    Ok(Value::Integer(JIFFIES_PER_SECOND))
+}
+
+// Runs `program` with the argument list and optional stdin text in
+// `args[1..]`, waiting for it to finish. No shell is involved.
+fn spawn_process(args: &[Value], name: &str) -> Result<std::process::Output> {
+    let program = extract_path(&args[0])?;
+    let argv = match args.get(1) {
+        None | Some(Value::Nil) => Vec::new(),
+        Some(Value::List(items)) => items.iter().map(extract_path).collect::<Result<Vec<_>>>()?,
+        Some(other) => return Err(Value::type_error("list of strings", other)),
+    };
+    let input = args.get(2).map(extract_path).transpose()?;
+    let mut command = Command::new(program);
+    command
+        .args(argv)
+        .stdin(if input.is_some() { Stdio::piped() } else { Stdio::null() })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    let mut child = command.spawn().map_err(|e| SchemeError::io(name, program, &e))?;
+    if let (Some(text), Some(mut stdin)) = (input, child.stdin.take()) {
+        // Written from a thread so a child that fills its output pipes before
+        // reading all of its input cannot deadlock us.
+        let text = text.to_string();
+        std::thread::spawn(move || stdin.write_all(text.as_bytes()));
+    }
+    let output = child.wait_with_output().map_err(|e| SchemeError::io(name, program, &e))?;
+    interrupt::check()?; // Ctrl-C reaches the child too, ending it
+    Ok(output)
+}
+
+// (run-process program [args [input]]): runs `program` with the list of
+// argument strings, feeding it `input` on stdin if given, and returns
+// {status: n, stdout: "...", stderr: "..."}. The status is #f if the
+// process was killed by a signal.
+fn run_process(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
+    check_arity!(args, 1, 3, "run-process");
+    let output = spawn_process(args, "run-process")?;
+    let status = output.status.code().map_or(Value::Bool(false), |code| Value::Integer(code as i64));
+    let mut result = MapData::new();
+    result.insert(map_key(&Value::symbol("status")), status);
+    result.insert(map_key(&Value::symbol("stdout")), Value::String(String::from_utf8_lossy(&output.stdout).into_owned()));
+    result.insert(map_key(&Value::symbol("stderr")), Value::String(String::from_utf8_lossy(&output.stderr).into_owned()));
+    new_map(result)
+}
+
+// (process-output program [args [input]]): the process's stdout. A failing
+// exit status raises an error that includes its stderr.
+fn process_output(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
+    check_arity!(args, 1, 3, "process-output");
+    let output = spawn_process(args, "process-output")?;
+    if !output.status.success() {
+        let mut message = format!("process-output: {} failed ({})", extract_path(&args[0])?, output.status);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        if !stderr.trim_end().is_empty() {
+            message = format!("{}: {}", message, stderr.trim_end());
+        }
+        return Err(SchemeError::Runtime(message));
+    }
+    Ok(Value::String(String::from_utf8_lossy(&output.stdout).into_owned()))
+}
+
+// --- Error Objects ---
+fn extract_error(value: &Value) -> Result<&SchemeError> {
+    match value {
+        Value::Error(err) => Ok(err),
+        other => Err(Value::type_error("error object", other)),
+    }
+}
+
+fn is_error_object(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
+    check_arity!(args, 1, "error-object?");
+    Ok(Value::Bool(matches!(args[0], Value::Error(_))))
+}
+
+fn error_object_message(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
+    check_arity!(args, 1, "error-object-message");
+    Ok(Value::String(extract_error(&args[0])?.to_string()))
+}
+
+// The path for I/O errors; other errors carry no irritants.
+fn error_object_irritants(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
+    check_arity!(args, 1, "error-object-irritants");
+    match extract_error(&args[0])? {
+        SchemeError::Io { path, .. } => Ok(Value::List(vec![Value::String(path.clone())])),
+        _ => Ok(Value::Nil),
+    }
+}
+
+fn is_file_error(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
+    check_arity!(args, 1, "file-error?");
+    Ok(Value::Bool(matches!(&args[0], Value::Error(err) if matches!(**err, SchemeError::Io { .. }))))
+}
+
+fn is_read_error(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
+    check_arity!(args, 1, "read-error?");
+    Ok(Value::Bool(matches!(&args[0], Value::Error(err) if matches!(**err, SchemeError::Parser(_) | SchemeError::Incomplete(_)))))
+}
+
+// (io-error-operation e), (io-error-path e), (io-error-kind e): the parts of
+// an I/O error. The kind is a symbol such as `not-found` or `permission-denied`.
+fn io_error_field(args: &[Value], field: fn(&str, &str, &str) -> Value) -> Result<Value> {
+    match extract_error(&args[0])? {
+        SchemeError::Io { operation, path, kind, .. } => Ok(field(operation, path, kind)),
+        _ => Err(Value::type_error("I/O error object", &args[0])),
+    }
+}
+
+fn io_error_operation(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
+    check_arity!(args, 1, "io-error-operation");
+    io_error_field(args, |operation, _, _| Value::symbol(operation))
+}
+
+fn io_error_path(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
+    check_arity!(args, 1, "io-error-path");
+    io_error_field(args, |_, path, _| Value::String(path.to_string()))
+}
+
+fn io_error_kind(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
+    check_arity!(args, 1, "io-error-kind");
+    io_error_field(args, |_, _, kind| Value::symbol(kind))
+}
+
+// --- Output ---
+// (display obj [port]): human-readable, so strings, symbols and chars print as their raw text
 fn display(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
-    // Basic display, prints without quotes for strings
-    for (i, arg) in args.iter().enumerate() {
-        if i > 0 { print!(" "); }
-         match arg {
-             Value::String(s) => print!("{}", s),
-             _ => print!("{:?}", arg), // Use Debug formatting for others
-         }
+    check_arity!(args, 1, 2, "display");
+    output_port_arg(args.get(1))?.borrow_mut().write_str(&args[0].to_string())?;
+    Ok(Value::Nil)
+}
+
+// (write obj [port]), (write-shared obj [port]), (write-simple obj [port]):
+// print `obj` so that `read` gives back an equal value. They differ only in
+// datum labels: `write` labels cycles, `write-shared` all shared structure,
+// `write-simple` nothing.
+fn write_styled(args: &[Value], style: Style, name: &str) -> Result<Value> {
+    check_arity!(args, 1, 2, name);
+    output_port_arg(args.get(1))?.borrow_mut().write_str(&printer::to_string(&args[0], style))?;
+    Ok(Value::Nil)
+}
+
+fn write(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
+    write_styled(args, Style::Write, "write")
+}
+
+fn write_shared(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
+    write_styled(args, Style::WriteShared, "write-shared")
+}
+
+fn write_simple(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
+    write_styled(args, Style::WriteSimple, "write-simple")
+}
+
+// (format [destination] fmt arg ...): expands the directives in `fmt` (see
+// format.rs). With no destination or #f it returns the string; with #t it
+// writes it to the current output port, and with a port to that port.
+fn builtin_format(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
+    check_arity!(args, >= 1, "format");
+    let (destination, rest) = match &args[0] {
+        Value::Bool(true) => (Some(port::current_output()), &args[1..]),
+        Value::Bool(false) => (None, &args[1..]),
+        Value::Port(port) => (Some(Rc::clone(port)), &args[1..]),
+        _ => (None, args),
+    };
+    let fmt = match rest.first() {
+        Some(Value::String(s)) => s,
+        Some(other) => return Err(Value::type_error("string", other)),
+        None => return Err(SchemeError::Arity { expected: "at least 2".to_string(), got: args.len() }),
+    };
+    let text = format::format(fmt, &rest[1..])?;
+    match destination {
+        Some(port) => {
+            port.borrow_mut().write_str(&text)?;
+            Ok(Value::Nil)
+        }
+        None => Ok(Value::String(text)),
     }
-    println!(); // Add newline
+}
+
+// (pretty-print obj [width]), also `pp`: `write` output laid out over
+// several lines to fit the width, followed by a newline.
+fn pretty_print(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
+    check_arity!(args, 1, 2, "pretty-print");
+    let width = match args.get(1) {
+        Some(arg) => check_width(extract_int!(arg, "pretty-print"))?,
+        None => pretty::width(),
+    };
+    let text = pretty::to_string(&args[0], width) + "\n";
+    port::current_output().borrow_mut().write_str(&text)?;
     Ok(Value::Nil)
 }
 
+fn check_width(width: i64) -> Result<usize> {
+    usize::try_from(width)
+        .ok()
+        .filter(|w| *w > 0)
+        .ok_or_else(|| SchemeError::Range(format!("pretty-print: width must be positive, got {}", width)))
+}
+
+// (pretty-print-width) returns the default width; (pretty-print-width n) sets it.
+fn pretty_print_width(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
+    check_arity!(args, <= 1, "pretty-print-width");
+    if let Some(arg) = args.first() {
+        pretty::set_width(check_width(extract_int!(arg, "pretty-print-width"))?);
+    }
+    Ok(Value::Integer(pretty::width() as i64))
+}
+
+// (pretty-print-repl) tells whether the REPL pretty-prints results;
+// (pretty-print-repl #f) switches it to plain one-line `write` output.
+fn pretty_print_repl(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
+    check_arity!(args, <= 1, "pretty-print-repl");
+    match args.first() {
+        Some(Value::Bool(enabled)) => pretty::set_repl_enabled(*enabled),
+        Some(other) => return Err(Value::type_error("boolean", other)),
+        None => {}
+    }
+    Ok(Value::Bool(pretty::repl_enabled()))
+}
+
 fn newline(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
-    check_arity!(args, 0, "newline");
-    println!();
+    check_arity!(args, <= 1, "newline");
+    output_port_arg(args.first())?.borrow_mut().write_str("\n")?;
     Ok(Value::Nil)
 }
 
@@ -347,19 +1784,110 @@ fn newline(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
 fn builtin_eval(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value> {
     check_arity!(args, 1, "eval");
     let expr_to_eval = &args[0];
-    // Evaluate the expression in the *current* dynamic environment
-    evaluate(expr_to_eval, env)
+    // Evaluate the expression in the global environment, not the caller's frame
+    evaluate(expr_to_eval, Environment::global(&env))
 }
 
+// --- Equivalence Predicates ---
 // General Equality Predicate
 fn equal_q(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
     check_arity!(args, 2, "equal?");
-    // Use the derived PartialEq implementation on Value
+    // Use the structural PartialEq implementation on Value
     Ok(Value::Bool(args[0] == args[1]))
 }
 
+fn eqv_q(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
+    check_arity!(args, 2, "eqv?");
+    Ok(Value::Bool(eqv(&args[0], &args[1])))
+}
+
+fn eq_q(args: &[Value], _env: Rc<RefCell<Environment>>) -> Result<Value> {
+    check_arity!(args, 2, "eq?");
+    // Integers and chars are immediates here, so eq? and eqv? agree; both
+    // compare everything else by identity
+    Ok(Value::Bool(eqv(&args[0], &args[1])))
+}
+
+fn list_items(val: &Value) -> Result<&[Value]> {
+    match val {
+        Value::List(items) => Ok(items),
+        Value::Nil => Ok(&[]),
+        _ => Err(Value::type_error("list", val)),
+    }
+}
+
+// Shared by memq/memv/member: the tail of `list` starting at the first match, or #f.
+fn member_with(args: &[Value], env: Rc<RefCell<Environment>>, default: fn(&Value, &Value) -> bool) -> Result<Value> {
+    let items = list_items(&args[1])?;
+    for (i, item) in items.iter().enumerate() {
+        let found = match args.get(2) {
+            Some(compare) => !matches!(apply(compare, vec![args[0].clone(), item.clone()], Rc::clone(&env))?, Value::Bool(false)),
+            None => default(&args[0], item),
+        };
+        if found {
+            return Ok(Value::List(items[i..].to_vec()));
+        }
+    }
+    Ok(Value::Bool(false))
+}
+
+// Shared by assq/assv/assoc: the first `(key value ...)` entry whose key matches, or #f.
+fn assoc_with(args: &[Value], env: Rc<RefCell<Environment>>, default: fn(&Value, &Value) -> bool) -> Result<Value> {
+    for entry in list_items(&args[1])? {
+        let key = match entry {
+            Value::List(fields) if !fields.is_empty() => &fields[0],
+            _ => return Err(Value::type_error("association list entry", entry)),
+        };
+        let found = match args.get(2) {
+            Some(compare) => !matches!(apply(compare, vec![args[0].clone(), key.clone()], Rc::clone(&env))?, Value::Bool(false)),
+            None => default(&args[0], key),
+        };
+        if found {
+            return Ok(entry.clone());
+        }
+    }
+    Ok(Value::Bool(false))
+}
+
+fn structurally_equal(a: &Value, b: &Value) -> bool {
+    a == b
+}
+
+fn memq(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value> {
+    check_arity!(args, 2, "memq");
+    member_with(args, env, eqv)
+}
+
+fn memv(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value> {
+    check_arity!(args, 2, "memv");
+    member_with(args, env, eqv)
+}
+
+fn member(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value> {
+    // (member obj list [compare])
+    check_arity!(args, 2, 3, "member");
+    member_with(args, env, structurally_equal)
+}
+
+fn assq(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value> {
+    check_arity!(args, 2, "assq");
+    assoc_with(args, env, eqv)
+}
+
+fn assv(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value> {
+    check_arity!(args, 2, "assv");
+    assoc_with(args, env, eqv)
+}
+
+fn assoc(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value> {
+    // (assoc key alist [compare])
+    check_arity!(args, 2, 3, "assoc");
+    assoc_with(args, env, structurally_equal)
+}
+
 // Function to populate the initial environment
 pub fn populate_environment(env: &mut Environment) {
+    START.get_or_init(Instant::now); // Jiffies count from startup
     let builtins: Vec<(&str, BuiltinFn)> = vec![
         // Arithmetic
         ("+", add), ("-", subtract), ("*", multiply), ("/", divide),
@@ -371,13 +1899,102 @@ pub fn populate_environment(env: &mut Environment) {
         ("null?", is_null), ("boolean?", is_boolean), ("symbol?", is_symbol),
         ("integer?", is_integer), ("string?", is_string), ("list?", is_list),
         ("procedure?", is_procedure), ("array?", is_array), ("map?", is_map),
-        ("equal?", equal_q),
+        ("hash-table?", is_hash_table), ("set?", is_set), ("char?", is_char),
+        ("equal?", equal_q), ("eqv?", eqv_q), ("eq?", eq_q),
+        ("memq", memq), ("memv", memv), ("member", member),
+        ("assq", assq), ("assv", assv), ("assoc", assoc),
         // Array Functions
          ("make-array", make_array), ("array-ref", array_ref), ("array-set!", array_set), ("array-length", array_length),
+        ("array-ref/from-end", array_ref_from_end), ("array-set!/from-end", array_set_from_end),
         // Map Functions
         ("make-map", make_map), ("map-ref", map_ref), ("map-set!", map_set), ("map-keys", map_keys),
+        ("map-has?", map_has), ("map-delete!", map_delete), ("map-values", map_values), ("map-count", map_count),
+        ("map->alist", map_to_alist), ("alist->map", alist_to_map),
+        ("map-for-each", map_for_each), ("map-map", map_map), ("map-filter", map_filter),
+        ("map-merge", map_merge), ("map-update!", map_update),
+        ("make-sorted-map", make_sorted_map), ("sorted-map?", is_sorted_map),
+        ("map-range", map_range), ("map-min", map_min), ("map-max", map_max),
+        // Hash Tables
+        ("make-hash-table", make_hash_table), ("hash", hash), ("equal-hash", hash),
+        ("hash-table-set!", hash_table_set), ("hash-table-ref", hash_table_ref),
+        ("hash-table-ref/default", hash_table_ref_default), ("hash-table-delete!", hash_table_delete),
+        ("hash-table-contains?", hash_table_contains), ("hash-table-exists?", hash_table_contains),
+        ("hash-table-size", hash_table_size), ("hash-table-keys", hash_table_keys),
+        ("hash-table-values", hash_table_values), ("hash-table->alist", hash_table_to_alist),
+        ("hash-table-walk", hash_table_walk), ("hash-table-update!", hash_table_update),
+        ("hash-table-update!/default", hash_table_update_default),
+        ("hash-table-equivalence-function", hash_table_equivalence_function),
+        ("hash-table-hash-function", hash_table_hash_function),
+        // Sets
+        ("make-set", make_set), ("list->set", list_to_set), ("set->list", set_to_list),
+        ("set-add!", set_add), ("set-remove!", set_remove), ("set-contains?", set_contains), ("set-size", set_size),
+        ("set-union", set_union), ("set-intersection", set_intersection), ("set-difference", set_difference),
+        // Characters and Strings
+        ("char->integer", char_to_integer), ("integer->char", integer_to_char), ("string=?", string_equal),
+        ("symbol->string", symbol_to_string), ("string->symbol", string_to_symbol),
+        // Bytevectors
+        ("bytevector?", is_bytevector), ("make-bytevector", make_bytevector), ("bytevector", bytevector),
+        ("bytevector-length", bytevector_length), ("bytevector-u8-ref", bytevector_u8_ref), ("bytevector-u8-set!", bytevector_u8_set),
+        ("bytevector-copy", bytevector_copy), ("bytevector-copy!", bytevector_copy_into), ("bytevector-append", bytevector_append),
+        ("utf8->string", utf8_to_string), ("string->utf8", string_to_utf8),
+        ("bytevector-u16-le-ref", bytevector_u16_le_ref), ("bytevector-u16-le-set!", bytevector_u16_le_set),
+        ("bytevector-u16-be-ref", bytevector_u16_be_ref), ("bytevector-u16-be-set!", bytevector_u16_be_set),
+        ("bytevector-s16-le-ref", bytevector_s16_le_ref), ("bytevector-s16-le-set!", bytevector_s16_le_set),
+        ("bytevector-s16-be-ref", bytevector_s16_be_ref), ("bytevector-s16-be-set!", bytevector_s16_be_set),
+        ("bytevector-u32-le-ref", bytevector_u32_le_ref), ("bytevector-u32-le-set!", bytevector_u32_le_set),
+        ("bytevector-u32-be-ref", bytevector_u32_be_ref), ("bytevector-u32-be-set!", bytevector_u32_be_set),
+        ("bytevector-s32-le-ref", bytevector_s32_le_ref), ("bytevector-s32-le-set!", bytevector_s32_le_set),
+        ("bytevector-s32-be-ref", bytevector_s32_be_ref), ("bytevector-s32-be-set!", bytevector_s32_be_set),
+        ("bytevector-u64-le-ref", bytevector_u64_le_ref), ("bytevector-u64-le-set!", bytevector_u64_le_set),
+        ("bytevector-u64-be-ref", bytevector_u64_be_ref), ("bytevector-u64-be-set!", bytevector_u64_be_set),
+        ("bytevector-s64-le-ref", bytevector_s64_le_ref), ("bytevector-s64-le-set!", bytevector_s64_le_set),
+        ("bytevector-s64-be-ref", bytevector_s64_be_ref), ("bytevector-s64-be-set!", bytevector_s64_be_set),
         // Other
         ("display", display), ("newline", newline),
+        ("write", write), ("write-shared", write_shared), ("write-simple", write_simple),
+        ("format", builtin_format),
+        // Ports
+        ("current-input-port", current_input_port), ("current-output-port", current_output_port),
+        ("current-error-port", current_error_port),
+        ("open-input-string", open_input_string), ("open-output-string", open_output_string),
+        ("get-output-string", get_output_string), ("with-output-to-string", with_output_to_string),
+        ("open-input-file", open_input_file), ("open-output-file", open_output_file),
+        ("close-port", close_port), ("close-input-port", close_port), ("close-output-port", close_port),
+        ("call-with-port", call_with_port),
+        ("port?", is_port), ("input-port?", is_input_port), ("output-port?", is_output_port),
+        ("input-port-open?", is_port_open), ("output-port-open?", is_port_open),
+        ("read-char", read_char), ("peek-char", peek_char), ("read", read), ("read-line", read_line),
+        ("read-string", read_string), ("char-ready?", char_ready),
+        ("eof-object", eof_object), ("eof-object?", is_eof_object),
+        // Binary Ports
+        ("open-input-bytevector", open_input_bytevector), ("open-output-bytevector", open_output_bytevector),
+        ("get-output-bytevector", get_output_bytevector),
+        ("open-binary-input-file", open_binary_input_file), ("open-binary-output-file", open_binary_output_file),
+        ("binary-port?", is_binary_port), ("textual-port?", is_textual_port),
+        ("read-u8", read_u8), ("peek-u8", peek_u8), ("u8-ready?", u8_ready),
+        ("read-bytevector", read_bytevector), ("read-bytevector!", read_bytevector_into),
+        ("write-u8", write_u8), ("write-bytevector", write_bytevector),
+        // File System
+        ("file-exists?", file_exists), ("file-directory?", file_is_directory),
+        ("delete-file", delete_file), ("delete-directory", delete_directory), ("rename-file", rename_file),
+        ("make-directory", make_directory), ("directory-list", directory_list),
+        ("file-size", file_size), ("file-modification-time", file_modification_time),
+        ("path-join", path_join), ("path-extension", path_extension),
+        ("path-dirname", path_dirname), ("path-basename", path_basename),
+        ("make-temporary-file", make_temporary_file),
+        // Process and OS
+        ("command-line", command_line),
+        ("get-environment-variable", get_environment_variable), ("get-environment-variables", get_environment_variables),
+        ("exit", exit), ("emergency-exit", emergency_exit),
+        ("current-second", current_second), ("current-jiffy", current_jiffy), ("jiffies-per-second", jiffies_per_second),
+        ("run-process", run_process), ("process-output", process_output),
+        // Error Objects
+        ("error-object?", is_error_object), ("error-object-message", error_object_message),
+        ("error-object-irritants", error_object_irritants),
+        ("file-error?", is_file_error), ("read-error?", is_read_error),
+        ("io-error-operation", io_error_operation), ("io-error-path", io_error_path), ("io-error-kind", io_error_kind),
+        ("pretty-print", pretty_print), ("pp", pretty_print),
+        ("pretty-print-width", pretty_print_width), ("pretty-print-repl", pretty_print_repl),
         ("eval", builtin_eval),
         // Constants (could be defined directly, but this is cleaner)
         // ("#t", |_args, _env| Ok(Value::Bool(true))), // Define #t/#f as vars? Usually they are literals.
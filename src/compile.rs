@@ -0,0 +1,409 @@
+use crate::analyze::{definitions, for_each_child, LambdaCode, Node};
+use crate::eval::grow_stack;
+use crate::value::Value;
+use crate::vm::{Capture, Op, Proto, NO_NAME};
+use std::collections::HashSet;
+use std::rc::Rc;
+
+// Compiles analyzed code into bytecode for the VM (see `vm`).
+//
+// A variable is a local slot of the procedure binding it, a captured
+// variable of a lambda using it from an enclosing procedure, or else a
+// global. Internal defines get slots when their procedure is compiled, so
+// every use in the body refers to the slot, and so do defines in a `guard`
+// clause when their handler is; any other define is global. Which variables
+// need boxes is worked out by name, conservatively: one used from a nested
+// lambda that is assigned with `set!` or bound by an internal define.
+
+// What a name refers to in the procedure being compiled.
+enum Access {
+    Local(u32),
+    Free(u32),
+    Global,
+}
+
+// A procedure being compiled.
+struct Function {
+    params: Rc<Vec<String>>,
+    source: Rc<Value>,
+    code: Vec<Op>,
+    constants: Vec<Value>,
+    protos: Vec<Rc<Proto>>,
+    local_names: Vec<String>,
+    scope: Vec<(String, u32)>, // Visible locals, innermost last
+    captures: Vec<Capture>,
+    free_names: Vec<String>,
+    boxed: HashSet<String>,
+}
+
+impl Function {
+    fn new(params: Rc<Vec<String>>, source: Rc<Value>) -> Self {
+        Function {
+            params,
+            source,
+            code: Vec::new(),
+            constants: Vec::new(),
+            protos: Vec::new(),
+            local_names: Vec::new(),
+            scope: Vec::new(),
+            captures: Vec::new(),
+            free_names: Vec::new(),
+            boxed: HashSet::new(),
+        }
+    }
+
+    fn add_local(&mut self, name: &str) -> u32 {
+        let slot = self.local_names.len() as u32;
+        self.local_names.push(name.to_string());
+        self.scope.push((name.to_string(), slot));
+        slot
+    }
+
+    fn local(&self, name: &str) -> Option<u32> {
+        self.scope.iter().rev().find(|(n, _)| n == name).map(|(_, slot)| *slot)
+    }
+
+    fn finish(self) -> Rc<Proto> {
+        Rc::new(Proto {
+            params: self.params,
+            source: self.source,
+            code: self.code,
+            constants: self.constants,
+            protos: self.protos,
+            local_names: self.local_names,
+            captures: self.captures,
+            free_names: self.free_names,
+        })
+    }
+}
+
+struct Compiler {
+    functions: Vec<Function>, // Top-level code first, then the lambdas enclosing the one being compiled
+}
+
+// Compiles a top-level expression.
+pub fn compile(node: &Node) -> Rc<Proto> {
+    let mut usage = Usage::default();
+    usage.scan(node, false);
+    let mut function = Function::new(Rc::new(Vec::new()), Rc::new(Value::Nil));
+    function.boxed = usage.captured.intersection(&usage.assigned).cloned().collect(); // For `guard` handlers' variables
+    let mut compiler = Compiler { functions: vec![function] };
+    compiler.compile(node, true);
+    compiler.functions.pop().expect("the top-level function").finish()
+}
+
+impl Compiler {
+    fn function(&mut self) -> &mut Function {
+        self.functions.last_mut().expect("compiling inside a function")
+    }
+
+    fn emit(&mut self, op: Op) -> usize {
+        let code = &mut self.function().code;
+        code.push(op);
+        code.len() - 1
+    }
+
+    fn here(&mut self) -> u32 {
+        self.function().code.len() as u32
+    }
+
+    // Points the jump at `at` to the next instruction.
+    fn patch(&mut self, at: usize) {
+        let target = self.here();
+        match &mut self.function().code[at] {
+            Op::Jump(t) | Op::JumpIfFalse(t) | Op::PushHandler(t) | Op::Case(_, t) => *t = target,
+            _ => unreachable!("patching a jump"),
+        }
+    }
+
+    fn constant(&mut self, value: Value) -> u32 {
+        let constants = &mut self.function().constants;
+        constants.push(value);
+        (constants.len() - 1) as u32
+    }
+
+    // Returns from the procedure if the value just computed is its result.
+    fn finish(&mut self, tail: bool) {
+        if tail {
+            self.emit(Op::Return);
+        }
+    }
+
+    fn resolve(&mut self, level: usize, name: &str) -> Access {
+        let function = &self.functions[level];
+        if let Some(slot) = function.local(name) {
+            return Access::Local(slot);
+        }
+        if let Some(i) = function.free_names.iter().position(|n| n == name) {
+            return Access::Free(i as u32);
+        }
+        if level == 0 {
+            return Access::Global;
+        }
+        let capture = match self.resolve(level - 1, name) {
+            Access::Local(slot) => Capture::Local(slot),
+            Access::Free(i) => Capture::Free(i),
+            Access::Global => return Access::Global,
+        };
+        let function = &mut self.functions[level];
+        function.captures.push(capture);
+        function.free_names.push(name.to_string());
+        Access::Free((function.free_names.len() - 1) as u32)
+    }
+
+    // Emits code leaving the value of `node` on the stack, or, in tail
+    // position, returning it.
+    fn compile(&mut self, node: &Node, tail: bool) {
+        grow_stack(|| self.compile_node(node, tail))
+    }
+
+    fn compile_node(&mut self, node: &Node, tail: bool) {
+        match node {
+            Node::Const(value) => {
+                let i = self.constant(value.clone());
+                self.emit(Op::Const(i));
+                self.finish(tail);
+            }
+            Node::Var(name) => {
+                let op = match self.resolve(self.functions.len() - 1, name) {
+                    Access::Local(slot) => Op::Local(slot),
+                    Access::Free(i) => Op::Free(i),
+                    Access::Global => Op::Global(self.constant(Value::Symbol(name.clone()))),
+                };
+                self.emit(op);
+                self.finish(tail);
+            }
+            Node::If(test, then, otherwise) => {
+                self.compile(test, false);
+                let to_otherwise = self.emit(Op::JumpIfFalse(0));
+                self.compile(then, tail);
+                let to_end = (!tail).then(|| self.emit(Op::Jump(0)));
+                self.patch(to_otherwise);
+                match otherwise {
+                    Some(otherwise) => self.compile(otherwise, tail),
+                    None => self.compile(&Node::Const(Value::Nil), tail),
+                }
+                if let Some(to_end) = to_end {
+                    self.patch(to_end);
+                }
+            }
+            Node::Define(name, value) => {
+                self.compile(value, false);
+                let op = match self.function().local(name) {
+                    Some(slot) => Op::SetLocal(slot),
+                    None => Op::DefineGlobal(self.constant(Value::symbol(name))), // Outside procedures and handlers
+                };
+                self.emit(op);
+                self.compile(&Node::Const(Value::Nil), tail);
+            }
+            Node::Set(name, value) => {
+                self.compile(value, false);
+                let op = match self.resolve(self.functions.len() - 1, name) {
+                    Access::Local(slot) => Op::SetLocal(slot),
+                    Access::Free(i) => Op::SetFree(i),
+                    Access::Global => Op::SetGlobal(self.constant(Value::symbol(name))),
+                };
+                self.emit(op);
+                self.compile(&Node::Const(Value::Nil), tail);
+            }
+            Node::Lambda(code) => {
+                let proto = self.lambda(code);
+                let protos = &mut self.function().protos;
+                protos.push(proto);
+                let i = (protos.len() - 1) as u32;
+                self.emit(Op::Closure(i));
+                self.finish(tail);
+            }
+            Node::Begin(body) => self.sequence(body, tail),
+            Node::Case(key, clauses) => {
+                self.compile(key, false);
+                let mut to_clauses = Vec::new();
+                let mut has_else = false;
+                for clause in clauses {
+                    match &clause.data {
+                        Some(data) => {
+                            let data = self.constant(Value::List(data.clone()));
+                            to_clauses.push(self.emit(Op::Case(data, 0)));
+                        }
+                        None => {
+                            to_clauses.push(self.emit(Op::Jump(0)));
+                            has_else = true;
+                            break;
+                        }
+                    }
+                }
+                let mut to_end = Vec::new();
+                if !has_else {
+                    self.emit(Op::Pop); // The key
+                    self.compile(&Node::Const(Value::Nil), tail); // No clause matched
+                    to_end.extend((!tail).then(|| self.emit(Op::Jump(0))));
+                }
+                for (clause, to_clause) in clauses.iter().zip(to_clauses) {
+                    self.patch(to_clause);
+                    self.emit(Op::Pop); // The key
+                    self.sequence(&clause.body, tail);
+                    to_end.extend((!tail).then(|| self.emit(Op::Jump(0))));
+                }
+                for at in to_end {
+                    self.patch(at);
+                }
+            }
+            Node::Guard { var, clauses, body, .. } => {
+                let to_handler = self.emit(Op::PushHandler(0));
+                self.sequence(body, false);
+                self.emit(Op::PopHandler);
+                self.finish(tail);
+                let to_end = (!tail).then(|| self.emit(Op::Jump(0)));
+                // The handler, reached with the error object on the stack
+                self.patch(to_handler);
+                let scope = self.function().scope.len();
+                let slot = self.function().add_local(var);
+                self.emit(Op::BindLocal(slot));
+                if self.function().boxed.contains(var) {
+                    self.emit(Op::BoxLocal(slot));
+                }
+                // The clauses' defines are the handler's own, as in the tree-walker's handler frame
+                let mut defined = vec![var.clone()];
+                for clause in clauses {
+                    clause.test.iter().chain(&clause.body).for_each(|expr| definitions(expr, &mut defined));
+                }
+                for name in &defined[1..] {
+                    let slot = self.function().add_local(name);
+                    if self.function().boxed.contains(name) {
+                        self.emit(Op::NewBox(slot));
+                    }
+                }
+                let mut to_done = Vec::new();
+                for clause in clauses {
+                    let to_next = match &clause.test {
+                        None => None, // else
+                        Some(test) => {
+                            self.compile(test, false);
+                            if clause.body.is_empty() {
+                                self.emit(Op::Dup); // (test) yields the test's value
+                            }
+                            Some(self.emit(Op::JumpIfFalse(0)))
+                        }
+                    };
+                    match (&clause.test, clause.body.is_empty()) {
+                        (Some(_), true) => self.finish(tail),
+                        (None, true) => self.compile(&Node::Const(Value::Bool(true)), tail), // (else) yields #t
+                        (_, false) => self.sequence(&clause.body, tail),
+                    }
+                    to_done.extend((!tail).then(|| self.emit(Op::Jump(0))));
+                    match to_next {
+                        Some(to_next) => {
+                            self.patch(to_next);
+                            if clause.body.is_empty() {
+                                self.emit(Op::Pop); // The test's value
+                            }
+                        }
+                        None => break,
+                    }
+                }
+                if clauses.iter().all(|clause| clause.test.is_some()) {
+                    self.emit(Op::Local(slot)); // No clause matched: raise the error again
+                    self.emit(Op::Raise);
+                }
+                self.function().scope.truncate(scope);
+                for at in to_done.into_iter().chain(to_end) {
+                    self.patch(at);
+                }
+            }
+            Node::Call { op, args, name } => {
+                self.compile(op, false);
+                for arg in args {
+                    self.compile(arg, false);
+                }
+                let name = match name {
+                    Some(name) => self.constant(name.clone()),
+                    None => NO_NAME,
+                };
+                let argc = args.len() as u32;
+                self.emit(if tail { Op::TailCall(argc, name) } else { Op::Call(argc, name) });
+            }
+            Node::Local(_) | Node::DefineLocal(..) | Node::SetLocal(..) => {
+                unreachable!("only the tree-walker's code is resolved to slots")
+            }
+            Node::Error(err) => {
+                let i = self.constant(Value::Error(Rc::new(err.clone())));
+                self.emit(Op::Const(i));
+                self.emit(Op::Raise);
+            }
+        }
+    }
+
+    fn sequence(&mut self, body: &[Node], tail: bool) {
+        let Some((last, init)) = body.split_last() else {
+            return self.compile(&Node::Const(Value::Nil), tail);
+        };
+        for node in init {
+            self.compile(node, false);
+            self.emit(Op::Pop);
+        }
+        self.compile(last, tail);
+    }
+
+    fn lambda(&mut self, code: &LambdaCode) -> Rc<Proto> {
+        let mut usage = Usage::default();
+        usage.scan(&code.body, false);
+        let mut defined = Vec::new();
+        definitions(&code.body, &mut defined);
+
+        let mut function = Function::new(Rc::clone(&code.params), Rc::clone(&code.source));
+        for param in code.params.iter() {
+            function.add_local(param);
+        }
+        for name in &defined {
+            if function.local(name).is_none() {
+                function.add_local(name); // A define of a parameter's name assigns the parameter
+            }
+        }
+        function.boxed = usage.captured.intersection(&usage.assigned).cloned().collect();
+        // Box the captured variables that are assigned: parameters already
+        // hold a value, defined variables not yet.
+        for (slot, name) in function.local_names.iter().enumerate() {
+            if function.boxed.contains(name) {
+                let slot = slot as u32;
+                function.code.push(if (slot as usize) < code.params.len() {
+                    Op::BoxLocal(slot)
+                } else {
+                    Op::NewBox(slot)
+                });
+            }
+        }
+
+        self.functions.push(function);
+        self.compile(&code.body, true);
+        self.functions.pop().expect("the lambda's function").finish()
+    }
+}
+
+// Names used from nested lambdas, and names assigned or defined, anywhere in a body.
+#[derive(Default)]
+struct Usage {
+    captured: HashSet<String>,
+    assigned: HashSet<String>,
+}
+
+impl Usage {
+    fn scan(&mut self, node: &Node, nested: bool) {
+        match node {
+            Node::Var(name) if nested => {
+                self.captured.insert(name.to_string());
+            }
+            Node::Set(name, _) => {
+                self.assigned.insert(name.clone());
+                if nested {
+                    self.captured.insert(name.clone());
+                }
+            }
+            Node::Define(name, _) => {
+                self.assigned.insert(name.clone());
+            }
+            Node::Lambda(code) => return self.scan(&code.body, true),
+            _ => {}
+        }
+        for_each_child(node, &mut |child| grow_stack(|| self.scan(child, nested)));
+    }
+}
@@ -0,0 +1,296 @@
+use crate::value::Value;
+
+// Forms handled by the evaluator itself rather than bound in the environment,
+// including the derived forms it expands (see `expand`).
+pub const SPECIAL_FORMS: &[&str] = &[
+    "quote", "if", "define", "set!", "lambda", "begin", "case", "guard",
+    "let", "let*", "letrec", "letrec*", "cond", "and", "or", "when", "unless",
+];
+
+// One-line signatures of the special forms and builtins, shown by the REPL
+// as hints while typing a call. Optional arguments are in brackets and
+// `...` marks a rest argument. Keep this in step with `populate_environment`.
+const SIGNATURES: &[(&str, &str)] = &[
+    // Special forms
+    ("quote", "(quote datum)"),
+    ("if", "(if test then [else])"),
+    ("define", "(define name expr) or (define (name param ...) body ...)"),
+    ("set!", "(set! name expr)"),
+    ("lambda", "(lambda (param ...) body ...)"),
+    ("begin", "(begin expr ...)"),
+    ("case", "(case key ((datum ...) expr ...) ... [(else expr ...)])"),
+    ("guard", "(guard (var clause ...) body ...)"),
+    ("let", "(let [name] ((var init) ...) body ...)"),
+    ("let*", "(let* ((var init) ...) body ...)"),
+    ("letrec", "(letrec ((var init) ...) body ...)"),
+    ("letrec*", "(letrec* ((var init) ...) body ...)"),
+    ("cond", "(cond (test expr ...) ... [(else expr ...)])"),
+    ("and", "(and expr ...)"),
+    ("or", "(or expr ...)"),
+    ("when", "(when test body ...)"),
+    ("unless", "(unless test body ...)"),
+    // Arithmetic and comparison
+    ("+", "(+ n ...)"),
+    ("-", "(- n ...)"),
+    ("*", "(* n ...)"),
+    ("/", "(/ n ...)"),
+    ("=", "(= n1 n2 ...)"),
+    ("<", "(< n1 n2 ...)"),
+    (">", "(> n1 n2 ...)"),
+    // Lists
+    ("cons", "(cons x list)"),
+    ("car", "(car list)"),
+    ("cdr", "(cdr list)"),
+    ("list", "(list x ...)"),
+    // Type predicates
+    ("null?", "(null? obj)"),
+    ("boolean?", "(boolean? obj)"),
+    ("symbol?", "(symbol? obj)"),
+    ("integer?", "(integer? obj)"),
+    ("string?", "(string? obj)"),
+    ("list?", "(list? obj)"),
+    ("procedure?", "(procedure? obj)"),
+    ("array?", "(array? obj)"),
+    ("map?", "(map? obj)"),
+    ("hash-table?", "(hash-table? obj)"),
+    ("set?", "(set? obj)"),
+    ("char?", "(char? obj)"),
+    // Equivalence
+    ("equal?", "(equal? a b)"),
+    ("eqv?", "(eqv? a b)"),
+    ("eq?", "(eq? a b)"),
+    ("memq", "(memq x list)"),
+    ("memv", "(memv x list)"),
+    ("member", "(member x list)"),
+    ("assq", "(assq key alist)"),
+    ("assv", "(assv key alist)"),
+    ("assoc", "(assoc key alist)"),
+    // Arrays
+    ("make-array", "(make-array k [fill])"),
+    ("array-ref", "(array-ref array index)"),
+    ("array-set!", "(array-set! array index value)"),
+    ("array-length", "(array-length array)"),
+    ("array-ref/from-end", "(array-ref/from-end array index)"),
+    ("array-set!/from-end", "(array-set!/from-end array index value)"),
+    // Maps
+    ("make-map", "(make-map [alist | key value ...])"),
+    ("map-ref", "(map-ref map key [fallback])"),
+    ("map-set!", "(map-set! map key value)"),
+    ("map-keys", "(map-keys map)"),
+    ("map-has?", "(map-has? map key)"),
+    ("map-delete!", "(map-delete! map key)"),
+    ("map-values", "(map-values map)"),
+    ("map-count", "(map-count map)"),
+    ("map->alist", "(map->alist map)"),
+    ("alist->map", "(alist->map alist)"),
+    ("map-for-each", "(map-for-each proc map)"),
+    ("map-map", "(map-map proc map)"),
+    ("map-filter", "(map-filter pred map)"),
+    ("map-merge", "(map-merge map ...)"),
+    ("map-update!", "(map-update! map key proc [default])"),
+    ("make-sorted-map", "(make-sorted-map [alist | key value ...])"),
+    ("sorted-map?", "(sorted-map? obj)"),
+    ("map-range", "(map-range map lo hi)"),
+    ("map-min", "(map-min map)"),
+    ("map-max", "(map-max map)"),
+    // Hash tables
+    ("make-hash-table", "(make-hash-table [equal-proc [hash-proc]])"),
+    ("hash", "(hash obj [bound])"),
+    ("equal-hash", "(equal-hash obj [bound])"),
+    ("hash-table-set!", "(hash-table-set! table key value)"),
+    ("hash-table-ref", "(hash-table-ref table key [thunk])"),
+    ("hash-table-ref/default", "(hash-table-ref/default table key default)"),
+    ("hash-table-delete!", "(hash-table-delete! table key)"),
+    ("hash-table-contains?", "(hash-table-contains? table key)"),
+    ("hash-table-exists?", "(hash-table-exists? table key)"),
+    ("hash-table-size", "(hash-table-size table)"),
+    ("hash-table-keys", "(hash-table-keys table)"),
+    ("hash-table-values", "(hash-table-values table)"),
+    ("hash-table->alist", "(hash-table->alist table)"),
+    ("hash-table-walk", "(hash-table-walk table proc)"),
+    ("hash-table-update!", "(hash-table-update! table key proc [thunk])"),
+    ("hash-table-update!/default", "(hash-table-update!/default table key proc default)"),
+    ("hash-table-equivalence-function", "(hash-table-equivalence-function table)"),
+    ("hash-table-hash-function", "(hash-table-hash-function table)"),
+    // Sets
+    ("make-set", "(make-set x ...)"),
+    ("list->set", "(list->set list)"),
+    ("set->list", "(set->list set)"),
+    ("set-add!", "(set-add! set x ...)"),
+    ("set-remove!", "(set-remove! set x ...)"),
+    ("set-contains?", "(set-contains? set x)"),
+    ("set-size", "(set-size set)"),
+    ("set-union", "(set-union set ...)"),
+    ("set-intersection", "(set-intersection set ...)"),
+    ("set-difference", "(set-difference set ...)"),
+    // Characters, strings and symbols
+    ("char->integer", "(char->integer char)"),
+    ("integer->char", "(integer->char n)"),
+    ("string=?", "(string=? s1 s2 ...)"),
+    ("symbol->string", "(symbol->string symbol)"),
+    ("string->symbol", "(string->symbol string)"),
+    // Bytevectors
+    ("bytevector?", "(bytevector? obj)"),
+    ("make-bytevector", "(make-bytevector k [byte])"),
+    ("bytevector", "(bytevector byte ...)"),
+    ("bytevector-length", "(bytevector-length bv)"),
+    ("bytevector-u8-ref", "(bytevector-u8-ref bv k)"),
+    ("bytevector-u8-set!", "(bytevector-u8-set! bv k byte)"),
+    ("bytevector-copy", "(bytevector-copy bv [start [end]])"),
+    ("bytevector-copy!", "(bytevector-copy! to at from [start [end]])"),
+    ("bytevector-append", "(bytevector-append bv ...)"),
+    ("utf8->string", "(utf8->string bv [start [end]])"),
+    ("string->utf8", "(string->utf8 string [start [end]])"),
+    // Output
+    ("display", "(display obj [port])"),
+    ("newline", "(newline [port])"),
+    ("write", "(write obj [port])"),
+    ("write-shared", "(write-shared obj [port])"),
+    ("write-simple", "(write-simple obj [port])"),
+    ("format", "(format [dest] fmt arg ...)"),
+    ("pretty-print", "(pretty-print obj [width])"),
+    ("pp", "(pp obj [width])"),
+    ("pretty-print-width", "(pretty-print-width [n])"),
+    ("pretty-print-repl", "(pretty-print-repl [on?])"),
+    // Ports
+    ("current-input-port", "(current-input-port)"),
+    ("current-output-port", "(current-output-port)"),
+    ("current-error-port", "(current-error-port)"),
+    ("open-input-string", "(open-input-string string)"),
+    ("open-output-string", "(open-output-string)"),
+    ("get-output-string", "(get-output-string port)"),
+    ("with-output-to-string", "(with-output-to-string thunk)"),
+    ("open-input-file", "(open-input-file path)"),
+    ("open-output-file", "(open-output-file path)"),
+    ("close-port", "(close-port port)"),
+    ("close-input-port", "(close-input-port port)"),
+    ("close-output-port", "(close-output-port port)"),
+    ("call-with-port", "(call-with-port port proc)"),
+    ("port?", "(port? obj)"),
+    ("input-port?", "(input-port? obj)"),
+    ("output-port?", "(output-port? obj)"),
+    ("input-port-open?", "(input-port-open? port)"),
+    ("output-port-open?", "(output-port-open? port)"),
+    ("read-char", "(read-char [port])"),
+    ("peek-char", "(peek-char [port])"),
+    ("read", "(read [port])"),
+    ("read-line", "(read-line [port])"),
+    ("read-string", "(read-string k [port])"),
+    ("char-ready?", "(char-ready? [port])"),
+    ("eof-object", "(eof-object)"),
+    ("eof-object?", "(eof-object? obj)"),
+    // Binary ports
+    ("open-input-bytevector", "(open-input-bytevector bv)"),
+    ("open-output-bytevector", "(open-output-bytevector)"),
+    ("get-output-bytevector", "(get-output-bytevector port)"),
+    ("open-binary-input-file", "(open-binary-input-file path)"),
+    ("open-binary-output-file", "(open-binary-output-file path)"),
+    ("binary-port?", "(binary-port? obj)"),
+    ("textual-port?", "(textual-port? obj)"),
+    ("read-u8", "(read-u8 [port])"),
+    ("peek-u8", "(peek-u8 [port])"),
+    ("u8-ready?", "(u8-ready? [port])"),
+    ("read-bytevector", "(read-bytevector k [port])"),
+    ("read-bytevector!", "(read-bytevector! bv [port [start [end]]])"),
+    ("write-u8", "(write-u8 byte [port])"),
+    ("write-bytevector", "(write-bytevector bv [port [start [end]]])"),
+    // File system
+    ("file-exists?", "(file-exists? path)"),
+    ("file-directory?", "(file-directory? path)"),
+    ("delete-file", "(delete-file path)"),
+    ("delete-directory", "(delete-directory path)"),
+    ("rename-file", "(rename-file from to)"),
+    ("make-directory", "(make-directory path [parents?])"),
+    ("directory-list", "(directory-list path)"),
+    ("file-size", "(file-size path)"),
+    ("file-modification-time", "(file-modification-time path)"),
+    ("path-join", "(path-join part ...)"),
+    ("path-extension", "(path-extension path)"),
+    ("path-dirname", "(path-dirname path)"),
+    ("path-basename", "(path-basename path)"),
+    ("make-temporary-file", "(make-temporary-file [prefix])"),
+    // Process and OS
+    ("command-line", "(command-line)"),
+    ("get-environment-variable", "(get-environment-variable name)"),
+    ("get-environment-variables", "(get-environment-variables)"),
+    ("exit", "(exit [status])"),
+    ("emergency-exit", "(emergency-exit [status])"),
+    ("current-second", "(current-second)"),
+    ("current-jiffy", "(current-jiffy)"),
+    ("jiffies-per-second", "(jiffies-per-second)"),
+    ("run-process", "(run-process program [args [input]])"),
+    ("process-output", "(process-output program [args [input]])"),
+    // Error objects
+    ("error-object?", "(error-object? obj)"),
+    ("error-object-message", "(error-object-message err)"),
+    ("error-object-irritants", "(error-object-irritants err)"),
+    ("file-error?", "(file-error? obj)"),
+    ("read-error?", "(read-error? obj)"),
+    ("io-error-operation", "(io-error-operation err)"),
+    ("io-error-path", "(io-error-path err)"),
+    ("io-error-kind", "(io-error-kind err)"),
+    ("eval", "(eval expr)"),
+];
+
+// The fixed-width integer accessors follow one pattern.
+fn bytevector_int_signature(name: &str) -> Option<String> {
+    let rest = name.strip_prefix("bytevector-")?;
+    let (ty, rest) = rest.split_once('-')?;
+    let (endian, op) = rest.split_once('-')?;
+    let valid = matches!(ty, "u16" | "s16" | "u32" | "s32" | "u64" | "s64") && matches!(endian, "le" | "be");
+    match op {
+        "ref" if valid => Some(format!("({} bv k)", name)),
+        "set!" if valid => Some(format!("({} bv k n)", name)),
+        _ => None,
+    }
+}
+
+// The signature of a special form or builtin by name.
+pub fn signature(name: &str) -> Option<String> {
+    SIGNATURES
+        .iter()
+        .find(|(n, _)| *n == name)
+        .map(|(_, sig)| sig.to_string())
+        .or_else(|| bytevector_int_signature(name))
+}
+
+// A lambda's docstring: a string literal opening a body of more than one expression.
+pub fn docstring(value: &Value) -> Option<&str> {
+    let body = match value {
+        Value::Lambda { body, .. } => body,
+        Value::Closure(closure) => &closure.proto.source,
+        _ => return None,
+    };
+    match &**body {
+        Value::List(items) if items.len() > 2 && matches!(&items[0], Value::Symbol(s) if s.as_str() == "begin") => match &items[1] {
+            Value::String(doc) => Some(doc),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+// What to show for `name` bound to `value`: a builtin's signature, or a
+// lambda's parameter list and docstring.
+pub fn describe(name: &str, value: &Value) -> Option<String> {
+    match value {
+        Value::Builtin(_, builtin) => signature(builtin),
+        Value::Lambda { params, .. } => Some(call_with_doc(name, params, value)),
+        Value::Closure(closure) => Some(call_with_doc(name, &closure.proto.params, value)),
+        _ => None,
+    }
+}
+
+// `(name param ...)`, followed by the docstring if `value` has one.
+fn call_with_doc(name: &str, params: &[String], value: &Value) -> String {
+    let mut call = format!("({}", name);
+    for param in params.iter() {
+        call.push(' ');
+        call.push_str(param);
+    }
+    call.push(')');
+    match docstring(value) {
+        Some(doc) => format!("{}: {}", call, doc),
+        None => call,
+    }
+}
@@ -4,37 +4,116 @@ use std::cell::RefCell;
 use std::collections::HashMap;
 use std::rc::Rc;
 
+// A scope. The frame of a procedure call (or a `guard` handler) keeps its
+// variables in slots, which the analysis pass resolved each reference to
+// by position (see `analyze::resolve`); the global environment keeps them
+// by name.
 #[derive(Debug, Clone)]
 pub struct Environment {
+    names: Rc<Vec<String>>, // The slots' variable names
+    slots: Vec<Option<Value>>, // None until defined
     bindings: HashMap<String, Value>,
     parent: Option<Rc<RefCell<Environment>>>,
+    frame: bool, // A procedure call's or `guard` handler's scope
 }
 
 impl Environment {
     pub fn new() -> Self {
         Environment {
+            names: Rc::default(),
+            slots: Vec::new(),
             bindings: HashMap::new(),
             parent: None,
+            frame: false,
         }
     }
 
-    pub fn new_child(parent: Rc<RefCell<Environment>>) -> Self {
+    // A frame with a slot for each of `names`, filled from `values` and
+    // then left undefined.
+    pub fn new_frame(names: Rc<Vec<String>>, values: Vec<Value>, parent: Rc<RefCell<Environment>>) -> Self {
+        let mut slots: Vec<Option<Value>> = values.into_iter().map(Some).collect();
+        slots.resize(names.len(), None);
         Environment {
+            names,
+            slots,
             bindings: HashMap::new(),
             parent: Some(parent),
+            frame: true,
         }
     }
 
+    // The global environment `env` is in: itself, unless it is a frame.
+    pub fn global(env: &Rc<RefCell<Environment>>) -> Rc<RefCell<Environment>> {
+        let mut env = Rc::clone(env);
+        while env.borrow().frame {
+            let parent = env.borrow().parent().expect("a frame has a parent");
+            env = parent;
+        }
+        env
+    }
+
     pub fn define(&mut self, name: String, value: Value) {
         self.bindings.insert(name, value);
     }
 
-    // Looks up in current scope only
-    pub fn lookup_local(&self, name: &str) -> Option<Value> {
-        self.bindings.get(name).cloned()
+    pub fn parent(&self) -> Option<Rc<RefCell<Environment>>> {
+        self.parent.clone()
+    }
+
+    // Puts `parent` behind this scope, to be searched after it. The REPL keeps
+    // its result history in a scope behind the root environment this way.
+    pub fn set_parent(&mut self, parent: Rc<RefCell<Environment>>) {
+        self.parent = Some(parent);
+    }
+
+    // The bindings made in this scope itself, not in its parents.
+    pub fn local_bindings(&self) -> impl Iterator<Item = (&String, &Value)> {
+        let slots = self.names.iter().zip(&self.slots).filter_map(|(name, slot)| Some((name, slot.as_ref()?)));
+        slots.chain(&self.bindings)
+    }
+
+    // Every name visible from this scope, including those of parent scopes.
+    pub fn names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.bindings.keys().cloned().collect();
+        if let Some(parent_env) = &self.parent {
+            names.extend(parent_env.borrow().names());
+        }
+        names
+    }
+
+    // The frame `depth` scopes up from this one.
+    fn frame<R>(&self, depth: usize, f: impl FnOnce(&Environment) -> R) -> R {
+        match depth {
+            0 => f(self),
+            _ => self.parent.as_ref().expect("a slot is resolved within the scope chain").borrow().frame(depth - 1, f),
+        }
+    }
+
+    // Reads slot `index` of the frame `depth` scopes up.
+    pub fn get(&self, depth: usize, index: usize) -> Result<Value> {
+        self.frame(depth, |frame| frame.slots[index].clone().ok_or_else(|| SchemeError::UndefinedVariable(frame.names[index].clone())))
+    }
+
+    // Assigns slot `index` of the frame `depth` scopes up, which must be defined.
+    pub fn set_slot(&mut self, depth: usize, index: usize, value: Value) -> Result<()> {
+        if depth > 0 {
+            let parent = self.parent.as_ref().expect("a slot is resolved within the scope chain");
+            return parent.borrow_mut().set_slot(depth - 1, index, value);
+        }
+        match &mut self.slots[index] {
+            Some(slot) => *slot = value,
+            None => return Err(SchemeError::UndefinedVariable(self.names[index].clone())),
+        }
+        Ok(())
+    }
+
+    // Defines the variable in slot `index` of this frame.
+    pub fn define_slot(&mut self, index: usize, value: Value) {
+        self.slots[index] = Some(value);
     }
 
-    // Looks up recursively through parent scopes
+    // Looks up a variable by name, recursively through parent scopes. Slots
+    // are only read by position, so this finds globals.
     pub fn lookup(&self, name: &str) -> Option<Value> {
         if let Some(value) = self.bindings.get(name) {
             Some(value.clone())
@@ -4,6 +4,8 @@ use thiserror::Error;
 pub enum SchemeError {
     #[error("Parser Error: {0}")]
     Parser(String),
+    #[error("Incomplete Input: {0}")]
+    Incomplete(String), // Input ended partway through a datum; more text could complete it
     #[error("Evaluation Error: {0}")]
     Eval(String),
     #[error("Runtime Error: {0}")]
@@ -14,8 +16,56 @@ pub enum SchemeError {
     UndefinedVariable(String),
     #[error("Not a procedure: {0}")]
     NotProcedure(String),
+    #[error("Range Error: {0}")]
+    Range(String), // Out-of-range indices and refused allocation sizes
+    #[error("Format Error at offset {offset}: {message}")]
+    Format { offset: usize, message: String }, // `format` directive errors; offset of the `~` in chars
+    #[error("I/O Error: {operation} {path}: {message}")]
+    Io { operation: String, path: String, kind: String, message: String }, // File system and port failures; see `SchemeError::io`
     #[error("Arity Mismatch: Expected {expected}, got {got}")]
     Arity { expected: String, got: usize },
+    #[error("Exit with status {0}")]
+    Exit(i32), // Raised by `exit`; unwinds to main, which exits the process. Not caught by `guard`
+    #[error("Interrupted")]
+    Interrupted, // Ctrl-C in the REPL; see `interrupt`. Not caught by `guard`
+    // Resource limits; see `limits`. Not caught by `guard`
+    #[error("Step limit exceeded: more than {0} evaluation steps")]
+    StepLimit(u64),
+    #[error("Recursion depth limit exceeded: more than {0} nested evaluations")]
+    DepthLimit(usize),
+    #[error("Memory limit exceeded: more than {0} collection elements allocated")]
+    MemoryLimit(u64),
+    #[error("Time limit exceeded: ran longer than {0:?}")]
+    TimeLimit(std::time::Duration),
 }
 
-pub type Result<T> = std::result::Result<T, SchemeError>;
\ No newline at end of file
+pub type Result<T> = std::result::Result<T, SchemeError>;
+
+impl SchemeError {
+    // Errors that end the evaluation rather than being handled by the
+    // program: `guard` passes them on.
+    pub fn escapes_guard(&self) -> bool {
+        matches!(
+            self,
+            SchemeError::Exit(_) | SchemeError::Interrupted | SchemeError::StepLimit(_)
+                | SchemeError::DepthLimit(_) | SchemeError::MemoryLimit(_) | SchemeError::TimeLimit(_)
+        )
+    }
+
+    // An I/O error from `operation` (the builtin's name) on `path`. `kind` is
+    // a symbol name Scheme code can dispatch on, e.g. `not-found`.
+    pub fn io(operation: &str, path: &str, err: &std::io::Error) -> Self {
+        use std::io::ErrorKind;
+        let kind = match err.kind() {
+            ErrorKind::NotFound => "not-found",
+            ErrorKind::PermissionDenied => "permission-denied",
+            ErrorKind::AlreadyExists => "already-exists",
+            ErrorKind::IsADirectory => "is-a-directory",
+            ErrorKind::NotADirectory => "not-a-directory",
+            ErrorKind::DirectoryNotEmpty => "directory-not-empty",
+            ErrorKind::InvalidInput | ErrorKind::InvalidData | ErrorKind::InvalidFilename => "invalid",
+            _ => "other",
+        };
+        SchemeError::Io { operation: operation.to_string(), path: path.to_string(), kind: kind.to_string(), message: err.to_string() }
+    }
+}
@@ -1,258 +1,365 @@
+use crate::analyze::{self, analyze, LambdaCode, Node};
+use crate::compile;
 use crate::env::Environment;
 use crate::error::{Result, SchemeError};
-use crate::value::{Value, BuiltinFn};
-use std::cell::RefCell;
+use crate::interrupt;
+use crate::limits;
+use crate::port;
+use crate::printer::{self, Style};
+use crate::value::{eqv, BuiltinFn, Value};
+use crate::vm;
+use std::cell::{Cell, RefCell};
 use std::rc::Rc;
-use std::collections::HashMap;
 
 // Represents the result of one evaluation step, facilitating TCO.
 enum EvalResult {
     Value(Value),
     TailCall {
+        op: Value, // The operator as written when it is a name, else the procedure; for backtraces
         proc: Value, // The procedure to call (Lambda or Builtin)
         args: Vec<Value>, // Evaluated arguments
         env: Rc<RefCell<Environment>>, // Environment for the call
     },
 }
 
-// The main evaluation function, potentially returning a TailCall signal.
-// Takes &Value as input, matching evaluate's initial call signature
-fn eval_step(expr: &Value, env: Rc<RefCell<Environment>>) -> Result<EvalResult> {
-    // --- No changes needed inside eval_step itself ---
-    // --- It still operates on a reference &Value ---
-     match expr {
-        // Self-evaluating types
-        Value::Integer(_) | Value::Bool(_) | Value::String(_) | Value::Nil |
-        Value::Array(_) | Value::Map(_) | Value::Lambda { .. } | Value::Builtin(_, _) => Ok(EvalResult::Value(expr.clone())),
-
-        // Symbol lookup
-        Value::Symbol(s) => {
-            if s.is_empty() { // Handle the empty symbol from parser for empty input
-                 Ok(EvalResult::Value(Value::Nil)) // Or some other inert value
-            } else {
-                env.borrow()
-                   .lookup(s)
-                   .map(EvalResult::Value)
-                   .ok_or_else(|| SchemeError::UndefinedVariable(s.clone()))
+// Evaluates `node` up to the next tail call, which it returns for the
+// trampoline to make. Subexpressions in other positions are evaluated by
+// nested calls to `execute`.
+fn eval_step(node: &Node, env: Rc<RefCell<Environment>>) -> Result<EvalResult> {
+    match node {
+        Node::Const(_) | Node::Var(_) | Node::Local(_) => execute(node, env).map(EvalResult::Value),
+        Node::If(test, then, otherwise) => {
+            let branch = match execute(test, Rc::clone(&env))? {
+                Value::Bool(false) => match otherwise { // Only #f is false
+                    Some(otherwise) => otherwise,
+                    None => return Ok(EvalResult::Value(Value::Nil)), // Unspecified in R7RS, Nil is common
+                },
+                _ => then,
+            };
+            eval_step(branch, env) // Tail position
+        }
+        Node::Define(name, value) => {
+            let value = execute(value, Rc::clone(&env))?;
+            env.borrow_mut().define(name.clone(), value); // Define in the *current* environment
+            Ok(EvalResult::Value(Value::Nil))
+        }
+        Node::DefineLocal(index, value) => {
+            let value = execute(value, Rc::clone(&env))?;
+            env.borrow_mut().define_slot(*index, value);
+            Ok(EvalResult::Value(Value::Nil))
+        }
+        Node::Set(name, value) => {
+            let value = execute(value, Rc::clone(&env))?;
+            env.borrow_mut().set(name, value)?; // Set in the environment chain
+            Ok(EvalResult::Value(Value::Nil))
+        }
+        Node::SetLocal(slot, value) => {
+            let value = execute(value, Rc::clone(&env))?;
+            env.borrow_mut().set_slot(slot.depth, slot.index, value)?;
+            Ok(EvalResult::Value(Value::Nil))
+        }
+        Node::Lambda(code) => Ok(EvalResult::Value(Value::Lambda {
+            params: Rc::clone(&code.params),
+            body: Rc::clone(&code.source),
+            code: Rc::clone(code),
+            env, // Capture current environment
+        })),
+        Node::Begin(body) => sequence(body, env),
+        Node::Case(key, clauses) => {
+            let key = execute(key, Rc::clone(&env))?;
+            for clause in clauses {
+                let matched = match &clause.data {
+                    None => true, // else
+                    Some(datums) => datums.iter().any(|d| eqv(d, &key)),
+                };
+                if matched {
+                    return sequence(&clause.body, env);
+                }
             }
+            Ok(EvalResult::Value(Value::Nil)) // No clause matched
         }
-
-        // List evaluation (special forms and procedure calls)
-        Value::List(list) => {
-            if list.is_empty() {
-                return Ok(EvalResult::Value(Value::Nil));
+        Node::Guard { clauses, body, frame, .. } => {
+            // If evaluating the body raises an error, binds the error object to var and
+            // runs the first clause whose test is true, as in cond. With no matching
+            // clause the error propagates.
+            let mut result = Ok(Value::Nil);
+            for expr in body {
+                result = execute(expr, Rc::clone(&env));
+                if result.is_err() {
+                    break;
+                }
+            }
+            let err = match result {
+                Ok(value) => return Ok(EvalResult::Value(value)),
+                Err(err) if err.escapes_guard() => return Err(err),
+                Err(err) => err,
+            };
+            take_backtrace(); // Handled here, so no longer of interest
+            let handler_env = Environment::new_frame(Rc::clone(frame), vec![Value::Error(Rc::new(err.clone()))], Rc::clone(&env));
+            let handler_env = Rc::new(RefCell::new(handler_env));
+            for clause in clauses {
+                let test_val = match &clause.test {
+                    None => Value::Bool(true), // else
+                    Some(test) => execute(test, Rc::clone(&handler_env))?,
+                };
+                if matches!(test_val, Value::Bool(false)) {
+                    continue;
+                }
+                if clause.body.is_empty() {
+                    return Ok(EvalResult::Value(test_val)); // (test) yields the test's value
+                }
+                return sequence(&clause.body, handler_env);
+            }
+            Err(err)
+        }
+        Node::Call { op, args, name } => {
+            let proc_val = execute(op, Rc::clone(&env))?;
+            let mut args_val = Vec::with_capacity(args.len());
+            for arg in args {
+                args_val.push(execute(arg, Rc::clone(&env))?);
             }
 
-            let (op_expr, args_expr) = list.split_first().unwrap();
+            if let Some(name) = traced_name(&proc_val) {
+                return call_traced(&name, &proc_val, args_val, env).map(EvalResult::Value);
+            }
 
-            // Handle Special Forms
-            if let Value::Symbol(op_sym) = op_expr {
-                match op_sym.as_str() {
-                    "quote" => {
-                        if args_expr.len() != 1 {
-                            return Err(SchemeError::Arity { expected: "1".to_string(), got: args_expr.len() });
-                        }
-                        return Ok(EvalResult::Value(args_expr[0].clone()));
-                    }
-                    "if" => {
-                        if !(args_expr.len() == 2 || args_expr.len() == 3) {
-                             return Err(SchemeError::Arity { expected: "2 or 3".to_string(), got: args_expr.len() });
-                        }
-                        let cond_expr = &args_expr[0];
-                        // Evaluate condition first using the main evaluate function
-                        let cond_val = evaluate_trampolined(Rc::new(cond_expr.clone()), Rc::clone(&env))?; // Clone expr into Rc for evaluate
-                        let branch_expr = match cond_val {
-                            Value::Bool(false) => { // Only #f is false
-                                if args_expr.len() == 3 {
-                                    &args_expr[2] // Else branch
-                                } else {
-                                    return Ok(EvalResult::Value(Value::Nil)); // Undefined/unspecified in R5RS, Nil is common
-                                }
-                            }
-                            _ => &args_expr[1], // Then branch (anything else is true)
-                        };
-                        // Tail call: evaluate the chosen branch in the same context
-                        // Pass the branch reference directly to eval_step for the *next* step
-                        return eval_step(branch_expr, env);
-                    }
-                    "define" => {
-                         if args_expr.len() != 2 {
-                             return Err(SchemeError::Arity { expected: "2".to_string(), got: args_expr.len() });
-                        }
-                        let var_expr = &args_expr[0];
-                        let val_expr = &args_expr[1];
+            let op = name.clone().unwrap_or_else(|| proc_val.clone());
+            let env = match &proc_val {
+                Value::Lambda { env: lambda_env, .. } => Rc::clone(lambda_env), // The call frame's parent
+                Value::Builtin(..) | Value::Closure(_) => env, // Builtins run in the caller's environment
+                _ => return Err(SchemeError::NotProcedure(format!("{:?}", proc_val))),
+            };
+            Ok(EvalResult::TailCall { op, proc: proc_val, args: args_val, env })
+        }
+        Node::Error(err) => Err(err.clone()),
+    }
+}
 
-                        let name = match var_expr {
-                             Value::Symbol(s) => s.clone(),
-                             _ => return Err(SchemeError::Type{ expected: "symbol".to_string(), found: var_expr.type_name()}),
-                        };
+// Evaluates a body, returning its last expression as the next step.
+fn sequence(body: &[Node], env: Rc<RefCell<Environment>>) -> Result<EvalResult> {
+    let Some((last, init)) = body.split_last() else {
+        return Ok(EvalResult::Value(Value::Nil));
+    };
+    for expr in init {
+        execute(expr, Rc::clone(&env))?;
+    }
+    eval_step(last, env) // Tail position
+}
 
-                        // Evaluate the value using the main evaluate function
-                        let value = evaluate_trampolined(Rc::new(val_expr.clone()), Rc::clone(&env))?; // Clone expr into Rc for evaluate
-                        // Define in the *current* environment
-                        env.borrow_mut().define(name, value);
-                        return Ok(EvalResult::Value(Value::Nil));
-                    }
-                     "set!" => {
-                        if args_expr.len() != 2 {
-                            return Err(SchemeError::Arity { expected: "2".to_string(), got: args_expr.len() });
-                        }
-                        let var_expr = &args_expr[0];
-                        let val_expr = &args_expr[1];
+// Creates the call frame for a lambda, binding each parameter to its argument.
+fn bind_arguments(code: &LambdaCode, args: Vec<Value>, parent: Rc<RefCell<Environment>>) -> Result<Environment> {
+    if code.params.len() != args.len() {
+        return Err(SchemeError::Arity { expected: format!("{}", code.params.len()), got: args.len() });
+    }
+    Ok(Environment::new_frame(Rc::clone(&code.frame), args, parent))
+}
 
-                        let name = match var_expr {
-                            Value::Symbol(s) => s.clone(),
-                            _ => return Err(SchemeError::Type{ expected: "symbol".to_string(), found: var_expr.type_name()}),
-                        };
+// The lambda call a trampoline is running: the operator, and the parameters
+// and call frame its arguments can be read back from for a backtrace.
+type Frame = (Value, Rc<Vec<String>>, Rc<RefCell<Environment>>);
 
-                        // Evaluate the value using the main evaluate function
-                        let value = evaluate_trampolined(Rc::new(val_expr.clone()), Rc::clone(&env))?; // Clone expr into Rc for evaluate
-                        // Set in the environment chain
-                        env.borrow_mut().set(&name, value)?;
-                        return Ok(EvalResult::Value(Value::Nil));
-                    }
-                    "lambda" => {
-                         if args_expr.len() < 1 {
-                            return Err(SchemeError::Eval("Invalid lambda syntax: requires parameters and body".to_string()));
-                        }
-                        let params_expr = &args_expr[0];
-                        let body_exprs = &args_expr[1..];
+// Non-tail evaluations nest on the Rust stack, as do reading, printing,
+// comparing, hashing and freeing nested data. When less than `RED_ZONE` is
+// left, the next level continues on a new segment of `STACK_SEGMENT` bytes,
+// so recursion is bounded by memory and the depth limit rather than the
+// thread's stack.
+const RED_ZONE: usize = 1024 * 1024;
+const STACK_SEGMENT: usize = 16 * 1024 * 1024;
 
-                        let params: Rc<Vec<String>> = match params_expr {
-                            Value::List(p_list) => {
-                                let mut names = Vec::new();
-                                for p in p_list {
-                                    if let Value::Symbol(s) = p {
-                                        names.push(s.clone());
-                                    } else {
-                                        return Err(SchemeError::Eval("Lambda parameters must be symbols".to_string()));
-                                    }
-                                }
-                                Rc::new(names)
-                            }
-                            _ => return Err(SchemeError::Eval("Lambda parameters must be a list of symbols".to_string())),
-                        };
+// Runs one level of a recursion whose depth the program controls.
+pub fn grow_stack<R>(f: impl FnOnce() -> R) -> R {
+    stacker::maybe_grow(RED_ZONE, STACK_SEGMENT, f)
+}
 
-                        let body = if body_exprs.len() == 1 {
-                             Rc::new(body_exprs[0].clone()) // body is Rc<Value>
-                        } else {
-                            let mut begin_list = vec![Value::Symbol("begin".to_string())];
-                            begin_list.extend(body_exprs.iter().cloned());
-                             Rc::new(Value::List(begin_list)) // body is Rc<Value>
-                        };
+// Evaluates an analyzed expression to its value. Constants and variables are
+// read directly; anything else runs a trampoline nested on the Rust stack.
+pub fn execute(node: &Node, env: Rc<RefCell<Environment>>) -> Result<Value> {
+    match node {
+        Node::Const(value) => return Ok(value.clone()),
+        Node::Var(name) => return env.borrow().lookup(name).ok_or_else(|| SchemeError::UndefinedVariable(name.to_string())),
+        Node::Local(slot) => return env.borrow().get(slot.depth, slot.index),
+        _ => {}
+    }
+    let _nested = limits::enter()?;
+    let mut frame = None;
+    let result = grow_stack(|| trampoline(node, env, &mut frame));
+    if let (Err(_), Some((op, params, call_env))) = (&result, frame) {
+        let call_env = call_env.borrow();
+        record_call(|| call_form(&op, (0..params.len()).map(|i| call_env.get(0, i).unwrap_or(Value::Nil))));
+    }
+    result
+}
 
-                        let lambda = Value::Lambda {
-                            params,
-                            body,
-                            env: Rc::clone(&env), // Capture current environment
-                        };
-                        return Ok(EvalResult::Value(lambda));
-                    }
-                     "begin" => {
-                        if args_expr.is_empty() {
-                            return Ok(EvalResult::Value(Value::Nil));
-                        }
-                        // Evaluate all but the last sequentially for side effects
-                        for expr in &args_expr[..args_expr.len() - 1] {
-                            // Use the main evaluate function here
-                             evaluate_trampolined(Rc::new(expr.clone()), Rc::clone(&env))?; // Clone expr into Rc for evaluate
-                        }
-                        // Tail call: evaluate the last expression by passing it to next eval_step
-                        return eval_step(&args_expr[args_expr.len() - 1], env);
-                    }
-                    _ => {} // Not a special form, proceed to procedure call
+fn trampoline(node: &Node, env: Rc<RefCell<Environment>>, frame: &mut Option<Frame>) -> Result<Value> {
+    interrupt::check()?; // Ctrl-C in the REPL
+    limits::step()?;
+    let mut step = eval_step(node, env)?;
+    loop {
+        let (op, proc, args, env) = match step {
+            EvalResult::Value(v) => return Ok(v),
+            EvalResult::TailCall { op, proc, args, env } => (op, proc, args, env),
+        };
+        match &proc {
+            Value::Lambda { params, code, .. } => {
+                if params.len() != args.len() {
+                    record_call(|| call_form(&op, args.iter().cloned()));
                 }
+                let call_env = Rc::new(RefCell::new(bind_arguments(code, args, env)?));
+                *frame = Some((op, Rc::clone(params), Rc::clone(&call_env))); // Replaces the caller's: a tail call
+                interrupt::check()?;
+                limits::step()?;
+                step = eval_step(&code.body, call_env)?;
             }
-         // --- Procedure Call ---
-            // 1. Evaluate the operator using the main evaluate function
-            // proc_val is the evaluated procedure (Value::Lambda or Value::Builtin)
-            let proc_val = evaluate_trampolined(Rc::new(op_expr.clone()), Rc::clone(&env))?;
-
-            // 2. Evaluate arguments using the main evaluate function
-            let mut args_val = Vec::with_capacity(args_expr.len());
-            for arg_expr in args_expr {
-                args_val.push(evaluate_trampolined(Rc::new(arg_expr.clone()), Rc::clone(&env))?);
-            }
-
-            // 3. Prepare for tail call (return TailCall signal)
-            // --- FIX: Match on a reference to proc_val ---
-            match &proc_val {
-                Value::Lambda { env: lambda_env, params: _, body: _ } => { // Use _ for fields not needed here
-                    // lambda_env is now &Rc<RefCell<Environment>> (a reference to the Rc)
-                    // proc_val is still fully valid because we only borrowed it.
-                    Ok(EvalResult::TailCall {
-                        // Clone the whole procedure Value (Lambda variant)
-                        proc: proc_val.clone(),
-                        args: args_val,
-                        // Clone the Rc pointer for the captured environment
-                        env: Rc::clone(lambda_env),
-                    })
+            Value::Builtin(func, _name) => {
+                // Builtins don't continue the loop; they return a final value or error.
+                let result = call_builtin(*func, &args, env); // Pass the env the builtin runs in
+                if result.is_err() {
+                    record_call(|| call_form(&op, args));
                 }
-                Value::Builtin { .. } => {
-                    // proc_val is still fully valid.
-                    Ok(EvalResult::TailCall {
-                        // Clone the whole procedure Value (Builtin variant)
-                        proc: proc_val.clone(),
-                        args: args_val,
-                        // For builtins, the 'next' environment is just the *current*
-                        // environment where the call is happening. Clone its Rc.
-                        env: Rc::clone(&env),
-                    })
-                }
-                _ => Err(SchemeError::NotProcedure(format!("{:?}", proc_val))), // Can still use proc_val here safely
+                return result;
             }
+            Value::Closure(closure) => return vm::call(closure, args, Some(op)), // Compiled elsewhere
+            _ => return Err(SchemeError::NotProcedure(format!("Internal Error: Tail call with non-procedure: {:?}", proc))),
         }
     }
 }
 
-// Renamed the public function to avoid confusion with eval_step
-// Now takes Rc<Value> to manage lifetime in the loop
-pub fn evaluate_trampolined(initial_expr: Rc<Value>, initial_env: Rc<RefCell<Environment>>) -> Result<Value> {
-    let mut current_expr_rc = initial_expr; // current_expr_rc now holds the Rc
-    let mut current_env = initial_env;
+// Calls a builtin, counting the list or string it returns against the element limit.
+pub fn call_builtin(func: BuiltinFn, args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value> {
+    let result = func(args, env)?;
+    limits::allocate(limits::copied_elements(&result))?;
+    Ok(result)
+}
 
-    loop {
-        // Check for stack depth / infinite loop prevention (optional)
+// Which evaluator runs code: this module's tree-walker, the reference
+// implementation, or the bytecode VM (see `vm`), chosen with `--vm`.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Backend {
+    Tree,
+    Bytecode,
+}
 
-        // Pass a reference to the Value inside the Rc to eval_step
-        match eval_step(&*current_expr_rc, Rc::clone(&current_env))? {
-            EvalResult::Value(v) => return Ok(v),
-            EvalResult::TailCall { proc, args, env: next_env_base } => {
-                match proc {
-                    Value::Lambda { params, body, env: _lambda_captured_env } => {
-                        if params.len() != args.len() {
-                             return Err(SchemeError::Arity { expected: format!("{}", params.len()), got: args.len() });
-                        }
+thread_local! {
+    static BACKEND: Cell<Backend> = const { Cell::new(Backend::Tree) };
+}
 
-                        let mut call_env_bindings = Environment::new_child(Rc::clone(&next_env_base));
-                        for (param_name, arg_val) in params.iter().zip(args.iter()) {
-                            call_env_bindings.define(param_name.clone(), arg_val.clone());
-                        }
+pub fn set_backend(backend: Backend) {
+    BACKEND.set(backend);
+}
 
-                        // --- The Fix ---
-                        // Assign the Rc<Value> directly. This clones the Rc pointer (cheap)
-                        // and ensures the body Value stays alive for the next iteration.
-                        current_expr_rc = Rc::clone(&body); // body is already Rc<Value>
-                        current_env = Rc::new(RefCell::new(call_env_bindings));
-                        // Continue the loop (tail call)
-                    }
-                     Value::Builtin(func, _name) => {
-                         // Builtins don't continue the loop; they return a final value or error.
-                        return func(&args, current_env); // Pass the env the builtin runs in
-                    }
-                     _ => {
-                        return Err(SchemeError::NotProcedure(format!("Internal Error: Tail call with non-procedure: {:?}", proc)));
-                     }
-                }
-            }
+// Evaluates a parsed expression: analyzes it, then runs the result with the
+// current back end.
+pub fn evaluate(expr: &Value, env: Rc<RefCell<Environment>>) -> Result<Value> {
+    let mut node = analyze(expr);
+    match BACKEND.get() {
+        Backend::Tree => {
+            analyze::resolve(&mut node);
+            execute(&node, env)
         }
+        Backend::Bytecode => vm::run(compile::compile(&node), env),
     }
 }
 
-// Keep a version matching the original signature expected by builtins like `eval`
-// This function now just wraps the call to the trampolined version.
-pub fn evaluate(expr: &Value, env: Rc<RefCell<Environment>>) -> Result<Value> {
-    // Clone the input expression into an Rc to pass to the TCO loop
-    let expr_rc = Rc::new(expr.clone());
-    evaluate_trampolined(expr_rc, env)
-}
\ No newline at end of file
+// Calls a procedure value with already-evaluated arguments.
+// Used by builtins that take procedure arguments (e.g. `map-for-each`).
+pub fn apply(proc: &Value, args: Vec<Value>, env: Rc<RefCell<Environment>>) -> Result<Value> {
+    if let Some(name) = traced_name(proc) {
+        return call_traced(&name, proc, args, env);
+    }
+    apply_untraced(proc, args, env)
+}
+
+fn apply_untraced(proc: &Value, args: Vec<Value>, env: Rc<RefCell<Environment>>) -> Result<Value> {
+    match proc {
+        Value::Lambda { code, env: closure_env, .. } => {
+            let call_env = bind_arguments(code, args, Rc::clone(closure_env))?;
+            execute(&code.body, Rc::new(RefCell::new(call_env)))
+        }
+        Value::Builtin(func, _name) => call_builtin(*func, &args, env),
+        Value::Closure(closure) => vm::call(closure, args, Some(proc.clone())),
+        _ => Err(SchemeError::NotProcedure(format!("{:?}", proc))),
+    }
+}
+
+// --- Backtraces ---
+// When an error unwinds out of procedure calls, each call is recorded as a
+// list `(op arg ...)`, innermost first, for the REPL's `*e`. A call replaced
+// by a tail call has no frame left and is not shown. Only the innermost
+// calls are kept, and `guard` drops those of an error it catches.
+
+const BACKTRACE_LIMIT: usize = 50;
+
+thread_local! {
+    static BACKTRACE: RefCell<Vec<Value>> = const { RefCell::new(Vec::new()) };
+}
+
+pub fn call_form(op: &Value, args: impl IntoIterator<Item = Value>) -> Value {
+    Value::List(std::iter::once(op.clone()).chain(args).collect())
+}
+
+pub fn record_call(call: impl FnOnce() -> Value) {
+    BACKTRACE.with_borrow_mut(|calls| {
+        if calls.len() < BACKTRACE_LIMIT {
+            calls.push(call());
+        }
+    });
+}
+
+// The calls the last uncaught error unwound through, innermost first.
+pub fn take_backtrace() -> Vec<Value> {
+    BACKTRACE.with_borrow_mut(std::mem::take)
+}
+
+// --- Tracing ---
+// The REPL's `,trace` prints each call of a traced procedure with its
+// arguments, and its result, indented by the depth of traced calls. A traced
+// call is made as an ordinary nested call so its result can be shown, so
+// tail calls through a traced procedure use stack.
+
+thread_local! {
+    static TRACED: RefCell<Vec<(String, Value)>> = const { RefCell::new(Vec::new()) };
+    static TRACE_DEPTH: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
+}
+
+// Starts tracing `proc`, showing its calls under `name`.
+pub fn trace(name: &str, proc: Value) {
+    TRACED.with_borrow_mut(|traced| {
+        traced.retain(|(_, p)| !eqv(p, &proc));
+        traced.push((name.to_string(), proc));
+    });
+}
+
+// Stops tracing the procedure shown as `name`, or every procedure. Returns
+// the names no longer traced.
+pub fn untrace(name: Option<&str>) -> Vec<String> {
+    TRACED.with_borrow_mut(|traced| {
+        let (removed, kept) = traced.drain(..).partition(|(n, _)| name.is_none_or(|name| name == n));
+        *traced = kept;
+        removed.into_iter().map(|(n, _)| n).collect()
+    })
+}
+
+pub fn traced_name(proc: &Value) -> Option<String> {
+    TRACED.with_borrow(|traced| traced.iter().find(|(_, p)| eqv(p, proc)).map(|(n, _)| n.clone()))
+}
+
+fn write_trace(depth: usize, line: &str) -> Result<()> {
+    let out = port::current_output();
+    let mut out = out.borrow_mut();
+    out.fresh_line()?;
+    out.write_str(&format!("{}{}\n", "| ".repeat(depth), line))
+}
+
+fn call_traced(name: &str, proc: &Value, args: Vec<Value>, env: Rc<RefCell<Environment>>) -> Result<Value> {
+    let depth = TRACE_DEPTH.get();
+    let call = Value::List(std::iter::once(Value::symbol(name)).chain(args.iter().cloned()).collect());
+    write_trace(depth, &printer::to_string(&call, Style::Write))?;
+    TRACE_DEPTH.set(depth + 1);
+    let result = apply_untraced(proc, args, env);
+    TRACE_DEPTH.set(depth);
+    match &result {
+        Ok(value) => write_trace(depth, &format!("=> {}", printer::to_string(value, Style::Write)))?,
+        Err(err) => write_trace(depth, &format!("raised {}", err))?,
+    }
+    result
+}
@@ -0,0 +1,228 @@
+use crate::error::{Result, SchemeError};
+use crate::value::Value;
+
+// Derived expression types. The evaluator implements only the core forms;
+// these are rewritten into them: `let` into a lambda call, `cond` into
+// nested `if`s, and so on. The analysis pass (see `analyze`) expands each
+// derived form it meets, one step at a time, and the REPL's `,expand` shows
+// the full rewrite of an expression.
+//
+// The rewrites of `or` and `cond`'s `=>` clauses bind a temporary, named
+// `#:value` like an uninterned symbol; the reader can only produce that
+// name as `|#:value|`, so it does not capture the user's variables.
+
+const TEMP: &str = "#:value";
+
+fn list(items: Vec<Value>) -> Value {
+    Value::List(items)
+}
+
+fn sym(name: &str) -> Value {
+    Value::symbol(name)
+}
+
+// `(head items...)`
+fn form(head: &str, items: impl IntoIterator<Item = Value>) -> Value {
+    list(std::iter::once(sym(head)).chain(items).collect())
+}
+
+// A body of expressions as one expression.
+fn sequence(body: &[Value]) -> Value {
+    match body {
+        [only] => only.clone(),
+        _ => form("begin", body.iter().cloned()),
+    }
+}
+
+// The unspecified value, as `if` without an alternative gives it.
+fn unspecified() -> Value {
+    form("if", [Value::Bool(false), Value::Bool(false)])
+}
+
+fn syntax_error(name: &str, shape: &str) -> SchemeError {
+    SchemeError::Eval(format!("Invalid {} syntax: expected {}", name, shape))
+}
+
+// The items of a list, treating `()` as empty.
+fn items(value: &Value) -> Option<&[Value]> {
+    match value {
+        Value::List(items) => Some(items),
+        Value::Nil => Some(&[]),
+        _ => None,
+    }
+}
+
+// Splits `((var init) ...)` into the variables and their initial values.
+fn bindings(name: &str, spec: &Value) -> Result<(Vec<Value>, Vec<Value>)> {
+    let shape = "((var init) ...) and a body";
+    let mut vars = Vec::new();
+    let mut inits = Vec::new();
+    for binding in items(spec).ok_or_else(|| syntax_error(name, shape))? {
+        match items(binding) {
+            Some([var @ Value::Symbol(_), init]) => {
+                vars.push(var.clone());
+                inits.push(init.clone());
+            }
+            _ => return Err(syntax_error(name, shape)),
+        }
+    }
+    Ok((vars, inits))
+}
+
+fn expand_let(args: &[Value]) -> Result<Value> {
+    let shape = "((var init) ...) and a body";
+    match args {
+        // Named let: (let name ((var init) ...) body ...) loops by calling name
+        [name @ Value::Symbol(_), spec, body @ ..] if !body.is_empty() => {
+            let (vars, inits) = bindings("let", spec)?;
+            let proc = form("lambda", std::iter::once(list(vars)).chain(body.iter().cloned()));
+            let letrec = form("letrec", [list(vec![list(vec![name.clone(), proc])]), name.clone()]);
+            Ok(list(std::iter::once(letrec).chain(inits).collect()))
+        }
+        [spec, body @ ..] if !body.is_empty() => {
+            let (vars, inits) = bindings("let", spec)?;
+            let proc = form("lambda", std::iter::once(list(vars)).chain(body.iter().cloned()));
+            Ok(list(std::iter::once(proc).chain(inits).collect()))
+        }
+        _ => Err(syntax_error("let", shape)),
+    }
+}
+
+fn expand_let_star(args: &[Value]) -> Result<Value> {
+    let [spec, body @ ..] = args else {
+        return Err(syntax_error("let*", "((var init) ...) and a body"));
+    };
+    let specs = items(spec).ok_or_else(|| syntax_error("let*", "((var init) ...) and a body"))?;
+    match specs.split_first() {
+        Some((first, rest)) if !body.is_empty() => {
+            let inner = form("let*", std::iter::once(list(rest.to_vec())).chain(body.iter().cloned()));
+            Ok(form("let", [list(vec![first.clone()]), inner]))
+        }
+        _ => Ok(form("let", args.iter().cloned())),
+    }
+}
+
+// Each init is evaluated after the previous variables are bound, and every
+// variable is visible to every init, so mutually recursive procedures work.
+fn expand_letrec(name: &str, args: &[Value]) -> Result<Value> {
+    let [spec, body @ ..] = args else {
+        return Err(syntax_error(name, "((var init) ...) and a body"));
+    };
+    if body.is_empty() {
+        return Err(syntax_error(name, "((var init) ...) and a body"));
+    }
+    let (vars, inits) = bindings(name, spec)?;
+    let defines = vars.into_iter().zip(inits).map(|(var, init)| form("define", [var, init]));
+    let proc = form("lambda", std::iter::once(list(Vec::new())).chain(defines).chain(body.iter().cloned()));
+    Ok(list(vec![proc]))
+}
+
+fn expand_cond(clauses: &[Value]) -> Result<Value> {
+    let shape = "(test expr ...) clauses";
+    let Some((clause, rest)) = clauses.split_first() else {
+        return Ok(unspecified());
+    };
+    let parts = items(clause).filter(|parts| !parts.is_empty()).ok_or_else(|| syntax_error("cond", shape))?;
+    let (test, body) = parts.split_first().unwrap();
+    let otherwise = || if rest.is_empty() { unspecified() } else { form("cond", rest.iter().cloned()) };
+    match (test, body) {
+        (Value::Symbol(s), _) if s.as_str() == "else" => {
+            if !rest.is_empty() || body.is_empty() {
+                return Err(SchemeError::Eval("Invalid cond syntax: else must be the last clause and have a body".to_string()));
+            }
+            Ok(sequence(body))
+        }
+        (_, []) => Ok(form("or", [test.clone(), otherwise()])),
+        // (test => receiver) calls receiver with the value of test
+        (_, [Value::Symbol(arrow), receiver]) if arrow.as_str() == "=>" => {
+            let call = list(vec![receiver.clone(), sym(TEMP)]);
+            let test_value = form("if", [sym(TEMP), call, otherwise()]);
+            Ok(form("let", [list(vec![list(vec![sym(TEMP), test.clone()])]), test_value]))
+        }
+        _ => Ok(form("if", [test.clone(), sequence(body), otherwise()])),
+    }
+}
+
+// Rewrites a use of the derived form `op` into core forms, one step: the
+// result may itself use derived forms. `None` if `op` is not derived.
+pub fn expand_once(op: &str, args: &[Value]) -> Option<Result<Value>> {
+    let expanded = match op {
+        "let" => expand_let(args),
+        "let*" => expand_let_star(args),
+        "letrec" | "letrec*" => expand_letrec(op, args),
+        "cond" => expand_cond(args),
+        "and" => Ok(match args {
+            [] => Value::Bool(true),
+            [only] => only.clone(),
+            [first, rest @ ..] => form("if", [first.clone(), form("and", rest.iter().cloned()), Value::Bool(false)]),
+        }),
+        "or" => Ok(match args {
+            [] => Value::Bool(false),
+            [only] => only.clone(),
+            [first, rest @ ..] => {
+                let test = form("if", [sym(TEMP), sym(TEMP), form("or", rest.iter().cloned())]);
+                form("let", [list(vec![list(vec![sym(TEMP), first.clone()])]), test])
+            }
+        }),
+        "when" | "unless" => match args.split_first() {
+            Some((test, body)) if !body.is_empty() => {
+                let body = sequence(body);
+                Ok(if op == "when" { form("if", [test.clone(), body]) } else { form("if", [test.clone(), unspecified(), body]) })
+            }
+            _ => Err(syntax_error(op, "a test and a body")),
+        },
+        // (define (name param ...) body ...) is (define name (lambda (param ...) body ...))
+        "define" => match args.split_first() {
+            Some((Value::List(signature), body)) if !body.is_empty() => match signature.split_first() {
+                Some((name @ Value::Symbol(_), params)) => {
+                    let proc = form("lambda", std::iter::once(list(params.to_vec())).chain(body.iter().cloned()));
+                    Ok(form("define", [name.clone(), proc]))
+                }
+                _ => Err(syntax_error("define", "(define (name param ...) body ...)")),
+            },
+            _ => return None,
+        },
+        _ => return None,
+    };
+    Some(expanded)
+}
+
+// Expands every derived form in `expr`, leaving only core forms. Quoted
+// data, lambda parameters and `case` data are not expressions and are kept.
+pub fn expand(expr: &Value) -> Result<Value> {
+    let Value::List(items) = expr else {
+        return Ok(expr.clone());
+    };
+    let Some((Value::Symbol(op), args)) = items.split_first() else {
+        return items.iter().map(expand).collect::<Result<Vec<_>>>().map(Value::List);
+    };
+    if let Some(expanded) = expand_once(op.as_str(), args) {
+        return expand(&expanded?);
+    }
+    let keep = match op.as_str() {
+        "quote" => return Ok(expr.clone()),
+        "lambda" => 2, // The head and the parameters
+        "case" => {
+            let Some((key, clauses)) = args.split_first() else {
+                return Ok(expr.clone());
+            };
+            let mut out = vec![items[0].clone(), expand(key)?];
+            for clause in clauses {
+                out.push(match clause {
+                    Value::List(parts) if !parts.is_empty() => {
+                        let body = parts[1..].iter().map(expand).collect::<Result<Vec<_>>>()?;
+                        list(std::iter::once(parts[0].clone()).chain(body).collect())
+                    }
+                    other => other.clone(),
+                });
+            }
+            return Ok(list(out));
+        }
+        _ => 1,
+    };
+    let mut out: Vec<Value> = items.iter().take(keep).cloned().collect();
+    for item in items.iter().skip(keep) {
+        out.push(expand(item)?);
+    }
+    Ok(list(out))
+}
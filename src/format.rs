@@ -0,0 +1,167 @@
+use crate::builtins::MAX_SEQUENCE_LENGTH;
+use crate::error::{Result, SchemeError};
+use crate::limits;
+use crate::printer::{self, Style};
+use crate::value::Value;
+use std::iter::Peekable;
+use std::str::CharIndices;
+
+// The directive language of `format`, after SRFI-28 and Common Lisp:
+//
+//   ~a  display the next argument        ~s  write the next argument
+//   ~d  integer in decimal               ~x  integer in hex
+//   ~b  integer in binary                ~o  integer in octal
+//   ~%  newline                          ~~  a literal tilde
+//
+// A directive may carry parameters between the `~` and the letter, in the
+// order `~mincol,'padchar.precision@X`, each optional:
+//
+//   mincol     pad the output to at least this many columns
+//   'padchar   pad with this char instead of a space
+//   .precision ~a/~s: print at most this many chars; ~d/~x/~b/~o: print at
+//              least this many digits, zero-filled
+//   @          ~a/~s pad on the left (right-align); numbers are always
+//              right-aligned
+//
+// `~n%` and `~n~` repeat the output n times. No parameter may exceed
+// MAX_SEQUENCE_LENGTH, the longest string or array the interpreter builds.
+// Every error reports the offset (in chars) of the `~` that started the
+// offending directive.
+
+fn error(offset: usize, message: impl Into<String>) -> SchemeError {
+    SchemeError::Format { offset, message: message.into() }
+}
+
+#[derive(Default)]
+struct Params {
+    mincol: Option<usize>,
+    padchar: Option<char>,
+    precision: Option<usize>,
+    at: bool,
+}
+
+fn read_number(chars: &mut Peekable<CharIndices>, offset: usize) -> Result<Option<usize>> {
+    let mut digits = String::new();
+    while let Some(&(_, c)) = chars.peek().filter(|(_, c)| c.is_ascii_digit()) {
+        digits.push(c);
+        chars.next();
+    }
+    if digits.is_empty() {
+        return Ok(None);
+    }
+    match digits.parse() {
+        Ok(n) if n <= MAX_SEQUENCE_LENGTH => Ok(Some(n)),
+        _ => Err(error(offset, format!("parameter {} exceeds the maximum of {}", digits, MAX_SEQUENCE_LENGTH))),
+    }
+}
+
+fn read_params(chars: &mut Peekable<CharIndices>, offset: usize) -> Result<Params> {
+    let mut params = Params { mincol: read_number(chars, offset)?, ..Params::default() };
+    if chars.next_if(|&(_, c)| c == ',').is_some() {
+        match (chars.next(), chars.next()) {
+            (Some((_, '\'')), Some((_, c))) => params.padchar = Some(c),
+            _ => return Err(error(offset, "expected 'c after ',' for the pad char")),
+        }
+    }
+    if chars.next_if(|&(_, c)| c == '.').is_some() {
+        params.precision = Some(read_number(chars, offset)?.ok_or_else(|| error(offset, "expected digits after '.'"))?);
+    }
+    params.at = chars.next_if(|&(_, c)| c == '@').is_some();
+    Ok(params)
+}
+
+// Checks that `out` may grow by `n` chars before they are built: the output
+// is a string like any other, bounded by MAX_SEQUENCE_LENGTH and counted
+// against the element limit (see limits.rs).
+fn reserve(out: &str, n: usize, offset: usize) -> Result<()> {
+    let len = out.len().saturating_add(n);
+    if len > MAX_SEQUENCE_LENGTH {
+        return Err(error(offset, format!("output of {} chars exceeds the maximum of {}", len, MAX_SEQUENCE_LENGTH)));
+    }
+    limits::reserve(len)
+}
+
+fn pad(out: &mut String, text: &str, params: &Params, left: bool, offset: usize) -> Result<()> {
+    let fill = params.mincol.map_or(0, |mincol| mincol.saturating_sub(text.chars().count()));
+    reserve(out, text.len() + fill, offset)?;
+    let padding = std::iter::repeat_n(params.padchar.unwrap_or(' '), fill);
+    if left {
+        out.extend(padding);
+        out.push_str(text);
+    } else {
+        out.push_str(text);
+        out.extend(padding);
+    }
+    Ok(())
+}
+
+fn format_integer(out: &mut String, n: i64, radix: u32, params: &Params, offset: usize) -> Result<()> {
+    let magnitude = n.unsigned_abs();
+    let mut digits = match radix {
+        2 => format!("{:b}", magnitude),
+        8 => format!("{:o}", magnitude),
+        16 => format!("{:x}", magnitude),
+        _ => magnitude.to_string(),
+    };
+    if let Some(precision) = params.precision.filter(|p| *p > digits.len()) {
+        reserve(out, precision + 1, offset)?;
+        digits = "0".repeat(precision - digits.len()) + &digits;
+    }
+    if n < 0 {
+        digits.insert(0, '-');
+    }
+    pad(out, &digits, params, true, offset)
+}
+
+// Expands `fmt` with `args`. Unused arguments are an error, like missing ones.
+pub fn format(fmt: &str, args: &[Value]) -> Result<String> {
+    let mut out = String::new();
+    let mut args = args.iter();
+    let mut chars = fmt.char_indices().peekable();
+    let mut char_offset = 0; // Offset of the current char, counted in chars
+    while let Some((byte_offset, c)) = chars.next() {
+        let offset = char_offset;
+        if c != '~' {
+            out.push(c);
+            char_offset += 1;
+            continue;
+        }
+        let params = read_params(&mut chars, offset)?;
+        let (end, directive) = chars.next().ok_or_else(|| error(offset, "incomplete directive at end of format string"))?;
+        char_offset += fmt[byte_offset..end].chars().count() + 1;
+        let mut next_arg = || args.next().ok_or_else(|| error(offset, format!("not enough arguments for ~{}", directive)));
+        match directive.to_ascii_lowercase() {
+            'a' | 's' => {
+                let style = if directive.eq_ignore_ascii_case(&'a') { Style::Display } else { Style::Write };
+                let mut text = printer::to_string(next_arg()?, style);
+                if let Some(precision) = params.precision {
+                    text = text.chars().take(precision).collect();
+                }
+                pad(&mut out, &text, &params, params.at, offset)?;
+            }
+            radix_char @ ('d' | 'x' | 'b' | 'o') => {
+                let radix = match radix_char {
+                    'x' => 16,
+                    'b' => 2,
+                    'o' => 8,
+                    _ => 10,
+                };
+                match next_arg()? {
+                    Value::Integer(n) => format_integer(&mut out, *n, radix, &params, offset)?,
+                    other => return Err(error(offset, format!("~{} expects an integer, found {}", directive, other.type_name()))),
+                }
+            }
+            repeated @ ('%' | '~') => {
+                let count = params.mincol.unwrap_or(1);
+                reserve(&out, count, offset)?;
+                out.extend(std::iter::repeat_n(if repeated == '%' { '\n' } else { '~' }, count));
+            }
+            _ => return Err(error(offset, format!("unknown directive ~{}", directive))),
+        }
+    }
+    let unused = args.count();
+    if unused > 0 {
+        return Err(error(fmt.chars().count(), format!("{} unused argument(s)", unused)));
+    }
+    Ok(out)
+}
@@ -0,0 +1,110 @@
+use crate::env::Environment;
+use crate::error::{Result, SchemeError};
+use crate::eval::apply;
+use crate::limits;
+use crate::value::{MapKey, Value};
+use std::cell::RefCell;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::rc::Rc;
+
+// SRFI-69 style hash table whose equality and hash functions are Scheme
+// procedures. Entries are bucketed by the hash procedure's result and each
+// bucket is searched with the equality procedure.
+pub struct HashTable {
+    equal: Value,
+    hash: Value,
+    buckets: HashMap<i64, Vec<(Value, Value)>>,
+    len: usize,
+}
+
+// Default hash used by `hash`, `equal-hash` and tables created without a hash procedure.
+pub fn structural_hash(value: &Value) -> i64 {
+    let mut hasher = DefaultHasher::new();
+    MapKey(value.clone()).hash(&mut hasher);
+    hasher.finish() as i64
+}
+
+impl HashTable {
+    pub fn new(equal: Value, hash: Value) -> Self {
+        HashTable { equal, hash, buckets: HashMap::new(), len: 0 }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn equal_proc(&self) -> &Value {
+        &self.equal
+    }
+
+    pub fn hash_proc(&self) -> &Value {
+        &self.hash
+    }
+
+    pub fn entries(&self) -> Vec<(Value, Value)> {
+        self.buckets.values().flat_map(|bucket| bucket.iter().cloned()).collect()
+    }
+}
+
+// The table operations call back into Scheme, so they take the table's
+// `Rc<RefCell<..>>` and only borrow it between procedure calls; an equality
+// or hash procedure that touches the table itself will not hit a borrow panic.
+
+fn hash_of(table: &Rc<RefCell<HashTable>>, key: &Value, env: &Rc<RefCell<Environment>>) -> Result<i64> {
+    let hash = table.borrow().hash.clone();
+    match apply(&hash, vec![key.clone()], Rc::clone(env))? {
+        Value::Integer(h) => Ok(h),
+        other => Err(SchemeError::Type { expected: "integer hash value".to_string(), found: other.type_name() }),
+    }
+}
+
+// Finds the bucket hash and the position of `key` inside that bucket.
+fn locate(table: &Rc<RefCell<HashTable>>, key: &Value, env: &Rc<RefCell<Environment>>) -> Result<(i64, Option<usize>)> {
+    let h = hash_of(table, key, env)?;
+    let (equal, candidates) = {
+        let t = table.borrow();
+        let candidates: Vec<Value> = t.buckets.get(&h).map(|b| b.iter().map(|(k, _)| k.clone()).collect()).unwrap_or_default();
+        (t.equal.clone(), candidates)
+    };
+    for (i, candidate) in candidates.iter().enumerate() {
+        let same = apply(&equal, vec![candidate.clone(), key.clone()], Rc::clone(env))?;
+        if !matches!(same, Value::Bool(false)) {
+            return Ok((h, Some(i)));
+        }
+    }
+    Ok((h, None))
+}
+
+pub fn get(table: &Rc<RefCell<HashTable>>, key: &Value, env: &Rc<RefCell<Environment>>) -> Result<Option<Value>> {
+    let (h, pos) = locate(table, key, env)?;
+    Ok(pos.map(|i| table.borrow().buckets[&h][i].1.clone()))
+}
+
+pub fn insert(table: &Rc<RefCell<HashTable>>, key: Value, value: Value, env: &Rc<RefCell<Environment>>) -> Result<()> {
+    let (h, pos) = locate(table, &key, env)?;
+    let mut t = table.borrow_mut();
+    match pos {
+        Some(i) => t.buckets.get_mut(&h).unwrap()[i].1 = value,
+        None => {
+            limits::allocate(1)?;
+            t.buckets.entry(h).or_default().push((key, value));
+            t.len += 1;
+        }
+    }
+    Ok(())
+}
+
+pub fn remove(table: &Rc<RefCell<HashTable>>, key: &Value, env: &Rc<RefCell<Environment>>) -> Result<bool> {
+    let (h, pos) = locate(table, key, env)?;
+    let Some(i) = pos else { return Ok(false) };
+    let mut t = table.borrow_mut();
+    let bucket = t.buckets.get_mut(&h).unwrap();
+    bucket.remove(i);
+    if bucket.is_empty() {
+        t.buckets.remove(&h);
+    }
+    t.len -= 1;
+    Ok(true)
+}
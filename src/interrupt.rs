@@ -0,0 +1,59 @@
+use crate::error::{Result, SchemeError};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+// Ctrl-C in the REPL. The SIGINT handler only sets a flag; the evaluator
+// polls it at every step of the trampoline, a read from the terminal polls
+// it while waiting for input (see `wait_for_stdin`), and builtins that wait
+// on a process poll it when they return, failing with
+// `SchemeError::Interrupted` so the REPL gets back to its prompt with the
+// environment intact. Scripts keep the default handler: Ctrl-C ends them.
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+pub fn install_handler() -> std::result::Result<(), ctrlc::Error> {
+    ctrlc::set_handler(|| INTERRUPTED.store(true, Ordering::Relaxed))
+}
+
+// Forgets a Ctrl-C that arrived while nothing was being evaluated.
+pub fn clear() {
+    INTERRUPTED.store(false, Ordering::Relaxed);
+}
+
+// Fails with `Interrupted`, once, if Ctrl-C was pressed since the last check.
+pub fn check() -> Result<()> {
+    if INTERRUPTED.swap(false, Ordering::Relaxed) {
+        return Err(SchemeError::Interrupted);
+    }
+    Ok(())
+}
+
+// How often a wait for terminal input looks at the flag.
+#[cfg(unix)]
+const POLL_INTERVAL_MS: i32 = 50;
+
+// Waits until stdin has input, failing with `Interrupted` if Ctrl-C is
+// pressed first. The handler's SIGINT does not end a blocked read, so the
+// wait polls the descriptor. Only a terminal is waited on: it hands over a
+// line at a time, while from a pipe `io::stdin` may already hold input that
+// polling cannot see.
+#[cfg(unix)]
+pub fn wait_for_stdin() -> Result<()> {
+    use std::io::IsTerminal;
+    if !std::io::stdin().is_terminal() {
+        return Ok(());
+    }
+    let mut stdin = libc::pollfd { fd: libc::STDIN_FILENO, events: libc::POLLIN, revents: 0 };
+    loop {
+        check()?;
+        // SAFETY: `stdin` is a valid pollfd, and the count is 1.
+        let ready = unsafe { libc::poll(&mut stdin, 1, POLL_INTERVAL_MS) };
+        if ready > 0 || (ready < 0 && std::io::Error::last_os_error().kind() != std::io::ErrorKind::Interrupted) {
+            return Ok(()); // Input, end of file or an error, which the read reports
+        }
+    }
+}
+
+#[cfg(not(unix))]
+pub fn wait_for_stdin() -> Result<()> {
+    Ok(())
+}
@@ -0,0 +1,136 @@
+use crate::error::{Result, SchemeError};
+use crate::value::Value;
+use std::cell::Cell;
+use std::time::{Duration, Instant};
+
+// Resource limits on evaluation, for running code that is not trusted:
+// - steps: iterations of the evaluator's trampoline, one per expression
+//   evaluated or procedure called, bounding the total work done;
+// - depth: evaluations nested on the Rust stack (non-tail calls and the
+//   evaluation of arguments and tests). The stack grows as needed (see
+//   `eval`), so this is what stops runaway recursion before it uses up
+//   memory;
+// - elements: collection elements allocated: list, array, map, set and hash
+//   table entries, and string and bytevector bytes;
+// - time: wall-clock time, checked every few hundred steps.
+// Each limit fails with its own `SchemeError`, which `guard` cannot catch.
+// The depth limit is always in force; the others are off unless set with
+// command-line options. The counts start over for each REPL entry, and once
+// for a script.
+
+#[derive(Clone, Copy)]
+pub struct Limits {
+    pub steps: Option<u64>,
+    pub depth: usize,
+    pub elements: Option<u64>,
+    pub time: Option<Duration>,
+}
+
+// Nested evaluations allowed by default: a few hundred megabytes of stack
+// in an optimized build.
+pub const DEFAULT_DEPTH: usize = 100_000;
+
+// How often the clock is read, in steps.
+const CLOCK_INTERVAL: u64 = 256;
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits { steps: None, depth: DEFAULT_DEPTH, elements: None, time: None }
+    }
+}
+
+struct Usage {
+    limits: Cell<Limits>,
+    steps: Cell<u64>,
+    depth: Cell<usize>,
+    elements: Cell<u64>,
+    deadline: Cell<Option<Instant>>,
+}
+
+thread_local! {
+    static USAGE: Usage = Usage {
+        limits: Cell::new(Limits::default()),
+        steps: Cell::new(0),
+        depth: Cell::new(0),
+        elements: Cell::new(0),
+        deadline: Cell::new(None),
+    };
+}
+
+pub fn set(limits: Limits) {
+    USAGE.with(|usage| usage.limits.set(limits));
+}
+
+// Starts counting afresh, from now.
+pub fn start() {
+    USAGE.with(|usage| {
+        usage.steps.set(0);
+        usage.elements.set(0);
+        usage.deadline.set(usage.limits.get().time.map(|time| Instant::now() + time));
+    });
+}
+
+// Counts one evaluation step, failing if the step or time limit is passed.
+pub fn step() -> Result<()> {
+    USAGE.with(|usage| {
+        let steps = usage.steps.get() + 1;
+        usage.steps.set(steps);
+        let limits = usage.limits.get();
+        if let Some(max) = limits.steps.filter(|&max| steps > max) {
+            return Err(SchemeError::StepLimit(max));
+        }
+        if steps % CLOCK_INTERVAL == 0 && usage.deadline.get().is_some_and(|deadline| Instant::now() > deadline) {
+            return Err(SchemeError::TimeLimit(limits.time.unwrap_or_default()));
+        }
+        Ok(())
+    })
+}
+
+// Held while an evaluation is nested on the stack; see `enter`.
+pub struct Nested(());
+
+impl Drop for Nested {
+    fn drop(&mut self) {
+        USAGE.with(|usage| usage.depth.set(usage.depth.get() - 1));
+    }
+}
+
+// Enters a nested evaluation, failing if that would pass the depth limit.
+pub fn enter() -> Result<Nested> {
+    USAGE.with(|usage| {
+        let depth = usage.depth.get() + 1;
+        let max = usage.limits.get().depth;
+        if depth > max {
+            return Err(SchemeError::DepthLimit(max));
+        }
+        usage.depth.set(depth);
+        Ok(Nested(()))
+    })
+}
+
+// Fails if allocating `n` more elements would pass the element limit,
+// without counting them: for checking a size before allocating.
+pub fn reserve(n: usize) -> Result<()> {
+    USAGE.with(|usage| match usage.limits.get().elements {
+        Some(max) if usage.elements.get().saturating_add(n as u64) > max => Err(SchemeError::MemoryLimit(max)),
+        _ => Ok(()),
+    })
+}
+
+// Counts `n` newly allocated elements, failing if they pass the element limit.
+pub fn allocate(n: usize) -> Result<()> {
+    reserve(n)?;
+    USAGE.with(|usage| usage.elements.set(usage.elements.get() + n as u64));
+    Ok(())
+}
+
+// The elements of a list or string, which are copied rather than shared, so
+// a builtin returning one has allocated it. Shared collections (arrays, maps,
+// ...) are counted where they are created and grown instead.
+pub fn copied_elements(value: &Value) -> usize {
+    match value {
+        Value::List(items) => items.len(),
+        Value::String(s) => s.len(),
+        _ => 0,
+    }
+}
@@ -1,77 +1,123 @@
 mod value;
+mod symbol;
+mod printer;
+mod pretty;
+mod format;
+mod port;
 mod error;
 mod parser;
 mod env;
 mod eval;
+mod expand;
+mod analyze;
+mod compile;
+mod vm;
 mod builtins;
+mod hashtable;
+mod mapdata;
+mod repl;
+mod doc;
+mod interrupt;
+mod limits;
+mod stats;
 
-use rustyline::error::ReadlineError;
-use rustyline::history::MemHistory;
-use rustyline::{DefaultEditor, Editor};
 use std::cell::RefCell;
 use std::rc::Rc;
+use std::time::Duration;
 
 use env::Environment;
 use error::Result; // Use our custom Result
 
-fn main() -> Result<()> { // Make main return our Result
-    println!("Rusty Scheme Interpreter");
-    println!("Press Ctrl+C or Ctrl+D to exit");
+#[global_allocator]
+static ALLOCATOR: stats::CountingAllocator = stats::CountingAllocator;
+
+// Runs a script file one top-level form at a time, leaving stdin to the
+// script: `cat data | rust-async filter.scm`. Stops at the first error.
+fn run_file(path: &str, env: Rc<RefCell<Environment>>) -> Result<()> {
+    let source = std::fs::read_to_string(path)
+        .map_err(|e| error::SchemeError::Runtime(format!("cannot read {}: {}", path, e)))?;
+    let mut rest = source.as_str();
+    while let Some((expr, used)) = parser::parse_prefix(rest)? {
+        eval::evaluate(&expr, Rc::clone(&env))?;
+        rest = &rest[used..];
+    }
+    Ok(())
+}
+
+const USAGE: &str = "Usage: rust-async [--vm] [--max-steps N] [--max-depth N] [--max-elements N] [--timeout SECONDS] [script [arg ...]]";
+
+// Reads the options from the front of `args`: the back end, and the
+// resource limits (see `limits`). Returns them and the number of arguments used.
+fn parse_options(args: &[String]) -> std::result::Result<(eval::Backend, limits::Limits, usize), String> {
+    let mut backend = eval::Backend::Tree;
+    let mut limits = limits::Limits::default();
+    let mut used = 0;
+    while let Some(option) = args.get(used).filter(|arg| arg.starts_with("--")) {
+        used += 1;
+        match option.as_str() {
+            "--" => break, // The script name follows, even if it starts with `--`
+            "--vm" => {
+                backend = eval::Backend::Bytecode;
+                continue;
+            }
+            _ => {}
+        }
+        let value = args.get(used).ok_or_else(|| format!("{} needs a value", option))?;
+        used += 1;
+        let count = || value.parse::<u64>().map_err(|_| format!("{} expects a count, got {}", option, value));
+        match option.as_str() {
+            "--max-steps" => limits.steps = Some(count()?),
+            "--max-elements" => limits.elements = Some(count()?),
+            "--max-depth" => limits.depth = count()? as usize,
+            "--timeout" => match value.parse::<f64>() {
+                Ok(seconds) if seconds.is_finite() && seconds >= 0.0 => limits.time = Some(Duration::from_secs_f64(seconds)),
+                _ => return Err(format!("--timeout expects a number of seconds, got {}", value)),
+            },
+            _ => return Err(format!("unknown option {}", option)),
+        }
+    }
+    Ok((backend, limits, used))
+}
+
+// Runs the script named on the command line, or the REPL. Returns the exit status.
+fn run() -> i32 {
+    let argv: Vec<String> = std::env::args().collect();
+    let (backend, limits, used) = match parse_options(&argv[1..]) {
+        Ok(parsed) => parsed,
+        Err(message) => {
+            eprintln!("{}\n{}", message, USAGE);
+            return 2;
+        }
+    };
+    eval::set_backend(backend);
+    limits::set(limits);
+    let script = &argv[1 + used..];
+    builtins::set_command_line(if script.is_empty() { argv[..1].to_vec() } else { script.to_vec() });
 
     // Create top-level environment
     let mut root_env_core = Environment::new();
     builtins::populate_environment(&mut root_env_core);
     let root_env = Rc::new(RefCell::new(root_env_core));
 
-    let mut rl = DefaultEditor::new().expect("nope");
-    // You can load history here if you want:
-    // if rl.load_history("history.txt").is_err() {
-    //     println!("No previous history.");
-    // }
-
-    loop {
-        let readline = rl.readline("λ> "); // Or use "> "
-        match readline {
-            Ok(line) => {
-                if line.trim().is_empty() {
-                    continue; // Skip empty lines
-                }
-                rl.add_history_entry(line.as_str());
-
-                match parser::parse(&line) {
-                    Ok(parsed_expr) => {
-                         // Handle the dummy empty symbol from parser
-                         if let value::Value::Symbol(s) = &parsed_expr {
-                             if s.is_empty() { continue; }
-                         }
-
-                        // Evaluate the parsed expression
-                        match eval::evaluate(&parsed_expr, Rc::clone(&root_env)) {
-                            Ok(result) => println!("{:?}", result), // Use Debug format from value.rs
-                            Err(e) => eprintln!("Error: {}", e),
-                        }
-                    }
-                    Err(e) => eprintln!("Parse Error: {}", e),
-                }
-            }
-            Err(ReadlineError::Interrupted) => {
-                println!("Interrupted (Ctrl+C)");
-                // Optionally break or continue based on preference
-                 break; // Exit on Ctrl+C
+    if let Some(path) = script.first() {
+        limits::start();
+        return match run_file(path, root_env) {
+            Ok(()) => 0,
+            Err(error::SchemeError::Exit(status)) => status,
+            Err(e) => {
+                let _ = port::stdout().borrow_mut().fresh_line();
+                eprintln!("Error: {}", e);
+                1
             }
-            Err(ReadlineError::Eof) => {
-                println!("Exiting (Ctrl+D)");
-                break; // Exit on Ctrl+D
-            }
-            Err(err) => {
-                eprintln!("Readline Error: {:?}", err);
-                break;
-            }
-        }
+        };
     }
 
-    // Save history on exit
-    // rl.save_history("history.txt").unwrap();
+    repl::run(root_env)
+}
 
-    Ok(())
-}
\ No newline at end of file
+fn main() {
+    let status = run();
+    if status != 0 {
+        std::process::exit(status);
+    }
+}
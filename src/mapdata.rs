@@ -0,0 +1,145 @@
+use crate::value::{MapKey, Value};
+use indexmap::IndexMap;
+use std::collections::BTreeMap;
+use std::ops::Bound;
+
+// Storage behind `Value::Map`. Ordinary maps (literals, `make-map`) keep
+// insertion order so iteration and printing are deterministic; sorted maps
+// (`make-sorted-map`) keep their keys in `compare_values` order and answer
+// range queries without a scan.
+#[derive(Clone, Debug)]
+pub enum MapData {
+    Insertion(IndexMap<MapKey, Value>),
+    Sorted(BTreeMap<MapKey, Value>),
+}
+
+impl MapData {
+    pub fn new() -> Self {
+        MapData::Insertion(IndexMap::new())
+    }
+
+    pub fn new_sorted() -> Self {
+        MapData::Sorted(BTreeMap::new())
+    }
+
+    // An empty map of the same kind, for builtins that derive a new map from an old one.
+    pub fn empty_like(&self) -> Self {
+        match self {
+            MapData::Insertion(_) => MapData::new(),
+            MapData::Sorted(_) => MapData::new_sorted(),
+        }
+    }
+
+    pub fn is_sorted(&self) -> bool {
+        matches!(self, MapData::Sorted(_))
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            MapData::Insertion(m) => m.len(),
+            MapData::Sorted(m) => m.len(),
+        }
+    }
+
+    pub fn get(&self, key: &MapKey) -> Option<&Value> {
+        match self {
+            MapData::Insertion(m) => m.get(key),
+            MapData::Sorted(m) => m.get(key),
+        }
+    }
+
+    pub fn contains_key(&self, key: &MapKey) -> bool {
+        self.get(key).is_some()
+    }
+
+    // Updating an existing key keeps its original position.
+    pub fn insert(&mut self, key: MapKey, value: Value) -> Option<Value> {
+        match self {
+            MapData::Insertion(m) => m.insert(key, value),
+            MapData::Sorted(m) => m.insert(key, value),
+        }
+    }
+
+    // Removal preserves the order of the remaining entries.
+    pub fn remove(&mut self, key: &MapKey) -> Option<Value> {
+        match self {
+            MapData::Insertion(m) => m.shift_remove(key),
+            MapData::Sorted(m) => m.remove(key),
+        }
+    }
+
+    pub fn iter(&self) -> Box<dyn Iterator<Item = (&MapKey, &Value)> + '_> {
+        match self {
+            MapData::Insertion(m) => Box::new(m.iter()),
+            MapData::Sorted(m) => Box::new(m.iter()),
+        }
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &MapKey> {
+        self.iter().map(|(k, _)| k)
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &Value> {
+        self.iter().map(|(_, v)| v)
+    }
+
+    // Entries with `lo <= key < hi` in key order; a missing bound is unbounded.
+    pub fn range(&self, lo: Option<&MapKey>, hi: Option<&MapKey>) -> Vec<(MapKey, Value)> {
+        if let (Some(lo), Some(hi)) = (lo, hi)
+            && lo > hi
+        {
+            return Vec::new();
+        }
+        let lower = lo.map_or(Bound::Unbounded, Bound::Included);
+        let upper = hi.map_or(Bound::Unbounded, Bound::Excluded);
+        match self {
+            MapData::Sorted(m) => m.range::<MapKey, _>((lower, upper)).map(|(k, v)| (k.clone(), v.clone())).collect(),
+            MapData::Insertion(m) => {
+                let mut entries: Vec<(MapKey, Value)> = m
+                    .iter()
+                    .filter(|(k, _)| lo.is_none_or(|lo| *k >= lo) && hi.is_none_or(|hi| *k < hi))
+                    .map(|(k, v)| (k.clone(), v.clone()))
+                    .collect();
+                entries.sort_by(|a, b| a.0.cmp(&b.0));
+                entries
+            }
+        }
+    }
+
+    // Entry with the smallest key.
+    pub fn min(&self) -> Option<(&MapKey, &Value)> {
+        match self {
+            MapData::Sorted(m) => m.first_key_value(),
+            MapData::Insertion(m) => m.iter().min_by(|a, b| a.0.cmp(b.0)),
+        }
+    }
+
+    // Entry with the largest key.
+    pub fn max(&self) -> Option<(&MapKey, &Value)> {
+        match self {
+            MapData::Sorted(m) => m.last_key_value(),
+            MapData::Insertion(m) => m.iter().max_by(|a, b| a.0.cmp(b.0)),
+        }
+    }
+}
+
+impl Default for MapData {
+    fn default() -> Self {
+        MapData::new()
+    }
+}
+
+// Maps are equal when they hold the same entries, regardless of order or kind.
+impl PartialEq for MapData {
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len() && self.iter().all(|(k, v)| other.get(k) == Some(v))
+    }
+}
+
+impl Extend<(MapKey, Value)> for MapData {
+    fn extend<T: IntoIterator<Item = (MapKey, Value)>>(&mut self, iter: T) {
+        for (k, v) in iter {
+            self.insert(k, v);
+        }
+    }
+}
@@ -1,10 +1,10 @@
-use crate::value::Value;
+use crate::mapdata::MapData;
+use crate::value::{Value, MapKey, SetData, CHAR_NAMES};
 use crate::error::{Result, SchemeError};
+use crate::eval::grow_stack;
 use std::cell::RefCell;
 use std::collections::HashMap;
-use std::iter::Peekable;
 use std::rc::Rc;
-use std::str::Chars;
 
 enum Token {
     LParen,     // (
@@ -12,97 +12,193 @@ enum Token {
     LBracket,   // [
     RBracket,   // ]
     LBrace,     // {
+    SetOpen,    // #{
+    BytevectorOpen, // #u8(
     RBrace,     // }
     Quote,      // '
     Symbol(String),
     Integer(i64),
     Bool(bool),
+    Char(char),
     String(String),
     Colon,      // :
     Comma,      // ,
     Dot,        // . (Currently unused, could be for improper lists later)
+    LabelDef(usize), // #0=
+    LabelRef(usize), // #0#
 }
 
-// Very basic tokenizer
-fn tokenize(input: &str) -> Result<Vec<Token>> {
-    let mut tokens = Vec::new();
-    let mut chars = input.chars().peekable();
+// Datum labels seen so far in the expression being read, by number.
+type Labels = HashMap<usize, Value>;
 
-    while let Some(&c) = chars.peek() {
+// A char iterator over a string that knows how much of it is left, so the
+// lexer can report how far into its input a datum ended.
+#[derive(Clone)]
+struct Scanner<'a> {
+    rest: &'a str, // Unconsumed input, starting with `next`
+    next: Option<char>,
+}
+
+impl<'a> Scanner<'a> {
+    fn new(input: &'a str) -> Self {
+        Scanner { rest: input, next: input.chars().next() }
+    }
+
+    fn peek(&self) -> Option<&char> {
+        self.next.as_ref()
+    }
+}
+
+impl Iterator for Scanner<'_> {
+    type Item = char;
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.next?;
+        self.rest = &self.rest[c.len_utf8()..];
+        self.next = self.rest.chars().next();
+        Some(c)
+    }
+}
+
+// Reads the rest of a `\` escape in a string or `|symbol|`.
+fn read_escape(chars: &mut Scanner) -> Result<char> {
+    let escaped_c = chars.next().ok_or_else(|| SchemeError::Incomplete("Unterminated string literal after escape".to_string()))?;
+    match escaped_c {
+        'n' => Ok('\n'),
+        't' => Ok('\t'),
+        'r' => Ok('\r'),
+        'a' => Ok('\u{7}'),
+        'b' => Ok('\u{8}'),
+        '\\' | '"' | '|' => Ok(escaped_c),
+        'x' => { // \x3bb; -- hex code point ending in a semicolon
+            let mut hex = String::new();
+            for c in chars.by_ref() {
+                if c == ';' {
+                    return u32::from_str_radix(&hex, 16)
+                        .ok()
+                        .and_then(char::from_u32)
+                        .ok_or_else(|| SchemeError::Parser(format!("Invalid escape sequence: \\x{};", hex)));
+                }
+                hex.push(c);
+            }
+            Err(SchemeError::Incomplete(format!("Unterminated escape sequence: \\x{}", hex)))
+        }
+        _ => Err(SchemeError::Parser(format!("Invalid escape sequence: \\{}", escaped_c))),
+    }
+}
+
+// Reads the next token, skipping whitespace and comments; None at end of input.
+fn next_token(chars: &mut Scanner) -> Result<Option<Token>> {
+    let mut token = None;
+    while token.is_none() {
+        let Some(&c) = chars.peek() else { break };
         match c {
-            '(' => { tokens.push(Token::LParen); chars.next(); }
-            ')' => { tokens.push(Token::RParen); chars.next(); }
-            '[' => { tokens.push(Token::LBracket); chars.next(); }
-            ']' => { tokens.push(Token::RBracket); chars.next(); }
-            '{' => { tokens.push(Token::LBrace); chars.next(); }
-            '}' => { tokens.push(Token::RBrace); chars.next(); }
-            '\'' => { tokens.push(Token::Quote); chars.next(); }
-            ':' => { tokens.push(Token::Colon); chars.next(); }
-            ',' => { tokens.push(Token::Comma); chars.next(); }
-            '.' => { tokens.push(Token::Dot); chars.next(); } // Keep for potential future use
+            '(' => { token = Some(Token::LParen); chars.next(); }
+            ')' => { token = Some(Token::RParen); chars.next(); }
+            '[' => { token = Some(Token::LBracket); chars.next(); }
+            ']' => { token = Some(Token::RBracket); chars.next(); }
+            '{' => { token = Some(Token::LBrace); chars.next(); }
+            '}' => { token = Some(Token::RBrace); chars.next(); }
+            '\'' => { token = Some(Token::Quote); chars.next(); }
+            ':' => { token = Some(Token::Colon); chars.next(); }
+            ',' => { token = Some(Token::Comma); chars.next(); }
+            '.' => { token = Some(Token::Dot); chars.next(); } // Keep for potential future use
             '"' => { // String literal
                 chars.next(); // Consume "
                 let mut s = String::new();
-                while let Some(&next_c) = chars.peek() {
-                     if next_c == '"' {
-                        chars.next(); // Consume "
-                        break;
-                    } else if next_c == '\\' { // Handle basic escape
-                        chars.next(); // consume \
-                        if let Some(escaped_c) = chars.next() {
-                             match escaped_c {
-                                'n' => s.push('\n'),
-                                't' => s.push('\t'),
-                                '\\' => s.push('\\'),
-                                '"' => s.push('"'),
-                                _ => return Err(SchemeError::Parser(format!("Invalid escape sequence: \\{}", escaped_c))),
-                            }
-                        } else {
-                             return Err(SchemeError::Parser("Unterminated string literal after escape".to_string()));
-                        }
-                    }
-                    else {
-                        s.push(next_c);
-                        chars.next();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => s.push(read_escape(chars)?),
+                        Some(next_c) => s.push(next_c),
+                        None => return Err(SchemeError::Incomplete("Unterminated string literal".to_string())),
                     }
                 }
-                 // Check if string was terminated
-                if chars.peek().is_none() && !input.ends_with('"') {
-                    // This check is tricky with escapes, refine if needed
-                     // return Err(SchemeError::Parser("Unterminated string literal".to_string()));
+                token = Some(Token::String(s));
+            }
+            '|' => { // Quoted symbol: |hello world|, with the same escapes as strings
+                chars.next(); // Consume |
+                let mut sym = String::new();
+                loop {
+                    match chars.next() {
+                        Some('|') => break,
+                        Some('\\') => sym.push(read_escape(chars)?),
+                        Some(next_c) => sym.push(next_c),
+                        None => return Err(SchemeError::Incomplete("Unterminated |symbol|".to_string())),
+                    }
                 }
-                tokens.push(Token::String(s));
+                token = Some(Token::Symbol(sym));
             }
             c if c.is_whitespace() => { chars.next(); } // Skip whitespace
-            c if c.is_digit(10) || (c == '-' && chars.clone().nth(1).map_or(false, |nc| nc.is_digit(10))) => { // Integer
+            c if c.is_ascii_digit() || (c == '-' && chars.clone().nth(1).is_some_and(|nc| nc.is_ascii_digit())) => { // Integer
                 let mut num_str = String::new();
                 if c == '-' {
                     num_str.push(chars.next().unwrap());
                 }
                 while let Some(&next_c) = chars.peek() {
-                    if next_c.is_digit(10) {
+                    if next_c.is_ascii_digit() {
                         num_str.push(chars.next().unwrap());
                     } else {
                         break;
                     }
                 }
                 match num_str.parse::<i64>() {
-                    Ok(n) => tokens.push(Token::Integer(n)),
+                    Ok(n) => token = Some(Token::Integer(n)),
                     Err(_) => return Err(SchemeError::Parser(format!("Invalid integer literal: {}", num_str))),
                 }
             }
             ';' => { // Comment: skip till end of line
-                 while let Some(next_c) = chars.next() {
+                 for next_c in chars.by_ref() {
                     if next_c == '\n' { break; }
                 }
             }
-            '#' => { // Booleans (#t, #f)
+            '#' => { // Booleans (#t, #f), characters (#\a, #\space), set literals (#{...}), bytevectors (#u8(...)) and datum labels (#0=, #0#)
                 chars.next(); // Consume #
                 match chars.next() {
-                    Some('t') => tokens.push(Token::Bool(true)),
-                    Some('f') => tokens.push(Token::Bool(false)),
+                    Some(d) if d.is_ascii_digit() => {
+                        let mut digits = d.to_string();
+                        while let Some(&next_c) = chars.peek().filter(|c| c.is_ascii_digit()) {
+                            digits.push(next_c);
+                            chars.next();
+                        }
+                        let n = digits.parse::<usize>().map_err(|_| SchemeError::Parser(format!("Invalid datum label: #{}", digits)))?;
+                        match chars.next() {
+                            Some('=') => token = Some(Token::LabelDef(n)),
+                            Some('#') => token = Some(Token::LabelRef(n)),
+                            None => return Err(SchemeError::Incomplete(format!("Incomplete datum label: #{}", digits))),
+                            _ => return Err(SchemeError::Parser(format!("Expected '=' or '#' after datum label #{}", digits))),
+                        }
+                    }
+                    Some('{') => token = Some(Token::SetOpen),
+                    Some('u') => match (chars.next(), chars.next()) {
+                        (Some('8'), Some('(')) => token = Some(Token::BytevectorOpen),
+                        (_, None) => return Err(SchemeError::Incomplete("Incomplete bytevector literal: #u8(".to_string())),
+                        _ => return Err(SchemeError::Parser("Expected '#u8(' to start a bytevector".to_string())),
+                    },
+                    Some('t') => token = Some(Token::Bool(true)),
+                    Some('f') => token = Some(Token::Bool(false)),
+                    Some('\\') => {
+                        let first = chars.next().ok_or_else(|| SchemeError::Incomplete("Incomplete character literal: #\\".to_string()))?;
+                        let mut name = first.to_string();
+                        while let Some(&next_c) = chars.peek() {
+                            if next_c.is_whitespace() || "()[]{}:,'\"".contains(next_c) {
+                                break;
+                            }
+                            name.push(chars.next().unwrap());
+                        }
+                        let c = if name.chars().count() == 1 {
+                            first
+                        } else if let Some(&(_, c)) = CHAR_NAMES.iter().find(|(n, _)| *n == name) {
+                            c
+                        } else if let Some(code) = name.strip_prefix('x').and_then(|hex| u32::from_str_radix(hex, 16).ok()) {
+                            char::from_u32(code).ok_or_else(|| SchemeError::Parser(format!("Invalid character code: #\\{}", name)))?
+                        } else {
+                            return Err(SchemeError::Parser(format!("Unknown character name: #\\{}", name)));
+                        };
+                        token = Some(Token::Char(c));
+                    }
                     Some(other) => return Err(SchemeError::Parser(format!("Invalid boolean literal: #{}", other))),
-                    None => return Err(SchemeError::Parser("Incomplete boolean literal: #".to_string())),
+                    None => return Err(SchemeError::Incomplete("Incomplete literal: #".to_string())),
                 }
             }
             _ => { // Symbol
@@ -114,7 +210,7 @@ fn tokenize(input: &str) -> Result<Vec<Token>> {
                     sym.push(chars.next().unwrap());
                 }
                 if !sym.is_empty() {
-                    tokens.push(Token::Symbol(sym));
+                    token = Some(Token::Symbol(sym));
                 } else {
                     // This case should ideally not be reached if input is valid
                     chars.next(); // Consume the unexpected character to avoid infinite loop
@@ -123,23 +219,40 @@ fn tokenize(input: &str) -> Result<Vec<Token>> {
             }
         }
     }
-    Ok(tokens)
+    Ok(token)
 }
 
 
-fn parse_expr<'a, I>(tokens: &mut Peekable<I>) -> Result<Value>
-where
-    I: Iterator<Item = &'a Token>,
-{
-    let token = tokens.next().ok_or_else(|| SchemeError::Parser("Unexpected end of input".to_string()))?;
+fn parse_expr(tokens: &mut Lexer, labels: &mut Labels) -> Result<Value> {
+    grow_stack(|| parse_datum(tokens, labels))
+}
 
-    match token {
-        Token::LParen => parse_list(tokens),
-        Token::LBracket => parse_array(tokens),
-        Token::LBrace => parse_map(tokens),
+fn parse_datum(tokens: &mut Lexer, labels: &mut Labels) -> Result<Value> {
+    let token = tokens.next()?.ok_or_else(|| SchemeError::Incomplete("Unexpected end of input".to_string()))?;
+
+    match &token {
+        Token::LParen => parse_list(tokens, labels),
+        Token::LBracket => parse_array(tokens, labels, None),
+        Token::LBrace => parse_map(tokens, labels, None),
+        Token::SetOpen => parse_set(tokens, labels, None),
+        Token::BytevectorOpen => parse_bytevector(tokens),
+        Token::LabelDef(n) => {
+            // Arrays, maps and sets are registered before their contents are
+            // read, so the contents may refer back to them. Other data can
+            // only be shared once complete.
+            let value = match tokens.peek()? {
+                Some(Token::LBracket) => { tokens.next()?; parse_array(tokens, labels, Some(*n))? }
+                Some(Token::LBrace) => { tokens.next()?; parse_map(tokens, labels, Some(*n))? }
+                Some(Token::SetOpen) => { tokens.next()?; parse_set(tokens, labels, Some(*n))? }
+                _ => parse_expr(tokens, labels)?,
+            };
+            labels.insert(*n, value.clone());
+            Ok(value)
+        }
+        Token::LabelRef(n) => labels.get(n).cloned().ok_or_else(|| SchemeError::Parser(format!("Undefined datum label: #{}#", n))),
         Token::Quote => {
-            let expr = parse_expr(tokens)?;
-            Ok(Value::List(vec![Value::Symbol("quote".to_string()), expr]))
+            let expr = parse_expr(tokens, labels)?;
+            Ok(Value::List(vec![Value::symbol("quote"), expr]))
         }
         Token::RParen => Err(SchemeError::Parser("Unexpected ')'".to_string())),
         Token::RBracket => Err(SchemeError::Parser("Unexpected ']'".to_string())),
@@ -147,62 +260,79 @@ where
         Token::Colon => Err(SchemeError::Parser("Unexpected ':'".to_string())),
         Token::Comma => Err(SchemeError::Parser("Unexpected ','".to_string())),
         Token::Dot => Err(SchemeError::Parser("Unexpected '.'".to_string())), // Handle later if needed
-        Token::Symbol(s) => Ok(Value::Symbol(s.clone())),
+        Token::Symbol(s) => Ok(Value::symbol(s)),
         Token::Integer(n) => Ok(Value::Integer(*n)),
         Token::Bool(b) => Ok(Value::Bool(*b)),
+        Token::Char(c) => Ok(Value::Char(*c)),
         Token::String(s) => Ok(Value::String(s.clone())),
     }
 }
 
-fn parse_list<'a, I>(tokens: &mut Peekable<I>) -> Result<Value>
-where
-    I: Iterator<Item = &'a Token>,
-{
+// Bytevector elements are integer literals from 0 to 255, separated by whitespace.
+fn parse_bytevector(tokens: &mut Lexer) -> Result<Value> {
+    let mut bytes = Vec::new();
+    while let Some(token) = tokens.next()? {
+        match token {
+            Token::RParen => return Ok(Value::Bytevector(Rc::new(RefCell::new(bytes)))),
+            Token::Integer(n) => {
+                let byte = u8::try_from(n).map_err(|_| SchemeError::Parser(format!("Bytevector element out of range: {}", n)))?;
+                bytes.push(byte);
+            }
+            _ => return Err(SchemeError::Parser("Bytevector elements must be integers from 0 to 255".to_string())),
+        }
+    }
+    Err(SchemeError::Incomplete("Unmatched '#u8('".to_string()))
+}
+
+fn parse_list(tokens: &mut Lexer, labels: &mut Labels) -> Result<Value> {
     let mut list = Vec::new();
-    while let Some(token) = tokens.peek() {
+    while let Some(token) = tokens.peek()? {
         match token {
             Token::RParen => {
-                tokens.next(); // Consume ')'
+                tokens.next()?; // Consume ')'
                 return Ok(Value::List(list));
             }
             _ => {
-                let expr = parse_expr(tokens)?;
+                let expr = parse_expr(tokens, labels)?;
                 list.push(expr);
             }
         }
     }
-    Err(SchemeError::Parser("Unmatched '('".to_string()))
+    Err(SchemeError::Incomplete("Unmatched '('".to_string()))
 }
 
 
-fn parse_array<'a, I>(tokens: &mut Peekable<I>) -> Result<Value>
-where
-    I: Iterator<Item = &'a Token>,
-{
+fn parse_array(tokens: &mut Lexer, labels: &mut Labels, label: Option<usize>) -> Result<Value> {
     let mut arr = Vec::new();
     let mut expect_comma = false;
+    let target = Rc::new(RefCell::new(Vec::new()));
+    if let Some(n) = label {
+        labels.insert(n, Value::Array(Rc::clone(&target)));
+    }
 
     // Handle empty array []
-    if let Some(Token::RBracket) = tokens.peek() {
-        tokens.next(); // Consume ']'
-        return Ok(Value::Array(Rc::new(RefCell::new(arr))));
+    if let Some(Token::RBracket) = tokens.peek()? {
+        tokens.next()?; // Consume ']'
+        *target.borrow_mut() = arr;
+        return Ok(Value::Array(target));
     }
 
 
-    while let Some(token) = tokens.peek() {
+    while let Some(token) = tokens.peek()? {
          match token {
             Token::RBracket => {
-                tokens.next(); // Consume ']'
-                return Ok(Value::Array(Rc::new(RefCell::new(arr))));
+                tokens.next()?; // Consume ']'
+                *target.borrow_mut() = arr;
+                return Ok(Value::Array(target));
             }
              Token::Comma => {
                 if !expect_comma {
                      return Err(SchemeError::Parser("Unexpected comma in array literal".to_string()));
                 }
-                 tokens.next(); // Consume ','
+                 tokens.next()?; // Consume ','
                 expect_comma = false;
                 // Allow trailing comma
-                if let Some(Token::RBracket) = tokens.peek() {
+                if let Some(Token::RBracket) = tokens.peek()? {
                     continue;
                 }
             }
@@ -210,32 +340,34 @@ where
                  if expect_comma {
                      return Err(SchemeError::Parser("Expected comma or ']' in array literal".to_string()));
                  }
-                let expr = parse_expr(tokens)?;
+                let expr = parse_expr(tokens, labels)?;
                 arr.push(expr);
                 expect_comma = true;
             }
         }
     }
-     Err(SchemeError::Parser("Unmatched '['".to_string()))
+     Err(SchemeError::Incomplete("Unmatched '['".to_string()))
 }
 
 
-fn parse_map<'a, I>(tokens: &mut Peekable<I>) -> Result<Value>
-where
-    I: Iterator<Item = &'a Token>,
-{
-    let mut map = HashMap::new();
+fn parse_map(tokens: &mut Lexer, labels: &mut Labels, label: Option<usize>) -> Result<Value> {
+    let mut map = MapData::new();
     let mut expect_comma = false; // Expect comma between pairs
     let mut expect_value = false; // Expect value after colon
-    let mut current_key: Option<String> = None;
+    let mut current_key: Option<Value> = None;
+    let target = Rc::new(RefCell::new(MapData::new()));
+    if let Some(n) = label {
+        labels.insert(n, Value::Map(Rc::clone(&target)));
+    }
 
      // Handle empty map {}
-    if let Some(Token::RBrace) = tokens.peek() {
-        tokens.next(); // Consume '}'
-        return Ok(Value::Map(Rc::new(RefCell::new(map))));
+    if let Some(Token::RBrace) = tokens.peek()? {
+        tokens.next()?; // Consume '}'
+        *target.borrow_mut() = map;
+        return Ok(Value::Map(target));
     }
 
-    while let Some(token) = tokens.peek() {
+    while let Some(token) = tokens.peek()? {
         match token {
             Token::RBrace => {
                 if expect_value {
@@ -244,8 +376,9 @@ where
                  if current_key.is_some() {
                      return Err(SchemeError::Parser("Expected ':' and value before '}' in map literal".to_string()));
                  }
-                tokens.next(); // Consume '}'
-                return Ok(Value::Map(Rc::new(RefCell::new(map))));
+                tokens.next()?; // Consume '}'
+                *target.borrow_mut() = map;
+                return Ok(Value::Map(target));
             }
             Token::Comma => {
                  if !expect_comma {
@@ -254,10 +387,10 @@ where
                  if expect_value || current_key.is_some() {
                       return Err(SchemeError::Parser("Unexpected comma after key or colon in map literal".to_string()));
                  }
-                tokens.next(); // Consume ','
+                tokens.next()?; // Consume ','
                 expect_comma = false;
                  // Allow trailing comma
-                if let Some(Token::RBrace) = tokens.peek() {
+                if let Some(Token::RBrace) = tokens.peek()? {
                     continue;
                 }
             }
@@ -265,64 +398,100 @@ where
                 if current_key.is_none() || expect_value {
                      return Err(SchemeError::Parser("Unexpected colon in map literal".to_string()));
                  }
-                tokens.next(); // Consume ':'
+                tokens.next()?; // Consume ':'
                 expect_value = true;
             }
-             Token::Symbol(key_str) => {
-                if expect_value { // Parsing the value part
-                    let value_expr = parse_expr(tokens)?;
-                     let key = current_key.take().unwrap(); // Should be Some if expect_value is true
-                    map.insert(key, value_expr);
+            _ => { // Any other token starts either a key or a value
+                 if expect_value { // Parsing the value part
+                    let value_expr = parse_expr(tokens, labels)?;
+                    let key = current_key.take().unwrap(); // Should be Some if expect_value is true
+                    map.insert(MapKey(key), value_expr);
                     expect_value = false;
                     expect_comma = true; // Expect comma after value (or closing brace)
-                } else if current_key.is_some() {
-                    return Err(SchemeError::Parser("Expected ':' after map key".to_string()));
-                 }
-                else { // Parsing the key part
+                 } else if let Some(key) = current_key {
+                     return Err(SchemeError::Parser(format!("Expected ':' after map key '{:?}'", key)));
+                 } else { // Parsing the key part: bare symbols, strings, numbers, chars, ...
                      if expect_comma {
                           return Err(SchemeError::Parser("Expected comma before next key in map literal".to_string()));
                      }
-                    current_key = Some(key_str.clone());
-                    tokens.next(); // Consume symbol token
-                }
+                    current_key = Some(parse_expr(tokens, labels)?);
+                 }
             }
-             // Allow string keys? For now, stick to symbols as keys like JS allows unquoted keys
-            // Token::String(key_str) => { ... }
+        }
+    }
 
-            _ => { // Any other token is either a value or an error
-                 if expect_value { // Parsing the value part
-                    let value_expr = parse_expr(tokens)?;
-                    let key = current_key.take().unwrap();
-                    map.insert(key, value_expr);
-                    expect_value = false;
-                    expect_comma = true;
-                 } else if current_key.is_some() {
-                     return Err(SchemeError::Parser(format!("Expected ':' after map key '{}'", current_key.unwrap())));
-                 } else {
-                     return Err(SchemeError::Parser(format!("Unexpected token {:?} in map literal; expected key (symbol)", "token")));
-                 }
+    Err(SchemeError::Incomplete("Unmatched '{'".to_string()))
+}
+
+
+// Set literal: #{1 2 3}. Elements are data like map values; commas between them are optional.
+fn parse_set(tokens: &mut Lexer, labels: &mut Labels, label: Option<usize>) -> Result<Value> {
+    let mut set = SetData::new();
+    let target = Rc::new(RefCell::new(SetData::new()));
+    if let Some(n) = label {
+        labels.insert(n, Value::Set(Rc::clone(&target)));
+    }
+    while let Some(token) = tokens.peek()? {
+        match token {
+            Token::RBrace => {
+                tokens.next()?; // Consume '}'
+                *target.borrow_mut() = set;
+                return Ok(Value::Set(target));
+            }
+            Token::Comma => {
+                tokens.next()?; // Optional separator
+            }
+            _ => {
+                set.insert(MapKey(parse_expr(tokens, labels)?));
             }
         }
     }
+    Err(SchemeError::Incomplete("Unmatched '#{'".to_string()))
+}
 
-    Err(SchemeError::Parser("Unmatched '{'".to_string()))
+
+// The parser's token stream, with one token of lookahead.
+struct Lexer<'a> {
+    input: &'a str,
+    chars: Scanner<'a>,
+    peeked: Option<Token>,
 }
 
+impl<'a> Lexer<'a> {
+    fn new(input: &'a str) -> Self {
+        Lexer { input, chars: Scanner::new(input), peeked: None }
+    }
+
+    fn peek(&mut self) -> Result<Option<&Token>> {
+        if self.peeked.is_none() {
+            self.peeked = next_token(&mut self.chars)?;
+        }
+        Ok(self.peeked.as_ref())
+    }
 
-pub fn parse(input: &str) -> Result<Value> {
-    let tokens = tokenize(input)?;
-    if tokens.is_empty() {
-        // Special case for empty input or only whitespace/comments
-        return Ok(Value::Symbol("".to_string())); // Return an inert value or a specific marker?
-                                                  // Let's use an empty symbol for now, eval can ignore it.
+    fn next(&mut self) -> Result<Option<Token>> {
+        match self.peeked.take() {
+            Some(token) => Ok(Some(token)),
+            None => next_token(&mut self.chars),
+        }
     }
-    let mut token_iter = tokens.iter().peekable();
-    let result = parse_expr(&mut token_iter)?;
-
-    // Ensure all tokens were consumed
-    if token_iter.peek().is_some() {
-        Err(SchemeError::Parser("Unexpected tokens after expression".to_string()))
-    } else {
-        Ok(result)
+
+    // Bytes of input read so far, including any peeked token.
+    fn offset(&self) -> usize {
+        self.input.len() - self.chars.rest.len()
     }
-}
\ No newline at end of file
+}
+
+// Reads the first datum in `input`, returning it with the number of bytes it
+// took up (including leading whitespace and comments), or None if there are
+// only whitespace and comments. The REPL and script files are read one
+// datum at a time with it, and `read` uses it on the unread text of a port.
+// Input that stops partway through a datum gives `SchemeError::Incomplete`.
+pub fn parse_prefix(input: &str) -> Result<Option<(Value, usize)>> {
+    let mut tokens = Lexer::new(input);
+    if tokens.peek()?.is_none() {
+        return Ok(None);
+    }
+    let value = parse_expr(&mut tokens, &mut Labels::new())?;
+    Ok(Some((value, tokens.offset())))
+}
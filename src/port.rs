@@ -0,0 +1,391 @@
+use crate::error::{Result, SchemeError};
+use crate::interrupt;
+use crate::parser;
+use crate::value::Value;
+use std::cell::RefCell;
+use std::fs::File;
+use std::io::{self, BufRead, BufWriter, Write};
+use std::rc::Rc;
+
+// Ports behind `Value::Port`. Input ports hold their text in memory and a
+// read position: string and file ports get all of it when opened, and the
+// stdin port reads another line each time its buffered one is used up.
+// Output ports write to a string buffer, a file, stdout or stderr.
+//
+// Binary ports carry bytes instead of chars: bytevector and binary file
+// input ports hold all their bytes, and binary output ports write to a byte
+// buffer or a file. The textual procedures refuse binary ports and the
+// binary ones (`read-u8`, `write-bytevector`, ...) refuse textual ports.
+//
+// The current input, output and error ports are per-thread and start out as
+// stdin, stdout and stderr; `with-output-to-string` swaps the output port for
+// the duration of a call.
+
+enum Kind {
+    Input { text: String, pos: usize, stdin: bool },
+    BinaryInput { bytes: Vec<u8>, pos: usize },
+    Output(Sink),
+}
+
+enum Sink {
+    String(String),
+    Bytes(Vec<u8>),
+    File(BufWriter<File>), // Textual or binary, as the port says
+    Stdout,
+    Stderr,
+}
+
+pub struct Port {
+    name: String, // "string", "stdin", or a file path; shown when printed
+    kind: Kind,
+    open: bool,
+    binary: bool,
+    at_line_start: bool, // Output ports: whether the last char written was a newline
+}
+
+pub type PortRef = Rc<RefCell<Port>>;
+
+impl Port {
+    fn new(name: &str, kind: Kind) -> PortRef {
+        let binary = matches!(kind, Kind::BinaryInput { .. } | Kind::Output(Sink::Bytes(_)));
+        Rc::new(RefCell::new(Port { name: name.to_string(), kind, open: true, binary, at_line_start: true }))
+    }
+
+    pub fn input_string(text: &str) -> PortRef {
+        Port::new("string", Kind::Input { text: text.to_string(), pos: 0, stdin: false })
+    }
+
+    pub fn output_string() -> PortRef {
+        Port::new("string", Kind::Output(Sink::String(String::new())))
+    }
+
+    pub fn open_input_file(path: &str) -> io::Result<PortRef> {
+        let text = std::fs::read_to_string(path)?;
+        Ok(Port::new(path, Kind::Input { text, pos: 0, stdin: false }))
+    }
+
+    pub fn open_output_file(path: &str) -> io::Result<PortRef> {
+        let file = File::create(path)?;
+        Ok(Port::new(path, Kind::Output(Sink::File(BufWriter::new(file)))))
+    }
+
+    pub fn input_bytevector(bytes: &[u8]) -> PortRef {
+        Port::new("bytevector", Kind::BinaryInput { bytes: bytes.to_vec(), pos: 0 })
+    }
+
+    pub fn output_bytevector() -> PortRef {
+        Port::new("bytevector", Kind::Output(Sink::Bytes(Vec::new())))
+    }
+
+    pub fn open_binary_input_file(path: &str) -> io::Result<PortRef> {
+        let bytes = std::fs::read(path)?;
+        Ok(Port::new(path, Kind::BinaryInput { bytes, pos: 0 }))
+    }
+
+    pub fn open_binary_output_file(path: &str) -> io::Result<PortRef> {
+        let port = Port::open_output_file(path)?;
+        port.borrow_mut().binary = true;
+        Ok(port)
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn is_input(&self) -> bool {
+        matches!(self.kind, Kind::Input { .. } | Kind::BinaryInput { .. })
+    }
+
+    pub fn is_binary(&self) -> bool {
+        self.binary
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.open
+    }
+
+    fn closed_error(&self) -> SchemeError {
+        SchemeError::Runtime(format!("port is closed: {}", self.name))
+    }
+
+    pub fn write_str(&mut self, s: &str) -> Result<()> {
+        if !self.open {
+            return Err(self.closed_error());
+        }
+        if self.binary {
+            return Err(SchemeError::Runtime(format!("not a textual port: {}", self.name)));
+        }
+        let written = match &mut self.kind {
+            Kind::Input { .. } | Kind::BinaryInput { .. } => return Err(SchemeError::Runtime(format!("not an output port: {}", self.name))),
+            Kind::Output(Sink::String(buf)) => {
+                buf.push_str(s);
+                Ok(())
+            }
+            Kind::Output(Sink::Bytes(_)) => unreachable!("byte sinks belong to binary ports"),
+            Kind::Output(Sink::File(file)) => file.write_all(s.as_bytes()),
+            // Flushed on every write so output shows up before the next prompt
+            Kind::Output(Sink::Stdout) => {
+                let mut out = io::stdout().lock();
+                out.write_all(s.as_bytes()).and_then(|_| out.flush())
+            }
+            Kind::Output(Sink::Stderr) => io::stderr().write_all(s.as_bytes()),
+        };
+        written.map_err(|e| SchemeError::io("write", &self.name, &e))?;
+        if let Some(last) = s.chars().last() {
+            self.at_line_start = last == '\n';
+        }
+        Ok(())
+    }
+
+    pub fn write_bytes(&mut self, bytes: &[u8]) -> Result<()> {
+        if !self.open {
+            return Err(self.closed_error());
+        }
+        if !self.binary {
+            return Err(SchemeError::Runtime(format!("not a binary port: {}", self.name)));
+        }
+        match &mut self.kind {
+            Kind::Output(Sink::Bytes(buf)) => {
+                buf.extend_from_slice(bytes);
+                Ok(())
+            }
+            Kind::Output(Sink::File(file)) => file.write_all(bytes).map_err(|e| SchemeError::io("write", &self.name, &e)),
+            _ => Err(SchemeError::Runtime(format!("not an output port: {}", self.name))),
+        }
+    }
+
+    // Starts a new line unless the output is already at the start of one.
+    pub fn fresh_line(&mut self) -> Result<()> {
+        if self.at_line_start { Ok(()) } else { self.write_str("\n") }
+    }
+
+    // Everything written so far to a string output port.
+    pub fn output_string_contents(&self) -> Option<&str> {
+        match &self.kind {
+            Kind::Output(Sink::String(buf)) => Some(buf),
+            _ => None,
+        }
+    }
+
+    // Everything written so far to a bytevector output port.
+    pub fn output_bytevector_contents(&self) -> Option<&[u8]> {
+        match &self.kind {
+            Kind::Output(Sink::Bytes(buf)) => Some(buf),
+            _ => None,
+        }
+    }
+
+    pub fn close(&mut self) -> Result<()> {
+        if !self.open {
+            return Ok(()); // Closing twice is harmless
+        }
+        self.open = false;
+        match &mut self.kind {
+            Kind::Input { text, .. } => {
+                *text = String::new(); // Drop the buffered text
+                Ok(())
+            }
+            Kind::BinaryInput { bytes, .. } => {
+                *bytes = Vec::new();
+                Ok(())
+            }
+            Kind::Output(Sink::File(file)) => {
+                file.flush().map_err(|e| SchemeError::io("close-port", &self.name, &e))
+            }
+            Kind::Output(_) => Ok(()),
+        }
+    }
+
+    // The buffered text not yet read.
+    fn unread(&self) -> Result<&str> {
+        if !self.open {
+            return Err(self.closed_error());
+        }
+        match &self.kind {
+            Kind::Output(_) => Err(SchemeError::Runtime(format!("not an input port: {}", self.name))),
+            Kind::BinaryInput { .. } => Err(SchemeError::Runtime(format!("not a textual port: {}", self.name))),
+            Kind::Input { text, pos, .. } => Ok(&text[*pos..]),
+        }
+    }
+
+    // The bytes not yet read from a binary input port.
+    fn unread_bytes(&self) -> Result<&[u8]> {
+        if !self.open {
+            return Err(self.closed_error());
+        }
+        match &self.kind {
+            Kind::BinaryInput { bytes, pos } => Ok(&bytes[*pos..]),
+            Kind::Input { .. } => Err(SchemeError::Runtime(format!("not a binary port: {}", self.name))),
+            Kind::Output(_) => Err(SchemeError::Runtime(format!("not an input port: {}", self.name))),
+        }
+    }
+
+    // Reads another line from stdin onto the end of the buffer, dropping the
+    // text already consumed. Returns false at end of file, and always for
+    // string and file ports, which have all their text from the start.
+    fn fill(&mut self) -> Result<bool> {
+        self.unread()?;
+        match &mut self.kind {
+            Kind::Input { text, pos, stdin: true } => {
+                text.drain(..*pos);
+                *pos = 0;
+                interrupt::wait_for_stdin()?;
+                let n = io::stdin()
+                    .lock()
+                    .read_line(text)
+                    .map_err(|e| SchemeError::io("read", "stdin", &e))?;
+                interrupt::check()?; // Ctrl-C while waiting for the line
+                Ok(n > 0)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    fn advance(&mut self, bytes: usize) {
+        if let Kind::Input { pos, .. } | Kind::BinaryInput { pos, .. } = &mut self.kind {
+            *pos += bytes;
+        }
+    }
+
+    // The next char without consuming it; None at end of file.
+    pub fn peek_char(&mut self) -> Result<Option<char>> {
+        while self.unread()?.is_empty() {
+            if !self.fill()? {
+                return Ok(None);
+            }
+        }
+        Ok(self.unread()?.chars().next())
+    }
+
+    pub fn read_char(&mut self) -> Result<Option<char>> {
+        let c = self.peek_char()?;
+        if let Some(c) = c {
+            self.advance(c.len_utf8());
+        }
+        Ok(c)
+    }
+
+    // The next line without its line ending; None at end of file.
+    pub fn read_line(&mut self) -> Result<Option<String>> {
+        loop {
+            let unread = self.unread()?;
+            if let Some(end) = unread.find('\n') {
+                let line = unread[..end].strip_suffix('\r').unwrap_or(&unread[..end]).to_string();
+                self.advance(end + 1);
+                return Ok(Some(line));
+            }
+            if !self.fill()? {
+                // A last line without a newline still counts as a line
+                let rest = self.unread()?.to_string();
+                self.advance(rest.len());
+                return Ok(if rest.is_empty() { None } else { Some(rest) });
+            }
+        }
+    }
+
+    // Up to `k` chars; fewer only at end of file, and None if there are none left.
+    pub fn read_string(&mut self, k: usize) -> Result<Option<String>> {
+        let mut out = String::new();
+        for _ in 0..k {
+            match self.read_char()? {
+                Some(c) => out.push(c),
+                None => break,
+            }
+        }
+        Ok(if out.is_empty() && k > 0 { None } else { Some(out) })
+    }
+
+    // The next byte without consuming it; None at end of file.
+    pub fn peek_u8(&self) -> Result<Option<u8>> {
+        Ok(self.unread_bytes()?.first().copied())
+    }
+
+    pub fn read_u8(&mut self) -> Result<Option<u8>> {
+        let byte = self.peek_u8()?;
+        if byte.is_some() {
+            self.advance(1);
+        }
+        Ok(byte)
+    }
+
+    // Up to `k` bytes; fewer only at end of file, and None if there are none left.
+    pub fn read_bytes(&mut self, k: usize) -> Result<Option<Vec<u8>>> {
+        let unread = self.unread_bytes()?;
+        if unread.is_empty() && k > 0 {
+            return Ok(None);
+        }
+        let bytes = unread[..k.min(unread.len())].to_vec();
+        self.advance(bytes.len());
+        Ok(Some(bytes))
+    }
+
+    // Binary input ports hold all their bytes, so reading never blocks.
+    pub fn u8_ready(&self) -> Result<bool> {
+        self.unread_bytes().map(|_| true)
+    }
+
+    // Whether a char (or end of file) can be read without blocking. Only
+    // stdin can block, and only once its buffered line is used up.
+    pub fn char_ready(&self) -> Result<bool> {
+        let buffered = !self.unread()?.is_empty();
+        Ok(buffered || !matches!(self.kind, Kind::Input { stdin: true, .. }))
+    }
+
+    // Reads one datum with the parser; None at end of file. On stdin a datum
+    // may span lines, so incomplete input pulls in more lines until it parses.
+    pub fn read_datum(&mut self) -> Result<Option<Value>> {
+        loop {
+            match parser::parse_prefix(self.unread()?) {
+                Ok(Some((value, used))) => {
+                    self.advance(used);
+                    return Ok(Some(value));
+                }
+                Ok(None) => {
+                    if !self.fill()? {
+                        let rest = self.unread()?.len();
+                        self.advance(rest); // Only whitespace and comments were left
+                        return Ok(None);
+                    }
+                }
+                Err(SchemeError::Incomplete(message)) => {
+                    if !self.fill()? {
+                        return Err(SchemeError::Parser(format!("read: unexpected end of file: {}", message)));
+                    }
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+thread_local! {
+    static STDOUT: PortRef = Port::new("stdout", Kind::Output(Sink::Stdout));
+    static CURRENT_INPUT: RefCell<PortRef> = RefCell::new(Port::new("stdin", Kind::Input { text: String::new(), pos: 0, stdin: true }));
+    static CURRENT_OUTPUT: RefCell<PortRef> = RefCell::new(STDOUT.with(Rc::clone));
+    static CURRENT_ERROR: RefCell<PortRef> = RefCell::new(Port::new("stderr", Kind::Output(Sink::Stderr)));
+}
+
+pub fn stdout() -> PortRef {
+    STDOUT.with(Rc::clone)
+}
+
+pub fn current_input() -> PortRef {
+    CURRENT_INPUT.with(|p| Rc::clone(&p.borrow()))
+}
+
+pub fn current_output() -> PortRef {
+    CURRENT_OUTPUT.with(|p| Rc::clone(&p.borrow()))
+}
+
+pub fn current_error() -> PortRef {
+    CURRENT_ERROR.with(|p| Rc::clone(&p.borrow()))
+}
+
+// Runs `f` with `port` as the current output port, restoring the previous
+// one afterwards whether or not `f` succeeds.
+pub fn with_current_output<T>(port: PortRef, f: impl FnOnce() -> T) -> T {
+    let previous = CURRENT_OUTPUT.with(|p| p.replace(port));
+    let result = f();
+    CURRENT_OUTPUT.with(|p| p.replace(previous));
+    result
+}
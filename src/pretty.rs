@@ -0,0 +1,255 @@
+use crate::eval::grow_stack;
+use crate::printer::{self, Style};
+use crate::value::Value;
+use std::cell::Cell;
+use std::collections::{HashMap, HashSet};
+
+// Width-aware pretty printing in `write` syntax, for `pretty-print`/`pp` and
+// the REPL. The value is first turned into a tree of nodes that know their
+// one-line width; layout then walks the tree once, printing a node on one
+// line when it fits in the remaining columns and breaking it across lines
+// otherwise. Every node is measured once and printed once, so the whole
+// thing is linear in the size of the output.
+//
+// Broken lists of plain data, arrays and sets are filled (as many atoms per
+// line as fit) when all their elements are atoms, and otherwise put one
+// element per line. Code gets the conventional Scheme indentation: calls
+// align their arguments under the first one, and forms like `define`,
+// `lambda` and `let` keep their header on the first line and indent the body
+// by two.
+
+pub const DEFAULT_WIDTH: usize = 80;
+
+thread_local! {
+    static WIDTH: Cell<usize> = const { Cell::new(DEFAULT_WIDTH) };
+    static REPL_ENABLED: Cell<bool> = const { Cell::new(true) };
+}
+
+// The width used when `pretty-print` is not given one, and by the REPL.
+pub fn width() -> usize {
+    WIDTH.with(Cell::get)
+}
+
+pub fn set_width(width: usize) {
+    WIDTH.with(|w| w.set(width));
+}
+
+// Whether the REPL pretty-prints results (otherwise it uses plain `write`).
+pub fn repl_enabled() -> bool {
+    REPL_ENABLED.with(Cell::get)
+}
+
+pub fn set_repl_enabled(enabled: bool) {
+    REPL_ENABLED.with(|e| e.set(enabled));
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Layout {
+    Data,        // Elements aligned just inside the opening bracket
+    Call,        // Arguments aligned under the first argument
+    Form(usize), // Head plus this many header items on the first line, body indented by 2
+    Entries,     // Map entries, one per line
+}
+
+enum Kind {
+    Atom(String),
+    Seq { open: String, items: Vec<Node>, sep: &'static str, close: &'static str, layout: Layout },
+    Entry(Box<Node>, Box<Node>), // `key: value` inside a map
+}
+
+struct Node {
+    flat: usize, // Width when printed on one line
+    kind: Kind,
+}
+
+impl Node {
+    fn atom(text: String) -> Node {
+        Node { flat: text.chars().count(), kind: Kind::Atom(text) }
+    }
+
+    fn seq(open: String, items: Vec<Node>, sep: &'static str, close: &'static str, layout: Layout) -> Node {
+        let flat = open.chars().count()
+            + items.iter().map(|item| item.flat).sum::<usize>()
+            + sep.len() * items.len().saturating_sub(1)
+            + close.len();
+        Node { flat, kind: Kind::Seq { open, items, sep, close, layout } }
+    }
+
+    fn entry(key: Node, value: Node) -> Node {
+        Node { flat: key.flat + 2 + value.flat, kind: Kind::Entry(Box::new(key), Box::new(value)) }
+    }
+
+    fn is_atom(&self) -> bool {
+        matches!(self.kind, Kind::Atom(_))
+    }
+}
+
+// Nodes nest as deep as the value, so freeing them grows the stack too.
+impl Drop for Node {
+    fn drop(&mut self) {
+        if let Kind::Seq { items, .. } = &mut self.kind
+            && !items.is_empty()
+        {
+            let items = std::mem::take(items);
+            grow_stack(|| drop(items));
+        }
+    }
+}
+
+// How a list starting with the symbol `head` is indented.
+fn form_layout(head: &str, items: &[Value]) -> Layout {
+    match head {
+        "define" | "lambda" | "let*" | "letrec" | "letrec*" | "when" | "unless" | "case" | "guard" => Layout::Form(1),
+        "let" if matches!(items.get(1), Some(Value::Symbol(_))) => Layout::Form(2), // Named let
+        "let" => Layout::Form(1),
+        "do" => Layout::Form(2),
+        "begin" => Layout::Form(0),
+        _ => Layout::Call,
+    }
+}
+
+// Converts a value to nodes, assigning datum labels the way `write` does.
+struct Builder {
+    labeled: HashSet<usize>,
+    assigned: HashMap<usize, usize>,
+}
+
+impl Builder {
+    fn build(&mut self, value: &Value) -> Node {
+        grow_stack(|| self.build_node(value))
+    }
+
+    fn build_node(&mut self, value: &Value) -> Node {
+        let mut label = String::new();
+        if let Some(id) = printer::container_id(value).filter(|id| self.labeled.contains(id)) {
+            if let Some(n) = self.assigned.get(&id) {
+                return Node::atom(format!("#{}#", n));
+            }
+            let n = self.assigned.len();
+            self.assigned.insert(id, n);
+            label = format!("#{}=", n);
+        }
+        match value {
+            Value::List(items) if !items.is_empty() => {
+                let layout = match &items[0] {
+                    Value::Symbol(head) => form_layout(head, items),
+                    _ => Layout::Data,
+                };
+                let nodes = items.iter().map(|item| self.build(item)).collect();
+                Node::seq(label + "(", nodes, " ", ")", layout)
+            }
+            Value::Array(arr) => {
+                let nodes = arr.borrow().iter().map(|item| self.build(item)).collect();
+                Node::seq(label + "[", nodes, ", ", "]", Layout::Data)
+            }
+            Value::Set(set) => {
+                let nodes = set.borrow().iter().map(|k| self.build(&k.0)).collect();
+                Node::seq(label + "#{", nodes, ", ", "}", Layout::Data)
+            }
+            Value::Bytevector(bytes) => {
+                let nodes = bytes.borrow().iter().map(|byte| Node::atom(byte.to_string())).collect();
+                Node::seq(label + "#u8(", nodes, " ", ")", Layout::Data)
+            }
+            Value::Map(map) => {
+                let nodes = map.borrow().iter().map(|(k, v)| Node::entry(self.build(&k.0), self.build(v))).collect();
+                Node::seq(label + "{", nodes, ", ", "}", Layout::Entries)
+            }
+            _ => Node::atom(printer::to_string(value, Style::Write)),
+        }
+    }
+}
+
+struct Out {
+    text: String,
+    col: usize,
+    width: usize,
+}
+
+impl Out {
+    fn push(&mut self, s: &str) {
+        self.text.push_str(s);
+        self.col += s.chars().count();
+    }
+
+    fn newline(&mut self, indent: usize) {
+        self.text.push('\n');
+        self.text.extend(std::iter::repeat_n(' ', indent));
+        self.col = indent;
+    }
+
+    fn flat(&mut self, node: &Node) {
+        match &node.kind {
+            Kind::Atom(text) => self.push(text),
+            Kind::Seq { open, items, sep, close, .. } => {
+                self.push(open);
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        self.push(sep);
+                    }
+                    grow_stack(|| self.flat(item));
+                }
+                self.push(close);
+            }
+            Kind::Entry(key, value) => {
+                grow_stack(|| self.flat(key));
+                self.push(": ");
+                grow_stack(|| self.flat(value));
+            }
+        }
+    }
+
+    // Prints `node`, leaving room for `trail` closing characters after it.
+    fn layout(&mut self, node: &Node, trail: usize) {
+        if self.col + node.flat + trail <= self.width {
+            return self.flat(node);
+        }
+        match &node.kind {
+            Kind::Atom(text) => self.push(text),
+            Kind::Entry(key, value) => {
+                grow_stack(|| self.layout(key, 2));
+                self.push(": ");
+                grow_stack(|| self.layout(value, trail));
+            }
+            Kind::Seq { open, items, sep, close, layout } => {
+                let start = self.col;
+                self.push(open);
+                let inner = self.col;
+                let line_end = sep.trim_end(); // What stays at the end of a broken line
+                // Items up to `header` share the opening line; the rest start
+                // new lines at `indent` unless filling.
+                let (header, indent) = match *layout {
+                    Layout::Form(n) => (n + 1, start + 2),
+                    Layout::Call if items.len() > 1 && inner + items[0].flat < self.width / 2 => {
+                        (2, inner + items[0].flat + 1)
+                    }
+                    _ => (1, inner),
+                };
+                let fill = *layout != Layout::Entries && !matches!(layout, Layout::Form(_)) && items.iter().all(Node::is_atom);
+                let last = items.len().saturating_sub(1);
+                for (i, item) in items.iter().enumerate() {
+                    let item_trail = if i == last { close.len() + trail } else { line_end.len() };
+                    if i > 0 {
+                        if i < header || (fill && self.col + sep.len() + item.flat + item_trail <= self.width) {
+                            self.push(sep);
+                        } else {
+                            self.push(line_end);
+                            self.newline(indent);
+                        }
+                    }
+                    grow_stack(|| self.layout(item, item_trail));
+                }
+                self.push(close);
+            }
+        }
+    }
+}
+
+// Lays out `value` to fit in `width` columns where possible. The result has
+// no trailing newline and reads back like `write` output.
+pub fn to_string(value: &Value, width: usize) -> String {
+    let mut builder = Builder { labeled: printer::find_labels(value, false), assigned: HashMap::new() };
+    let node = builder.build(value);
+    let mut out = Out { text: String::new(), col: 0, width };
+    out.layout(&node, 0);
+    out.text
+}
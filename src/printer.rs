@@ -0,0 +1,231 @@
+use crate::eval::grow_stack;
+use crate::value::{char_name, Value};
+use std::collections::{HashMap, HashSet};
+use std::fmt::{self, Write};
+
+// Printing for `Value`, in the styles of the R7RS output procedures.
+// `write` output reads back with `parser::parse_prefix` to an equal value for
+// everything that has a literal syntax: strings are escaped, symbols that
+// would not read back as themselves are quoted with `|...|`, and chars use
+// `#\` syntax. `display` output is for humans and prints strings, symbols and
+// chars as their raw text.
+//
+// Arrays, maps and sets are shared `Rc<RefCell<..>>` objects and may contain
+// themselves, so before printing we look for the objects reachable from
+// inside themselves and print those with R7RS datum labels:
+// `(array-set! a 0 a)` prints as `#0=[#0#]`, which the reader accepts.
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Style {
+    Write,       // Labels cycles only
+    WriteShared, // Labels every container that appears more than once
+    WriteSimple, // No labels; does not terminate on cyclic data
+    Display,     // Human-readable; labels cycles like `Write`
+}
+
+// Identity of a shared container, if the value is one.
+pub fn container_id(value: &Value) -> Option<usize> {
+    match value {
+        Value::Array(arr) => Some(arr.as_ptr() as *const () as usize),
+        Value::Map(map) => Some(map.as_ptr() as *const () as usize),
+        Value::Set(set) => Some(set.as_ptr() as *const () as usize),
+        _ => None,
+    }
+}
+
+// Calls `visit` on each value directly contained in `value`.
+fn for_each_child(value: &Value, mut visit: impl FnMut(&Value)) {
+    match value {
+        Value::List(items) => items.iter().for_each(visit),
+        Value::Array(arr) => arr.borrow().iter().for_each(visit),
+        Value::Map(map) => map.borrow().iter().for_each(|(k, v)| {
+            visit(&k.0);
+            visit(v);
+        }),
+        Value::Set(set) => set.borrow().iter().for_each(|k| visit(&k.0)),
+        _ => {}
+    }
+}
+
+// Finds the containers that need a label: those reached again while they
+// are still being traversed (the targets of cycles), or with `all_shared`
+// those reached more than once at all.
+pub fn find_labels(value: &Value, all_shared: bool) -> HashSet<usize> {
+    struct Walk {
+        on_path: HashSet<usize>,
+        done: HashSet<usize>,
+        labeled: HashSet<usize>,
+        all_shared: bool,
+    }
+    fn walk(value: &Value, state: &mut Walk) {
+        let id = container_id(value);
+        if let Some(id) = id {
+            if state.on_path.contains(&id) || (state.all_shared && state.done.contains(&id)) {
+                state.labeled.insert(id);
+                return;
+            }
+            if !state.done.insert(id) {
+                return; // Already fully explored
+            }
+            state.on_path.insert(id);
+        }
+        for_each_child(value, |child| grow_stack(|| walk(child, state)));
+        if let Some(id) = id {
+            state.on_path.remove(&id);
+        }
+    }
+    let mut state = Walk { on_path: HashSet::new(), done: HashSet::new(), labeled: HashSet::new(), all_shared };
+    walk(value, &mut state);
+    state.labeled
+}
+
+// True if `name` reads back as this symbol without `|...|` quoting. The
+// tokenizer decides what a token is from its first character, so names
+// starting like a number, a `#` literal or a `.` need quoting too.
+fn is_plain_symbol(name: &str) -> bool {
+    let mut chars = name.chars();
+    let Some(first) = chars.next() else {
+        return false;
+    };
+    let starts_number = first.is_ascii_digit() || (first == '-' && chars.next().is_some_and(|c| c.is_ascii_digit()));
+    !starts_number
+        && !matches!(first, '#' | '.')
+        && !name.chars().any(|c| c.is_whitespace() || c.is_control() || "()[]{}:,'\"|;\\".contains(c))
+}
+
+// Writes `c` as it appears inside a string or `|symbol|` delimited by `quote`.
+fn write_escaped_char<W: Write>(out: &mut W, c: char, quote: char) -> fmt::Result {
+    match c {
+        '\n' => out.write_str("\\n"),
+        '\t' => out.write_str("\\t"),
+        '\r' => out.write_str("\\r"),
+        '\\' => out.write_str("\\\\"),
+        c if c == quote => write!(out, "\\{}", c),
+        c if c.is_control() => write!(out, "\\x{:x};", c as u32),
+        c => out.write_char(c),
+    }
+}
+
+struct Printer {
+    style: Style,
+    labeled: HashSet<usize>,
+    assigned: HashMap<usize, usize>, // container id -> label number, in print order
+}
+
+impl Printer {
+    fn write_seq<'a, W: Write>(&mut self, out: &mut W, items: impl Iterator<Item = &'a Value>, sep: &str) -> fmt::Result {
+        for (i, item) in items.enumerate() {
+            if i > 0 {
+                out.write_str(sep)?;
+            }
+            self.write(out, item)?;
+        }
+        Ok(())
+    }
+
+    fn write<W: Write>(&mut self, out: &mut W, value: &Value) -> fmt::Result {
+        grow_stack(|| self.write_datum(out, value))
+    }
+
+    fn write_datum<W: Write>(&mut self, out: &mut W, value: &Value) -> fmt::Result {
+        if let Some(id) = container_id(value).filter(|id| self.labeled.contains(id)) {
+            if let Some(n) = self.assigned.get(&id) {
+                return write!(out, "#{}#", n);
+            }
+            let n = self.assigned.len();
+            self.assigned.insert(id, n);
+            write!(out, "#{}=", n)?;
+        }
+        let display = self.style == Style::Display;
+        match value {
+            Value::Integer(n) => write!(out, "{}", n),
+            Value::Bool(b) => write!(out, "{}", if *b { "#t" } else { "#f" }),
+            Value::Char(c) if display => out.write_char(*c),
+            Value::Char(c) => match char_name(*c) {
+                Some(name) => write!(out, "#\\{}", name),
+                None if c.is_control() => write!(out, "#\\x{:x}", *c as u32),
+                None => write!(out, "#\\{}", c),
+            },
+            Value::Symbol(s) if display || is_plain_symbol(s) => out.write_str(s),
+            Value::Symbol(s) => {
+                out.write_char('|')?;
+                s.chars().try_for_each(|c| write_escaped_char(out, c, '|'))?;
+                out.write_char('|')
+            }
+            Value::String(s) if display => out.write_str(s),
+            Value::String(s) => {
+                out.write_char('"')?;
+                s.chars().try_for_each(|c| write_escaped_char(out, c, '"'))?;
+                out.write_char('"')
+            }
+            Value::Nil => write!(out, "()"),
+            Value::List(lst) => {
+                out.write_char('(')?;
+                self.write_seq(out, lst.iter(), " ")?;
+                out.write_char(')')
+            }
+            Value::Array(arr) => {
+                out.write_char('[')?;
+                self.write_seq(out, arr.borrow().iter(), ", ")?;
+                out.write_char(']')
+            }
+            Value::Map(map) => {
+                out.write_char('{')?;
+                for (i, (k, v)) in map.borrow().iter().enumerate() {
+                    if i > 0 {
+                        out.write_str(", ")?;
+                    }
+                    self.write(out, &k.0)?;
+                    out.write_str(": ")?;
+                    self.write(out, v)?;
+                }
+                out.write_char('}')
+            }
+            Value::HashTable(table) => write!(out, "#<hash-table:{}>", table.borrow().len()),
+            Value::Bytevector(bytes) => {
+                out.write_str("#u8(")?;
+                for (i, byte) in bytes.borrow().iter().enumerate() {
+                    if i > 0 {
+                        out.write_char(' ')?;
+                    }
+                    write!(out, "{}", byte)?;
+                }
+                out.write_char(')')
+            }
+            Value::Set(set) => {
+                out.write_str("#{")?;
+                self.write_seq(out, set.borrow().iter().map(|k| &k.0), ", ")?;
+                out.write_char('}')
+            }
+            Value::Lambda { params, .. } => write!(out, "#<procedure:{}>", params.join(" ")),
+            Value::Closure(closure) => write!(out, "#<procedure:{}>", closure.proto.params.join(" ")),
+            Value::Builtin(_, name) => write!(out, "#<builtin:{}>", name),
+            Value::Port(port) => {
+                let port = port.borrow();
+                let binary = if port.is_binary() { "binary-" } else { "" };
+                let direction = if port.is_input() { "input" } else { "output" };
+                let closed = if port.is_open() { "" } else { " (closed)" };
+                write!(out, "#<{}{}-port:{}{}>", binary, direction, port.name(), closed)
+            }
+            Value::Eof => write!(out, "#<eof>"),
+            Value::Error(err) => write!(out, "#<error:{}>", err),
+        }
+    }
+}
+
+// Prints `value` in the given style.
+pub fn print<W: Write>(out: &mut W, value: &Value, style: Style) -> fmt::Result {
+    let labeled = match style {
+        Style::WriteSimple => HashSet::new(),
+        Style::WriteShared => find_labels(value, true),
+        Style::Write | Style::Display => find_labels(value, false),
+    };
+    let mut printer = Printer { style, labeled, assigned: HashMap::new() };
+    printer.write(out, value)
+}
+
+pub fn to_string(value: &Value, style: Style) -> String {
+    let mut s = String::new();
+    let _ = print(&mut s, value, style); // Writing to a String cannot fail
+    s
+}
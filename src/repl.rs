@@ -0,0 +1,798 @@
+use crate::doc;
+use crate::env::Environment;
+use crate::builtins;
+use crate::error::{Result, SchemeError};
+use crate::expand;
+use crate::mapdata::MapData;
+use crate::interrupt;
+use crate::limits;
+use crate::parser;
+use crate::port;
+use crate::pretty;
+use crate::printer::{self, Style};
+use crate::stats;
+use crate::value::{MapKey, Value};
+use crate::eval;
+use crate::vm;
+use rustyline::completion::Completer;
+use rustyline::error::ReadlineError;
+use rustyline::highlight::{CmdKind, Highlighter};
+use rustyline::hint::{Hint, Hinter};
+use rustyline::history::{FileHistory, History};
+use rustyline::validate::{ValidationContext, ValidationResult, Validator};
+use rustyline::{Config, Context, Editor, Helper};
+use std::borrow::Cow;
+use std::cell::{Cell, RefCell};
+use std::io::IsTerminal;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::Instant;
+
+// The interactive loop. The `Validator` below keeps the line editor open
+// while the input ends partway through a datum (an open paren, bracket,
+// brace or string): Enter then starts a new line of the same entry, so a
+// multi-line definition is typed, pasted and recalled from history as one
+// entry. Rustyline applies the validator to piped input too, gathering lines
+// until the datum is complete. Every complete datum in an entry is evaluated.
+//
+// The same helper completes names bound in the environment with Tab, colors
+// literals and comments, highlights the bracket matching the one at the
+// cursor, and hints the signature of the procedure being called. In a
+// terminal, history is kept in the user's config directory across sessions.
+
+// True if `input` ends partway through a datum, so more text could complete it.
+fn is_incomplete(input: &str) -> bool {
+    let mut rest = input;
+    loop {
+        match parser::parse_prefix(rest) {
+            Ok(Some((_, used))) => rest = &rest[used..],
+            Ok(None) => return false,
+            Err(SchemeError::Incomplete(_)) => return true,
+            Err(_) => return false, // Reported when the entry is evaluated
+        }
+    }
+}
+
+// Lexical classes the highlighter and the other helpers care about.
+#[derive(Clone, Copy, PartialEq)]
+enum Class {
+    Comment,
+    String,
+    Literal, // Numbers, chars and booleans
+    Open,
+    Close,
+}
+
+fn is_delimiter(c: char) -> bool {
+    c.is_whitespace() || "()[]{}:,'\";".contains(c)
+}
+
+// Byte index of the end of the run of non-delimiters starting at `start`.
+fn word_end(line: &str, start: usize) -> usize {
+    line[start..].find(is_delimiter).map_or(line.len(), |n| start + n)
+}
+
+// A rough tokenization of a possibly unfinished line: the spans of
+// comments, strings, literals and brackets. Symbols are left out.
+fn scan(line: &str) -> Vec<(Range<usize>, Class)> {
+    let mut spans = Vec::new();
+    let mut chars = line.char_indices().peekable();
+    while let Some((start, c)) = chars.next() {
+        let (end, class) = match c {
+            ';' => (line.len(), Class::Comment),
+            '"' | '|' => {
+                let mut end = line.len(); // Unterminated: runs to the end
+                while let Some((i, next)) = chars.next() {
+                    if next == '\\' {
+                        chars.next();
+                    } else if next == c {
+                        end = i + 1;
+                        break;
+                    }
+                }
+                if c == '|' {
+                    continue; // |symbol|: skipped so its contents are not highlighted
+                }
+                (end, Class::String)
+            }
+            '(' | '[' | '{' => (start + 1, Class::Open),
+            ')' | ']' | '}' => (start + 1, Class::Close),
+            '#' => match chars.peek() {
+                Some((_, '{')) => {
+                    chars.next();
+                    (start + 2, Class::Open)
+                }
+                Some((_, 'u')) if line[start..].starts_with("#u8(") => {
+                    (start + 4, Class::Open)
+                }
+                Some((_, '\\')) => {
+                    chars.next();
+                    let first = chars.peek().map_or(line.len(), |(i, c)| i + c.len_utf8());
+                    (word_end(line, first).max(first), Class::Literal)
+                }
+                _ => (word_end(line, start + 1), Class::Literal),
+            },
+            c if c.is_ascii_digit() => (word_end(line, start), Class::Literal),
+            '-' if chars.peek().is_some_and(|(_, c)| c.is_ascii_digit()) => (word_end(line, start), Class::Literal),
+            c if is_delimiter(c) => continue,
+            _ => {
+                let end = word_end(line, start);
+                while chars.peek().is_some_and(|(i, _)| *i < end) {
+                    chars.next();
+                }
+                continue;
+            }
+        };
+        while chars.peek().is_some_and(|(i, _)| *i < end) {
+            chars.next();
+        }
+        spans.push((start..end, class));
+    }
+    spans
+}
+
+// The bracket matching the one at `pos` or just before it, as byte ranges
+// of both brackets.
+fn matching_brackets(spans: &[(Range<usize>, Class)], pos: usize) -> Option<(Range<usize>, Range<usize>)> {
+    let brackets: Vec<&(Range<usize>, Class)> = spans.iter().filter(|(_, class)| matches!(class, Class::Open | Class::Close)).collect();
+    let at = brackets
+        .iter()
+        .position(|(range, _)| range.contains(&pos))
+        .or_else(|| brackets.iter().position(|(range, _)| range.end == pos))?;
+    let mut depth = 0i32;
+    if brackets[at].1 == Class::Open {
+        for (range, class) in &brackets[at..] {
+            depth += if *class == Class::Open { 1 } else { -1 };
+            if depth == 0 {
+                return Some((brackets[at].0.clone(), range.clone()));
+            }
+        }
+    } else {
+        for (range, class) in brackets[..=at].iter().rev() {
+            depth += if *class == Class::Close { 1 } else { -1 };
+            if depth == 0 {
+                return Some((brackets[at].0.clone(), range.clone()));
+            }
+        }
+    }
+    None
+}
+
+// A hint that is only displayed; accepting hints must not insert signatures.
+pub struct SignatureHint(String);
+
+impl Hint for SignatureHint {
+    fn display(&self) -> &str {
+        &self.0
+    }
+
+    fn completion(&self) -> Option<&str> {
+        None
+    }
+}
+
+pub struct ReplHelper {
+    env: Rc<RefCell<Environment>>,
+    forced_refresh: Cell<bool>, // The final redraw of an entry shows no bracket match
+}
+
+impl ReplHelper {
+    // The signature or docstring to show for `name`, if it is a procedure or special form.
+    fn describe(&self, name: &str) -> Option<String> {
+        match self.env.borrow().lookup(name) {
+            Some(value) => doc::describe(name, &value),
+            None => doc::signature(name),
+        }
+    }
+}
+
+impl Validator for ReplHelper {
+    fn validate(&self, ctx: &mut ValidationContext) -> rustyline::Result<ValidationResult> {
+        // A command's argument may be a multi-line expression too
+        let input = split_command(ctx.input()).map_or(ctx.input(), |(_, arg)| arg);
+        if is_incomplete(input) {
+            Ok(ValidationResult::Incomplete)
+        } else {
+            Ok(ValidationResult::Valid(None))
+        }
+    }
+}
+
+// Tab completes the name before the cursor from the bindings visible in
+// the environment and the special forms.
+impl Completer for ReplHelper {
+    type Candidate = String;
+
+    fn complete(&self, line: &str, pos: usize, _ctx: &Context<'_>) -> rustyline::Result<(usize, Vec<String>)> {
+        let in_text = scan(line)
+            .iter()
+            .any(|(range, class)| matches!(class, Class::String | Class::Comment) && range.start < pos && pos <= range.end);
+        let start = line[..pos].rfind(is_delimiter).map_or(0, |i| i + line[i..].chars().next().map_or(1, char::len_utf8));
+        let prefix = &line[start..pos];
+        if in_text || prefix.starts_with('#') {
+            return Ok((pos, Vec::new()));
+        }
+        if line[..start].trim_start() == "," {
+            let commands = COMMANDS.iter().filter(|(name, _, _)| name.starts_with(prefix)).map(|(name, _, _)| name.to_string());
+            return Ok((start, commands.collect()));
+        }
+        let mut candidates: Vec<String> = self.env.borrow().names();
+        candidates.extend(doc::SPECIAL_FORMS.iter().map(|form| form.to_string()));
+        candidates.retain(|name| name.starts_with(prefix));
+        candidates.sort();
+        candidates.dedup();
+        Ok((start, candidates))
+    }
+}
+
+// While the cursor is at the end of the line, hints the signature of the
+// procedure or form whose call is open there: typing `(map-ref ` shows
+// `(map-ref map key [fallback])`, and a lambda shows its parameters and docstring.
+impl Hinter for ReplHelper {
+    type Hint = SignatureHint;
+
+    fn hint(&self, line: &str, pos: usize, _ctx: &Context<'_>) -> Option<SignatureHint> {
+        if pos < line.len() {
+            return None;
+        }
+        let spans = scan(line);
+        if spans.last().is_some_and(|(range, class)| matches!(class, Class::String | Class::Comment) && range.end == line.len()) {
+            return None; // Typing inside a string or comment
+        }
+        let mut depth = 0;
+        let open = spans.iter().rev().find(|(_, class)| {
+            match class {
+                Class::Close => depth += 1,
+                Class::Open if depth > 0 => depth -= 1,
+                Class::Open => return true,
+                _ => {}
+            }
+            false
+        })?;
+        if !line[open.0.clone()].ends_with('(') || open.0.end == line.len() {
+            return None;
+        }
+        let head_start = open.0.end;
+        let head = &line[head_start..word_end(line, head_start)];
+        if head.is_empty() || head_start + head.len() == line.len() {
+            return None; // No name yet, or still typing it (completion's job)
+        }
+        self.describe(head).map(|sig| SignatureHint(format!("  {}", sig)))
+    }
+}
+
+// Colors literals green, strings yellow and comments grey, and shows the
+// bracket matching the one at the cursor in bold.
+impl Highlighter for ReplHelper {
+    fn highlight<'l>(&self, line: &'l str, pos: usize) -> Cow<'l, str> {
+        let spans = scan(line);
+        let matched = if self.forced_refresh.get() { None } else { matching_brackets(&spans, pos) };
+        let mut out = String::with_capacity(line.len() * 2);
+        let mut last = 0;
+        for (range, class) in &spans {
+            let color = match class {
+                Class::Comment => "\x1b[90m",
+                Class::String => "\x1b[33m",
+                Class::Literal => "\x1b[32m",
+                Class::Open | Class::Close => match &matched {
+                    Some((a, b)) if a == range || b == range => "\x1b[1;34m",
+                    _ => continue,
+                },
+            };
+            out.push_str(&line[last..range.start]);
+            out.push_str(color);
+            out.push_str(&line[range.clone()]);
+            out.push_str("\x1b[0m");
+            last = range.end;
+        }
+        if last == 0 {
+            return Cow::Borrowed(line);
+        }
+        out.push_str(&line[last..]);
+        Cow::Owned(out)
+    }
+
+    fn highlight_hint<'h>(&self, hint: &'h str) -> Cow<'h, str> {
+        Cow::Owned(format!("\x1b[2m{}\x1b[0m", hint))
+    }
+
+    fn highlight_char(&self, _line: &str, _pos: usize, kind: CmdKind) -> bool {
+        self.forced_refresh.set(kind == CmdKind::ForcedRefresh);
+        true // Bracket matching depends on the cursor, so every move redraws
+    }
+}
+
+impl Helper for ReplHelper {}
+
+// --- Result history ---
+// The last three results are bound to `*1`, `*2` and `*3`, and the last
+// error to `*e` as `{error: <error object>, backtrace: ((op arg ...) ...)}`.
+// They live in a scope behind the root environment: code can use them like
+// variables, but a `define` shadows them rather than changing them, and
+// `,env` and `,reset` leave them alone.
+
+struct Session {
+    env: Rc<RefCell<Environment>>, // The root environment
+    results: Rc<RefCell<Environment>>, // `*1`, `*2`, `*3` and `*e`
+}
+
+impl Session {
+    fn new(env: Rc<RefCell<Environment>>) -> Self {
+        let results = Rc::new(RefCell::new(Environment::new()));
+        env.borrow_mut().set_parent(Rc::clone(&results));
+        Session { env, results }
+    }
+
+    fn remember_result(&self, value: &Value) {
+        let mut results = self.results.borrow_mut();
+        for (from, to) in [("*2", "*3"), ("*1", "*2")] {
+            if let Some(older) = results.lookup(from) {
+                results.define(to.to_string(), older);
+            }
+        }
+        results.define("*1".to_string(), value.clone());
+    }
+
+    fn remember_error(&self, err: &SchemeError) {
+        let mut entry = MapData::new();
+        entry.insert(MapKey(Value::symbol("error")), Value::Error(Rc::new(err.clone())));
+        entry.insert(MapKey(Value::symbol("backtrace")), Value::List(eval::take_backtrace()));
+        self.results.borrow_mut().define("*e".to_string(), Value::Map(Rc::new(RefCell::new(entry))));
+    }
+
+    // Prints the result of an evaluation and adds it to the history. Returns
+    // the status if it called `exit`.
+    fn print_result(&self, result: Result<Value>) -> Option<i32> {
+        // Keep the result off the end of any unfinished line of output
+        let _ = port::stdout().borrow_mut().fresh_line();
+        match result {
+            Err(SchemeError::Exit(status)) => return Some(status),
+            Ok(result) => {
+                if pretty::repl_enabled() {
+                    println!("{}", pretty::to_string(&result, pretty::width()));
+                } else {
+                    println!("{}", printer::to_string(&result, Style::Write));
+                }
+                self.remember_result(&result);
+            }
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                self.remember_error(&e);
+            }
+        }
+        None
+    }
+}
+
+// --- Meta-commands ---
+// An entry starting with `,` is a command to the REPL itself rather than
+// code: `,time (fib 20)`, `,describe map-ref`. A command name may be
+// shortened to any unambiguous prefix (`,d`, `,ex`).
+
+const COMMANDS: &[(&str, &str, &str)] = &[
+    ("help", "", "List the commands"),
+    ("time", "EXPR", "Evaluate EXPR, then show the time taken and the allocations made"),
+    ("env", "[PREFIX]", "List this session's definitions, or every name starting with PREFIX"),
+    ("describe", "NAME", "Show what NAME is bound to, with its signature and docstring"),
+    ("load", "FILE", "Evaluate the forms in FILE"),
+    ("reset", "", "Discard all definitions and traces, restoring the initial environment"),
+    ("expand", "EXPR", "Show EXPR with its derived forms rewritten into core forms"),
+    ("inspect", "[EXPR]", "Browse the parts of the value of EXPR (default *1)"),
+    ("trace", "PROC", "Print each call of the procedure PROC and its result"),
+    ("untrace", "[PROC]", "Stop tracing PROC, or every traced procedure"),
+    ("disasm", "PROC", "Show the bytecode of the compiled procedure PROC (with --vm)"),
+    ("quit", "[STATUS]", "Leave the REPL"),
+];
+
+// Splits an entry starting with `,` into the command name and its argument text.
+fn split_command(entry: &str) -> Option<(&str, &str)> {
+    let rest = entry.trim_start().strip_prefix(',')?;
+    let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+    Some((&rest[..end], rest[end..].trim()))
+}
+
+// The command `name` refers to, exactly or as an unambiguous prefix.
+fn find_command(name: &str) -> std::result::Result<&'static (&'static str, &'static str, &'static str), String> {
+    if let Some(command) = COMMANDS.iter().find(|(n, _, _)| *n == name) {
+        return Ok(command);
+    }
+    let matches: Vec<_> = COMMANDS.iter().filter(|(n, _, _)| !name.is_empty() && n.starts_with(name)).collect();
+    match matches.as_slice() {
+        [command] => Ok(command),
+        [] => Err(format!("Unknown command ,{} (,help lists the commands)", name)),
+        _ => Err(format!("Ambiguous command ,{}: {}", name, matches.iter().map(|(n, _, _)| format!(",{}", n)).collect::<Vec<_>>().join(" "))),
+    }
+}
+
+// The single datum making up `arg`, or a usage error.
+fn one_datum(arg: &str, usage: &str) -> std::result::Result<Value, String> {
+    match parser::parse_prefix(arg) {
+        Ok(Some((datum, used))) if arg[used..].trim().is_empty() => Ok(datum),
+        Err(e) => Err(e.to_string()),
+        _ => Err(usage.to_string()),
+    }
+}
+
+// `line` truncated to fit the terminal.
+fn fit(line: String) -> String {
+    let width = pretty::width().max(20);
+    match line.char_indices().nth(width - 3) {
+        Some((cut, _)) if line.chars().count() > width => format!("{}...", &line[..cut]),
+        _ => line,
+    }
+}
+
+// One line describing `name` bound to `value`.
+fn binding_line(name: &str, value: &Value) -> String {
+    fit(doc::describe(name, value).unwrap_or_else(|| format!("{} = {}", name, printer::to_string(value, Style::Write))))
+}
+
+impl Session {
+    // Runs the command `,name arg`. Returns the status if it ended the session.
+    fn run_command(&self, rl: &mut Editor<ReplHelper, FileHistory>, name: &str, arg: &str) -> Option<i32> {
+        let env = &self.env;
+        let (name, usage_arg, _) = match find_command(name) {
+            Ok(command) => command,
+            Err(message) => {
+                eprintln!("{}", message);
+                return None;
+            }
+        };
+        let usage = format!("Usage: ,{} {}", name, usage_arg);
+        let datum = || one_datum(arg, &usage);
+        let result: std::result::Result<Option<i32>, String> = match *name {
+            "help" => {
+                for (name, arg, help) in COMMANDS {
+                    println!("  {:<18} {}", format!(",{} {}", name, arg), help);
+                }
+                Ok(None)
+            }
+            "time" => datum().map(|expr| {
+                let (allocations, bytes) = stats::allocations();
+                let start = Instant::now();
+                let result = eval::evaluate(&expr, Rc::clone(env));
+                let elapsed = start.elapsed();
+                let (allocations_after, bytes_after) = stats::allocations();
+                let status = self.print_result(result);
+                if status.is_none() {
+                    println!(";; {:.3?} elapsed, {} allocations ({} bytes)", elapsed, allocations_after - allocations, bytes_after - bytes);
+                }
+                status
+            }),
+            "env" => {
+                let mut lines: Vec<(String, String)> = if arg.is_empty() {
+                    // Builtins still bound to their own name are left out
+                    env.borrow()
+                        .local_bindings()
+                        .filter(|(name, value)| !matches!(value, Value::Builtin(_, builtin) if builtin == *name))
+                        .map(|(name, value)| (name.clone(), binding_line(name, value)))
+                        .collect()
+                } else {
+                    let env = env.borrow();
+                    let mut names = env.names();
+                    names.retain(|name| name.starts_with(arg));
+                    names.sort();
+                    names.dedup();
+                    names.into_iter().filter_map(|name| env.lookup(&name).map(|value| {
+                        let line = binding_line(&name, &value);
+                        (name, line)
+                    })).collect()
+                };
+                lines.sort();
+                if lines.is_empty() {
+                    println!(";; no bindings");
+                }
+                for (_, line) in lines {
+                    println!("{}", line);
+                }
+                Ok(None)
+            }
+            "describe" if arg.is_empty() => Err(usage),
+            "describe" => {
+                match env.borrow().lookup(arg) {
+                    Some(value) => {
+                        let kind = match value {
+                            Value::Builtin(..) => "builtin procedure".to_string(),
+                            _ => value.type_name(),
+                        };
+                        println!("{}: {}", arg, kind);
+                        println!("  {}", doc::describe(arg, &value).unwrap_or_else(|| printer::to_string(&value, Style::Write)));
+                    }
+                    None if doc::SPECIAL_FORMS.contains(&arg) => {
+                        println!("{}: special form", arg);
+                        if let Some(signature) = doc::signature(arg) {
+                            println!("  {}", signature);
+                        }
+                    }
+                    None => println!("{} is not bound", arg),
+                }
+                Ok(None)
+            }
+            "load" if arg.is_empty() => Err(usage),
+            "load" => {
+                // The path may be written as a string literal, to include spaces
+                let path = match parser::parse_prefix(arg) {
+                    Ok(Some((Value::String(ref path), _))) => path.to_string(),
+                    _ => arg.to_string(),
+                };
+                match crate::run_file(&path, Rc::clone(env)) {
+                    Ok(()) => {
+                        println!(";; loaded {}", path);
+                        Ok(None)
+                    }
+                    Err(err) => Ok(self.print_result(Err(err))),
+                }
+            }
+            "reset" => {
+                eval::untrace(None);
+                let mut root = Environment::new();
+                builtins::populate_environment(&mut root);
+                root.set_parent(Rc::clone(&self.results));
+                *env.borrow_mut() = root; // In place, so closures and the line editor see it
+                println!(";; environment reset");
+                Ok(None)
+            }
+            "expand" => datum().and_then(|expr| expand::expand(&expr).map_err(|e| format!("Error: {}", e))).map(|expanded| {
+                println!("{}", pretty::to_string(&expanded, pretty::width()));
+                None
+            }),
+            "trace" => datum().map(|expr| {
+                match eval::evaluate(&expr, Rc::clone(env)) {
+                    Ok(proc @ (Value::Lambda { .. } | Value::Builtin(..) | Value::Closure(_))) => {
+                        let name = printer::to_string(&expr, Style::Display);
+                        eval::trace(&name, proc);
+                        println!(";; tracing {}", name);
+                        None
+                    }
+                    Ok(other) => self.print_result(Err(Value::type_error("procedure", &other))),
+                    Err(err) => self.print_result(Err(err)),
+                }
+            }),
+            "disasm" => datum().map(|expr| match eval::evaluate(&expr, Rc::clone(env)) {
+                Ok(Value::Closure(ref closure)) => {
+                    print!("{}", vm::disassemble(&closure.proto));
+                    None
+                }
+                Ok(Value::Lambda { .. }) => {
+                    println!(";; {} is not compiled; start the REPL with --vm", printer::to_string(&expr, Style::Write));
+                    None
+                }
+                Ok(other) => self.print_result(Err(Value::type_error("procedure", &other))),
+                Err(err) => self.print_result(Err(err)),
+            }),
+            "inspect" => {
+                let source = if arg.is_empty() { "*1" } else { arg };
+                one_datum(source, &usage).map(|expr| match eval::evaluate(&expr, Rc::clone(env)) {
+                    Ok(value) => {
+                        let label = if let Value::Symbol(name) = &expr { name.to_string() } else { "value".to_string() };
+                        self.inspect(rl, &label, value);
+                        None
+                    }
+                    Err(err) => self.print_result(Err(err)),
+                })
+            }
+            "untrace" => {
+                let untraced = eval::untrace(Some(arg).filter(|arg| !arg.is_empty()));
+                if untraced.is_empty() {
+                    println!(";; nothing traced{}", if arg.is_empty() { String::new() } else { format!(" as {}", arg) });
+                } else {
+                    println!(";; untraced {}", untraced.join(" "));
+                }
+                Ok(None)
+            }
+            "quit" if arg.is_empty() => Ok(Some(0)),
+            "quit" => arg.parse::<i32>().map(Some).map_err(|_| usage),
+            _ => unreachable!("every command is handled"),
+        };
+        result.unwrap_or_else(|message| {
+            eprintln!("{}", message);
+            None
+        })
+    }
+}
+
+// --- Inspector ---
+// `,inspect` shows a value with its parts numbered, then reads commands at
+// an `inspect>` prompt: a number descends into that part, `u` goes back up,
+// an empty line lists the parts again, and `q` (or Ctrl+D) leaves.
+
+const INSPECT_LIMIT: usize = 40; // Parts listed; the rest can still be entered by number
+
+fn numbered(items: impl Iterator<Item = Value>) -> Vec<(String, Value)> {
+    items.enumerate().map(|(i, item)| (format!("[{}]", i), item)).collect()
+}
+
+// The labelled parts of `value`. A closure's are its parameters, its body
+// and the variables it captured, innermost first, up to the root environment.
+fn inspect_parts(value: &Value, root: &Rc<RefCell<Environment>>) -> Vec<(String, Value)> {
+    match value {
+        Value::List(items) => numbered(items.iter().cloned()),
+        Value::Array(items) => numbered(items.borrow().iter().cloned()),
+        Value::Set(set) => numbered(set.borrow().iter().map(|key| key.0.clone())),
+        Value::Map(map) => map.borrow().iter().map(|(k, v)| (printer::to_string(&k.0, Style::Write), v.clone())).collect(),
+        Value::HashTable(table) => {
+            table.borrow().entries().into_iter().map(|(k, v)| (printer::to_string(&k, Style::Write), v)).collect()
+        }
+        Value::Lambda { params, body, env, .. } => {
+            let mut parts = vec![
+                ("params".to_string(), Value::List(params.iter().map(|p| Value::symbol(p)).collect())),
+                ("body".to_string(), (**body).clone()),
+            ];
+            let mut scope = Some(Rc::clone(env));
+            while let Some(frame) = scope.filter(|frame| !Rc::ptr_eq(frame, root)) {
+                let frame = frame.borrow();
+                let mut captured: Vec<(String, Value)> = frame
+                    .local_bindings()
+                    .filter(|(name, _)| !parts[2..].iter().any(|(seen, _)| seen == *name)) // Shadowed
+                    .map(|(name, value)| (name.clone(), value.clone()))
+                    .collect();
+                captured.sort_by(|a, b| a.0.cmp(&b.0));
+                parts.extend(captured);
+                scope = frame.parent();
+            }
+            parts
+        }
+        Value::Closure(closure) => {
+            let mut parts = vec![
+                ("params".to_string(), Value::List(closure.proto.params.iter().map(|p| Value::symbol(p)).collect())),
+                ("body".to_string(), (*closure.proto.source).clone()),
+            ];
+            let free = closure.proto.free_names.iter().zip(&closure.free);
+            parts.extend(free.filter_map(|(name, local)| Some((name.clone(), local.get()?))));
+            parts
+        }
+        _ => Vec::new(),
+    }
+}
+
+impl Session {
+    fn inspect(&self, rl: &mut Editor<ReplHelper, FileHistory>, label: &str, value: Value) {
+        let mut path = vec![(label.to_string(), value)];
+        let mut list = true;
+        loop {
+            let value = &path.last().expect("the path starts at the inspected value").1;
+            let parts = inspect_parts(value, &self.env);
+            if list {
+                let labels: Vec<&str> = path.iter().map(|(label, _)| label.as_str()).collect();
+                println!("{}: {}", labels.join(" "), value.type_name());
+                println!("  {}", fit(printer::to_string(value, Style::Write)));
+                for (i, (label, part)) in parts.iter().enumerate().take(INSPECT_LIMIT) {
+                    let part = printer::to_string(part, Style::Write);
+                    if *label == format!("[{}]", i) {
+                        println!("{}", fit(format!("  {:>2}. {}", i, part)));
+                    } else {
+                        println!("{}", fit(format!("  {:>2}. {}: {}", i, label, part)));
+                    }
+                }
+                if parts.len() > INSPECT_LIMIT {
+                    println!("  ... {} more", parts.len() - INSPECT_LIMIT);
+                }
+            }
+            list = true;
+            let Ok(line) = rl.readline("inspect> ") else {
+                break; // Ctrl+C or Ctrl+D
+            };
+            match line.trim() {
+                "" => {}
+                "q" => break,
+                "u" if path.len() > 1 => {
+                    path.pop();
+                }
+                "u" => {
+                    println!(";; already at the top");
+                    list = false;
+                }
+                command => match command.parse::<usize>().ok().and_then(|i| parts.get(i)) {
+                    Some(part) => path.push(part.clone()),
+                    None => {
+                        println!(";; a part number, u (up), q (quit), or an empty line to list again");
+                        list = false;
+                    }
+                },
+            }
+        }
+    }
+}
+
+// Where history is kept: `$XDG_CONFIG_HOME/rusty-scheme/history`, falling
+// back to `~/.config` (`%APPDATA%` on Windows).
+fn history_path() -> Option<PathBuf> {
+    let config_dir = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("APPDATA").map(PathBuf::from))
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(config_dir.join("rusty-scheme").join("history"))
+}
+
+fn save_history(history: &mut FileHistory, path: &Path) {
+    let saved = path.parent().map_or(Ok(()), std::fs::create_dir_all).map_err(|e| e.to_string())
+        .and_then(|_| history.save(path).map_err(|e| e.to_string()));
+    if let Err(e) = saved {
+        eprintln!("Could not save history to {}: {}", path.display(), e);
+    }
+}
+
+// Runs the REPL until end of input or `exit`, returning the exit status.
+pub fn run(root_env: Rc<RefCell<Environment>>) -> i32 {
+    println!("Rusty Scheme Interpreter");
+    println!("Press Ctrl+C to interrupt an evaluation, Ctrl+D to exit");
+    if let Err(e) = interrupt::install_handler() {
+        eprintln!("Could not install the Ctrl+C handler: {}", e);
+    }
+
+    let config = Config::builder().max_history_size(1000).expect("valid history size").history_ignore_dups(true).expect("valid history setting").build();
+    let mut rl: Editor<ReplHelper, FileHistory> = Editor::with_config(config).expect("failed to set up the line editor");
+    rl.set_helper(Some(ReplHelper { env: Rc::clone(&root_env), forced_refresh: Cell::new(false) }));
+    // Piped input is not worth remembering, and would crowd out the user's history
+    let history = history_path().filter(|_| std::io::stdin().is_terminal());
+    if let Some(path) = &history {
+        let _ = rl.load_history(path); // Missing on first run
+    }
+
+    let session = Session::new(Rc::clone(&root_env));
+    let mut exit_status = 0; // Set by `(exit n)`
+    'repl: loop {
+        match rl.readline("λ> ") {
+            Ok(entry) => {
+                if entry.trim().is_empty() {
+                    continue; // Skip empty lines
+                }
+                let _ = rl.add_history_entry(entry.as_str());
+                interrupt::clear();
+                limits::start();
+                eval::take_backtrace(); // Left by an error a builtin recovered from
+
+                if let Some((name, arg)) = split_command(&entry) {
+                    if let Some(status) = session.run_command(&mut rl, name, arg) {
+                        exit_status = status;
+                        break 'repl;
+                    }
+                    continue;
+                }
+
+                // Evaluate each datum in the entry: `(define x 1) x` gives two results
+                let mut rest = entry.as_str();
+                loop {
+                    match parser::parse_prefix(rest) {
+                        Ok(Some((expr, used))) => {
+                            rest = &rest[used..];
+                            let result = eval::evaluate(&expr, Rc::clone(&root_env));
+                            let interrupted = matches!(result, Err(SchemeError::Interrupted));
+                            if let Some(status) = session.print_result(result) {
+                                exit_status = status;
+                                break 'repl;
+                            }
+                            if interrupted {
+                                break; // Skip the rest of the entry too
+                            }
+                        }
+                        Ok(None) => break,
+                        Err(e) => {
+                            eprintln!("Parse Error: {}", e);
+                            break;
+                        }
+                    }
+                }
+            }
+            Err(ReadlineError::Interrupted) => {
+                continue; // Ctrl+C at the prompt discards the line
+            }
+            Err(ReadlineError::Eof) => {
+                println!("Exiting (Ctrl+D)");
+                break; // Exit on Ctrl+D
+            }
+            Err(err) => {
+                eprintln!("Readline Error: {:?}", err);
+                break;
+            }
+        }
+    }
+
+    if let Some(path) = &history {
+        save_history(rl.history_mut(), path);
+    }
+
+    exit_status
+}
@@ -1,94 +1,224 @@
+use crate::analyze::LambdaCode;
 use crate::env::Environment;
 use crate::error::{Result, SchemeError};
+use crate::eval::grow_stack;
 use std::cell::RefCell;
-use std::collections::HashMap;
+use crate::hashtable::HashTable;
+use crate::mapdata::MapData;
+use crate::port::PortRef;
+use crate::printer;
+use crate::symbol::Symbol;
+use crate::vm::Closure;
+use indexmap::IndexSet;
+use std::cmp::Ordering;
 use std::fmt;
+use std::hash::{Hash, Hasher};
 use std::rc::Rc;
 
 // Type alias for built-in functions
 pub type BuiltinFn = fn(&[Value], Rc<RefCell<Environment>>) -> Result<Value>;
 
-#[derive(Clone)]
 pub enum Value {
     Integer(i64),
     Bool(bool),
-    Symbol(String),
+    Char(char),
+    Symbol(Symbol), // Interned, so comparing two symbols is a pointer check
     String(String),
     Nil,
     List(Vec<Value>),
     Array(Rc<RefCell<Vec<Value>>>), // Rc for sharing, RefCell for interior mutability
-    Map(Rc<RefCell<HashMap<String, Value>>>), // Keys are strings, values are Values
+    Map(Rc<RefCell<MapData>>), // Keys are any hashable Value
+    HashTable(Rc<RefCell<HashTable>>), // SRFI-69 table with Scheme-level equality and hash procedures
+    Set(Rc<RefCell<SetData>>), // Insertion-ordered, elements compared like map keys
+    Bytevector(Rc<RefCell<Vec<u8>>>), // Mutable bytes, written #u8(1 2 3)
     Lambda {
         params: Rc<Vec<String>>,
         body: Rc<Value>, // Body is usually a single expression, often (begin ...)
+        code: Rc<LambdaCode>, // The lambda analyzed, whose body is what runs
         env: Rc<RefCell<Environment>>, // Closure environment
     },
     Builtin(BuiltinFn, String), // Store name for display
+    Closure(Rc<Closure>), // Procedure compiled for the bytecode VM; see vm.rs
+    Port(PortRef), // Input or output port; see port.rs
+    Eof, // The end-of-file object returned by input procedures
+    Error(Rc<SchemeError>), // Error object bound by `guard`
 }
 
-impl fmt::Debug for Value {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+// Lists nest by value, so cloning and dropping one recurses as deep as the
+// list does, and so does dropping the last reference to nested arrays; these
+// grow the stack like evaluation does (see `grow_stack`).
+impl Clone for Value {
+    fn clone(&self) -> Self {
         match self {
-            Value::Integer(n) => write!(f, "{}", n),
-            Value::Bool(b) => write!(f, "{}", if *b { "#t" } else { "#f" }),
-            Value::Symbol(s) => write!(f, "{}", s),
-            Value::String(s) => write!(f, "\"{}\"", s), // Display with quotes
-            Value::Nil => write!(f, "()"),
-            Value::List(lst) => {
-                let strs: Vec<String> = lst.iter().map(|v| format!("{:?}", v)).collect();
-                write!(f, "({})", strs.join(" "))
-            }
-            Value::Array(arr) => {
-                let borrowed = arr.borrow();
-                let strs: Vec<String> = borrowed.iter().map(|v| format!("{:?}", v)).collect();
-                write!(f, "[{}]", strs.join(", "))
+            Value::Integer(n) => Value::Integer(*n),
+            Value::Bool(b) => Value::Bool(*b),
+            Value::Char(c) => Value::Char(*c),
+            Value::Symbol(s) => Value::Symbol(s.clone()),
+            Value::String(s) => Value::String(s.clone()),
+            Value::Nil => Value::Nil,
+            Value::List(items) => Value::List(grow_stack(|| items.clone())),
+            Value::Array(arr) => Value::Array(Rc::clone(arr)),
+            Value::Map(map) => Value::Map(Rc::clone(map)),
+            Value::HashTable(table) => Value::HashTable(Rc::clone(table)),
+            Value::Set(set) => Value::Set(Rc::clone(set)),
+            Value::Bytevector(bytes) => Value::Bytevector(Rc::clone(bytes)),
+            Value::Lambda { params, body, code, env } => {
+                Value::Lambda { params: Rc::clone(params), body: Rc::clone(body), code: Rc::clone(code), env: Rc::clone(env) }
             }
-            Value::Map(map) => {
-                let borrowed = map.borrow();
-                let strs: Vec<String> = borrowed
-                    .iter()
-                    .map(|(k, v)| format!("{}: {:?}", k, v))
-                    .collect();
-                write!(f, "{{{}}}", strs.join(", "))
-            }
-            Value::Lambda { params, .. } => write!(f, "#<procedure:{}>", params.join(" ")),
-            Value::Builtin(_, name) => write!(f, "#<builtin:{}>", name),
+            Value::Builtin(func, name) => Value::Builtin(*func, name.clone()),
+            Value::Closure(closure) => Value::Closure(Rc::clone(closure)),
+            Value::Port(port) => Value::Port(Rc::clone(port)),
+            Value::Eof => Value::Eof,
+            Value::Error(err) => Value::Error(Rc::clone(err)),
         }
     }
 }
 
-// PartialEq for basic comparisons (useful for tests, equal?)
+impl Drop for Value {
+    fn drop(&mut self) {
+        let items = match self {
+            Value::List(items) => std::mem::take(items),
+            // The last reference to an array frees its elements here too
+            Value::Array(arr) if Rc::strong_count(arr) == 1 => std::mem::take(&mut *arr.borrow_mut()),
+            _ => return,
+        };
+        if !items.is_empty() {
+            grow_stack(|| drop(items));
+        }
+    }
+}
+
+// Debug is `write` output, so error messages show values as they read.
+impl fmt::Debug for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        printer::print(f, self, printer::Style::Write)
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        printer::print(f, self, printer::Style::Display)
+    }
+}
+
+// PartialEq is `equal?`: structural on data, identity on procedures and hash tables.
 impl PartialEq for Value {
     fn eq(&self, other: &Self) -> bool {
-        match (self, other) {
-            (Value::Integer(a), Value::Integer(b)) => a == b,
-            (Value::Bool(a), Value::Bool(b)) => a == b,
-            (Value::Symbol(a), Value::Symbol(b)) => a == b,
+        grow_stack(|| match (self, other) {
             (Value::String(a), Value::String(b)) => a == b,
-            (Value::Nil, Value::Nil) => true,
             (Value::List(a), Value::List(b)) => a == b, // Recursive PartialEq
-            (Value::Array(a), Value::Array(b)) => Rc::ptr_eq(a, b) || *a.borrow() == *b.borrow(), // Structural for arrays
-            (Value::Map(a), Value::Map(b)) => Rc::ptr_eq(a, b) || *a.borrow() == *b.borrow(), // Structural for maps
-            // Lambdas and Builtins are generally compared by identity (pointer equality) in Scheme (eq?)
-            // If we needed structural comparison for functions, it'd be complex.
-            _ => false, // Different types are not equal
+            (Value::Array(a), Value::Array(b)) => Rc::ptr_eq(a, b) || with_pair_guard(self, other, true, || read_both(a, b, |x, y| x == y).unwrap_or(false)), // Structural for arrays
+            (Value::Map(a), Value::Map(b)) => Rc::ptr_eq(a, b) || with_pair_guard(self, other, true, || read_both(a, b, |x, y| x == y).unwrap_or(false)), // Structural for maps
+            (Value::Set(a), Value::Set(b)) => Rc::ptr_eq(a, b) || with_pair_guard(self, other, true, || read_both(a, b, |x, y| x == y).unwrap_or(false)), // Same elements, any order
+            (Value::Bytevector(a), Value::Bytevector(b)) => *a.borrow() == *b.borrow(),
+            _ => eqv(self, other),
+        })
+    }
+}
+
+thread_local! {
+    // Container pairs whose comparison is in progress on this thread.
+    static COMPARING: RefCell<Vec<(usize, usize)>> = const { RefCell::new(Vec::new()) };
+}
+
+fn container_addr(value: &Value) -> usize {
+    match value {
+        Value::Array(arr) => arr.as_ptr() as *const () as usize,
+        Value::Map(map) => map.as_ptr() as *const () as usize,
+        Value::Set(set) => set.as_ptr() as *const () as usize,
+        _ => 0,
+    }
+}
+
+// Makes recursive comparison terminate on cyclic arrays, maps and sets: a
+// pair met again while it is still being compared is assumed to match
+// (`assumed`), which is the standard coinductive reading of `equal?`.
+fn with_pair_guard<T>(a: &Value, b: &Value, assumed: T, compare: impl FnOnce() -> T) -> T {
+    let pair = (container_addr(a), container_addr(b));
+    if COMPARING.with(|c| c.borrow().contains(&pair)) {
+        return assumed;
+    }
+    COMPARING.with(|c| c.borrow_mut().push(pair));
+    let result = compare();
+    COMPARING.with(|c| c.borrow_mut().pop());
+    result
+}
+
+// Compares the contents of two containers, or returns None when either is
+// mutably borrowed: a set or map that something is being inserted into, such
+// as itself, cannot be read and so compares by identity alone.
+fn read_both<T, R>(a: &RefCell<T>, b: &RefCell<T>, compare: impl FnOnce(&T, &T) -> R) -> Option<R> {
+    let (a, b) = (a.try_borrow().ok()?, b.try_borrow().ok()?);
+    Some(compare(&a, &b))
+}
+
+// `eqv?`: numbers, chars and booleans by value, symbols by interned
+// identity, and arrays, maps, sets, bytevectors, hash tables and procedures
+// by identity. Strings and non-empty lists are copied wherever they go in
+// this implementation, so they have no identity to compare and are never
+// `eqv?`, even to themselves; every empty list is the same one.
+// `eq?` is the same predicate: integers and chars are immediates here.
+pub fn eqv(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        (Value::Integer(a), Value::Integer(b)) => a == b,
+        (Value::Bool(a), Value::Bool(b)) => a == b,
+        (Value::Char(a), Value::Char(b)) => a == b,
+        (Value::Symbol(a), Value::Symbol(b)) => a == b,
+        (Value::Nil, Value::Nil) => true,
+        // The empty list is written `()` but can also come out of builtins as an empty List
+        (Value::Nil, Value::List(l)) | (Value::List(l), Value::Nil) => l.is_empty(),
+        (Value::List(a), Value::List(b)) => a.is_empty() && b.is_empty(),
+        (Value::Array(a), Value::Array(b)) => Rc::ptr_eq(a, b),
+        (Value::Map(a), Value::Map(b)) => Rc::ptr_eq(a, b),
+        (Value::Set(a), Value::Set(b)) => Rc::ptr_eq(a, b),
+        (Value::Bytevector(a), Value::Bytevector(b)) => Rc::ptr_eq(a, b),
+        (Value::HashTable(a), Value::HashTable(b)) => Rc::ptr_eq(a, b),
+        (Value::Lambda { params: p1, body: b1, env: e1, .. }, Value::Lambda { params: p2, body: b2, env: e2, .. }) => {
+            Rc::ptr_eq(p1, p2) && Rc::ptr_eq(b1, b2) && Rc::ptr_eq(e1, e2)
         }
+        (Value::Builtin(f1, _), Value::Builtin(f2, _)) => std::ptr::fn_addr_eq(*f1, *f2),
+        (Value::Closure(a), Value::Closure(b)) => Rc::ptr_eq(a, b),
+        (Value::Port(a), Value::Port(b)) => Rc::ptr_eq(a, b),
+        (Value::Eof, Value::Eof) => true,
+        (Value::Error(a), Value::Error(b)) => Rc::ptr_eq(a, b),
+        _ => false, // Different types are not equal
     }
 }
 
 impl Value {
+    pub fn symbol(name: &str) -> Value {
+        Value::Symbol(Symbol::intern(name))
+    }
+
+    // True for the empty list, whether written `()` or built as an empty List.
+    pub fn is_null(&self) -> bool {
+        match self {
+            Value::Nil => true,
+            Value::List(items) => items.is_empty(),
+            _ => false,
+        }
+    }
+
     pub fn type_name(&self) -> String {
         match self {
             Value::Integer(_) => "integer".to_string(),
             Value::Bool(_) => "boolean".to_string(),
+            Value::Char(_) => "char".to_string(),
             Value::Symbol(_) => "symbol".to_string(),
             Value::String(_) => "string".to_string(),
             Value::Nil => "nil".to_string(),
             Value::List(_) => "list".to_string(),
             Value::Array(_) => "array".to_string(),
             Value::Map(_) => "map".to_string(),
+            Value::HashTable(_) => "hash-table".to_string(),
+            Value::Set(_) => "set".to_string(),
+            Value::Bytevector(_) => "bytevector".to_string(),
             Value::Lambda { .. } => "procedure".to_string(),
             Value::Builtin(_, _) => "procedure".to_string(),
+            Value::Closure(_) => "procedure".to_string(),
+            Value::Port(_) => "port".to_string(),
+            Value::Eof => "eof-object".to_string(),
+            Value::Error(_) => "error-object".to_string(),
         }
     }
 
@@ -99,4 +229,168 @@ impl Value {
             found: found.type_name(),
         }
     }
+}
+
+// Storage behind `Value::Set`; insertion-ordered like `MapData::Insertion`.
+pub type SetData = IndexSet<MapKey>;
+
+// Names used when printing (and reading) character literals like #\space.
+pub const CHAR_NAMES: &[(&str, char)] = &[
+    ("space", ' '), ("newline", '\n'), ("tab", '\t'), ("return", '\r'),
+    ("nul", '\0'), ("null", '\0'), ("alarm", '\u{7}'), ("backspace", '\u{8}'),
+    ("delete", '\u{7f}'), ("escape", '\u{1b}'),
+];
+
+pub fn char_name(c: char) -> Option<&'static str> {
+    CHAR_NAMES.iter().find(|(_, ch)| *ch == c).map(|(name, _)| *name)
+}
+
+// A map key. Equality is `equal?`, so lists and arrays compare
+// structurally and procedures and other opaque objects by identity.
+// Mutating an array or map after using it as a key leaves the entry
+// stranded under its old hash, as with any structurally hashed key.
+#[derive(Clone, Debug)]
+pub struct MapKey(pub Value);
+
+impl PartialEq for MapKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.0 == other.0
+    }
+}
+
+impl Eq for MapKey {}
+
+impl Hash for MapKey {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        hash_value(&self.0, state);
+    }
+}
+
+// How many levels of nested arrays and lists feed a hash. Cutting off
+// deeper structure keeps hashing cyclic arrays finite and stays consistent
+// with equality, since equal values agree on every level.
+const HASH_DEPTH: usize = 4;
+
+// Structural hash consistent with `MapKey` equality.
+pub fn hash_value<H: Hasher>(value: &Value, state: &mut H) {
+    hash_to_depth(value, state, HASH_DEPTH)
+}
+
+fn hash_to_depth<H: Hasher>(value: &Value, state: &mut H, depth: usize) {
+    if matches!(value, Value::List(items) if items.is_empty()) {
+        return hash_to_depth(&Value::Nil, state, depth); // Same key as ()
+    }
+    std::mem::discriminant(value).hash(state);
+    match value {
+        Value::Integer(n) => n.hash(state),
+        Value::Bool(b) => b.hash(state),
+        Value::Char(c) => c.hash(state),
+        Value::Symbol(s) => s.hash(state),
+        Value::String(s) => s.hash(state),
+        Value::Nil => {}
+        Value::List(_) | Value::Array(_) if depth == 0 => {}
+        Value::List(items) => items.iter().for_each(|v| hash_to_depth(v, state, depth - 1)),
+        Value::Array(arr) => arr.borrow().iter().for_each(|v| hash_to_depth(v, state, depth - 1)),
+        // Maps and sets hash by type alone: their entry order is unspecified,
+        // and a set may be hashed while it is being inserted into itself.
+        Value::Map(_) | Value::Set(_) => {}
+        Value::Bytevector(bytes) => bytes.borrow().hash(state),
+        Value::HashTable(table) => Rc::as_ptr(table).hash(state),
+        Value::Lambda { body, .. } => Rc::as_ptr(body).hash(state),
+        Value::Builtin(func, _) => (*func as usize).hash(state),
+        Value::Closure(closure) => Rc::as_ptr(closure).hash(state),
+        Value::Port(port) => Rc::as_ptr(port).hash(state),
+        Value::Eof => {}
+        Value::Error(err) => Rc::as_ptr(err).hash(state),
+    }
+}
+
+impl PartialOrd for MapKey {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for MapKey {
+    fn cmp(&self, other: &Self) -> Ordering {
+        compare_values(&self.0, &other.0)
+    }
+}
+
+// Total order over values, used by sorted maps and range queries.
+// Values of different types order by type; within a type by content,
+// with sequences compared lexicographically and opaque objects by identity.
+pub fn compare_values(a: &Value, b: &Value) -> Ordering {
+    fn rank(v: &Value) -> u8 {
+        match v {
+            Value::Nil => 0,
+            Value::List(items) if items.is_empty() => 0, // Orders (and compares equal) as ()
+            Value::Bool(_) => 1,
+            Value::Integer(_) => 2,
+            Value::Char(_) => 3,
+            Value::String(_) => 4,
+            Value::Symbol(_) => 5,
+            Value::List(_) => 6,
+            Value::Array(_) => 7,
+            Value::Map(_) => 8,
+            Value::Set(_) => 9,
+            Value::HashTable(_) => 10,
+            Value::Lambda { .. } => 11,
+            Value::Builtin(..) => 12,
+            Value::Port(_) => 13,
+            Value::Eof => 14,
+            Value::Error(_) => 15,
+            Value::Bytevector(_) => 16,
+            Value::Closure(_) => 17,
+        }
+    }
+    fn compare_seqs(a: &[Value], b: &[Value]) -> Ordering {
+        a.iter()
+            .zip(b)
+            .map(|(x, y)| grow_stack(|| compare_values(x, y)))
+            .find(|o| o.is_ne())
+            .unwrap_or_else(|| a.len().cmp(&b.len()))
+    }
+    // Containers that cannot be read right now order by identity
+    fn by_address(a: &Value, b: &Value) -> Ordering {
+        container_addr(a).cmp(&container_addr(b))
+    }
+    match (a, b) {
+        (Value::Integer(x), Value::Integer(y)) => x.cmp(y),
+        (Value::Bool(x), Value::Bool(y)) => x.cmp(y),
+        (Value::Char(x), Value::Char(y)) => x.cmp(y),
+        (Value::String(x), Value::String(y)) => x.cmp(y),
+        (Value::Symbol(x), Value::Symbol(y)) => x.cmp(y),
+        (Value::List(x), Value::List(y)) => compare_seqs(x, y),
+        (Value::Array(x), Value::Array(y)) => with_pair_guard(a, b, Ordering::Equal, || {
+            read_both(x, y, |x, y| compare_seqs(x, y)).unwrap_or_else(|| by_address(a, b))
+        }),
+        (Value::Map(x), Value::Map(y)) => with_pair_guard(a, b, Ordering::Equal, || {
+            // Compare as sorted entry lists so that key order does not matter
+            let sorted = |m: &MapData| {
+                let mut entries: Vec<Value> = m.iter().map(|(k, v)| Value::List(vec![k.0.clone(), v.clone()])).collect();
+                entries.sort_by(compare_values);
+                entries
+            };
+            read_both(x, y, |x, y| compare_seqs(&sorted(x), &sorted(y))).unwrap_or_else(|| by_address(a, b))
+        }),
+        (Value::Set(x), Value::Set(y)) => with_pair_guard(a, b, Ordering::Equal, || {
+            let sorted = |s: &SetData| {
+                let mut elems: Vec<Value> = s.iter().map(|k| k.0.clone()).collect();
+                elems.sort_by(compare_values);
+                elems
+            };
+            read_both(x, y, |x, y| compare_seqs(&sorted(x), &sorted(y))).unwrap_or_else(|| by_address(a, b))
+        }),
+        (Value::Bytevector(x), Value::Bytevector(y)) => x.borrow().cmp(&y.borrow()),
+        (Value::HashTable(x), Value::HashTable(y)) => Rc::as_ptr(x).cmp(&Rc::as_ptr(y)),
+        (Value::Lambda { params: p1, body: b1, env: e1, .. }, Value::Lambda { params: p2, body: b2, env: e2, .. }) => {
+            (Rc::as_ptr(b1), Rc::as_ptr(e1), Rc::as_ptr(p1)).cmp(&(Rc::as_ptr(b2), Rc::as_ptr(e2), Rc::as_ptr(p2)))
+        }
+        (Value::Builtin(f1, _), Value::Builtin(f2, _)) => (*f1 as usize).cmp(&(*f2 as usize)),
+        (Value::Closure(x), Value::Closure(y)) => Rc::as_ptr(x).cmp(&Rc::as_ptr(y)),
+        (Value::Port(x), Value::Port(y)) => Rc::as_ptr(x).cmp(&Rc::as_ptr(y)),
+        (Value::Error(x), Value::Error(y)) => Rc::as_ptr(x).cmp(&Rc::as_ptr(y)),
+        _ => rank(a).cmp(&rank(b)),
+    }
 }
\ No newline at end of file
@@ -0,0 +1,450 @@
+use crate::env::Environment;
+use crate::error::{Result, SchemeError};
+use crate::eval;
+use crate::interrupt;
+use crate::limits;
+use crate::printer::{self, Style};
+use crate::value::{eqv, Value};
+use std::cell::RefCell;
+use std::fmt::Write;
+use std::rc::Rc;
+
+// The bytecode back end, selected with `--vm`: `compile` turns analyzed code
+// into `Proto`s, and this module runs them on a stack machine.
+//
+// Each call has a frame of local slots (parameters, internal defines and
+// `guard` variables) and shares one operand stack. A lambda captures the
+// variables it uses from enclosing procedures by copying them into its
+// closure; a captured variable that is ever assigned lives in a box shared by
+// every closure that captured it. Globals are looked up by name in the
+// environment the code was compiled for, as in the tree-walker. Tail calls
+// replace the caller's frame, and non-tail calls push frames on the heap, so
+// compiled code does not recurse on the Rust stack.
+
+#[derive(Clone, Copy)]
+pub enum Op {
+    Const(u32),        // Push constant
+    Local(u32),        // Push local slot
+    SetLocal(u32),     // Pop into local slot (through its box, if boxed)
+    BindLocal(u32),    // Pop into local slot as a fresh binding
+    BoxLocal(u32),     // Move the local's value into a new box
+    NewBox(u32),       // Bind the local to a new box, not yet assigned
+    Free(u32),         // Push captured variable
+    SetFree(u32),      // Pop into captured (boxed) variable
+    Global(u32),       // Push global named by a constant
+    SetGlobal(u32),    // Pop into existing global
+    DefineGlobal(u32), // Pop into new or existing global
+    Closure(u32),      // Push a closure of a nested proto
+    Pop,
+    Dup,
+    Jump(u32),
+    JumpIfFalse(u32), // Pop, and jump if it is #f
+    Case(u32, u32),   // Jump if the value on top is eqv? to one of the data in a constant list
+    Call(u32, u32),   // Call with n arguments, the operator's name a constant (or NO_NAME)
+    TailCall(u32, u32),
+    Return,
+    PushHandler(u32), // Until PopHandler, an error jumps to the target with the error on the stack
+    PopHandler,
+    Raise, // Pop an error object and raise its error
+}
+
+pub const NO_NAME: u32 = u32::MAX;
+
+// How a closure gets each captured variable from the procedure creating it.
+#[derive(Clone, Copy)]
+pub enum Capture {
+    Local(u32),
+    Free(u32),
+}
+
+// A compiled lambda expression (or top-level expression).
+pub struct Proto {
+    pub params: Rc<Vec<String>>,
+    pub source: Rc<Value>, // The body as written
+    pub code: Vec<Op>,
+    pub constants: Vec<Value>,
+    pub protos: Vec<Rc<Proto>>,
+    pub local_names: Vec<String>, // Parameters first
+    pub captures: Vec<Capture>,
+    pub free_names: Vec<String>,
+}
+
+pub struct Closure {
+    pub proto: Rc<Proto>,
+    pub free: Vec<Local>,
+    pub env: Rc<RefCell<Environment>>, // Where its globals are
+}
+
+#[derive(Clone)]
+pub enum Local {
+    Unassigned, // An internal define not yet run
+    Value(Value),
+    Boxed(Rc<RefCell<Option<Value>>>),
+}
+
+impl Local {
+    pub fn get(&self) -> Option<Value> {
+        match self {
+            Local::Unassigned => None,
+            Local::Value(value) => Some(value.clone()),
+            Local::Boxed(cell) => cell.borrow().clone(),
+        }
+    }
+
+    fn set(&mut self, value: Value) {
+        match self {
+            Local::Boxed(cell) => *cell.borrow_mut() = Some(value),
+            _ => *self = Local::Value(value),
+        }
+    }
+}
+
+struct Frame {
+    closure: Rc<Closure>,
+    pc: usize,
+    base: usize,       // Index of local slot 0
+    stack_base: usize, // Operand stack height on entry
+    op: Option<Value>, // The operator called, for backtraces; None for top-level code
+    _nested: limits::Nested,
+}
+
+struct Handler {
+    frames: usize, // Frame count when pushed: the handler's frame is the last
+    stack: usize,
+    locals: usize,
+    target: usize,
+}
+
+#[derive(Default)]
+struct Machine {
+    stack: Vec<Value>,
+    locals: Vec<Local>,
+    frames: Vec<Frame>,
+    handlers: Vec<Handler>,
+}
+
+// Runs compiled top-level code in `env`.
+pub fn run(proto: Rc<Proto>, env: Rc<RefCell<Environment>>) -> Result<Value> {
+    let closure = Rc::new(Closure { proto, free: Vec::new(), env });
+    call(&closure, Vec::new(), None)
+}
+
+// Calls a compiled procedure with already-evaluated arguments, running it to
+// completion. Called by builtins through `eval::apply`, so it may nest on
+// the Rust stack, which grows as it does for the tree-walker.
+pub fn call(closure: &Rc<Closure>, args: Vec<Value>, op: Option<Value>) -> Result<Value> {
+    eval::grow_stack(|| {
+        let mut machine = Machine::default();
+        machine.stack.push(Value::Closure(Rc::clone(closure)));
+        let argc = args.len();
+        machine.stack.extend(args);
+        let result = machine.call_closure(Rc::clone(closure), argc, op, false).and_then(|()| machine.execute());
+        if result.is_err() {
+            machine.record_backtrace();
+        }
+        result
+    })
+}
+
+fn unassigned(name: &str) -> SchemeError {
+    SchemeError::UndefinedVariable(name.to_string())
+}
+
+fn global_name(constant: &Value) -> &str {
+    match constant {
+        Value::Symbol(name) => name.as_str(),
+        _ => unreachable!("global names are symbol constants"),
+    }
+}
+
+impl Machine {
+    // Runs until the first frame returns, handling errors raised under a `guard`.
+    fn execute(&mut self) -> Result<Value> {
+        loop {
+            let err = match self.dispatch() {
+                Ok(value) => return Ok(value),
+                Err(err) => err,
+            };
+            let Some(handler) = self.handlers.pop().filter(|_| !err.escapes_guard()) else {
+                return Err(err);
+            };
+            eval::take_backtrace(); // Handled here, so no longer of interest
+            self.frames.truncate(handler.frames);
+            self.locals.truncate(handler.locals);
+            self.stack.truncate(handler.stack);
+            self.frames.last_mut().expect("a handler belongs to a live frame").pc = handler.target;
+            self.stack.push(Value::Error(Rc::new(err)));
+        }
+    }
+
+    fn current(&self) -> (Rc<Closure>, usize, usize) {
+        let frame = self.frames.last().expect("running code has a frame");
+        (Rc::clone(&frame.closure), frame.pc, frame.base)
+    }
+
+    fn pop(&mut self) -> Value {
+        self.stack.pop().expect("operand stack underflow")
+    }
+
+    fn dispatch(&mut self) -> Result<Value> {
+        let (mut closure, mut pc, mut base) = self.current();
+        loop {
+            let op = closure.proto.code[pc];
+            pc += 1;
+            match op {
+                Op::Const(i) => self.stack.push(closure.proto.constants[i as usize].clone()),
+                Op::Local(slot) => {
+                    let value = self.locals[base + slot as usize].get();
+                    self.stack.push(value.ok_or_else(|| unassigned(&closure.proto.local_names[slot as usize]))?);
+                }
+                Op::SetLocal(slot) => {
+                    let value = self.pop();
+                    self.locals[base + slot as usize].set(value);
+                }
+                Op::BindLocal(slot) => self.locals[base + slot as usize] = Local::Value(self.pop()),
+                Op::BoxLocal(slot) => {
+                    let local = &mut self.locals[base + slot as usize];
+                    *local = Local::Boxed(Rc::new(RefCell::new(local.get())));
+                }
+                Op::NewBox(slot) => self.locals[base + slot as usize] = Local::Boxed(Rc::new(RefCell::new(None))),
+                Op::Free(i) => {
+                    let value = closure.free[i as usize].get();
+                    self.stack.push(value.ok_or_else(|| unassigned(&closure.proto.free_names[i as usize]))?);
+                }
+                Op::SetFree(i) => match &closure.free[i as usize] {
+                    Local::Boxed(cell) => *cell.borrow_mut() = Some(self.pop()),
+                    _ => unreachable!("assigned captured variables are boxed"),
+                },
+                Op::Global(i) => {
+                    let name = global_name(&closure.proto.constants[i as usize]);
+                    let value = closure.env.borrow().lookup(name);
+                    self.stack.push(value.ok_or_else(|| SchemeError::UndefinedVariable(name.to_string()))?);
+                }
+                Op::SetGlobal(i) => {
+                    let value = self.pop();
+                    closure.env.borrow_mut().set(global_name(&closure.proto.constants[i as usize]), value)?;
+                }
+                Op::DefineGlobal(i) => {
+                    let value = self.pop();
+                    closure
+                        .env
+                        .borrow_mut()
+                        .define(global_name(&closure.proto.constants[i as usize]).to_string(), value);
+                }
+                Op::Closure(i) => {
+                    let proto = Rc::clone(&closure.proto.protos[i as usize]);
+                    let free = proto
+                        .captures
+                        .iter()
+                        .map(|capture| match *capture {
+                            Capture::Local(slot) => self.locals[base + slot as usize].clone(),
+                            Capture::Free(j) => closure.free[j as usize].clone(),
+                        })
+                        .collect();
+                    self.stack.push(Value::Closure(Rc::new(Closure { proto, free, env: Rc::clone(&closure.env) })));
+                }
+                Op::Pop => {
+                    self.pop();
+                }
+                Op::Dup => {
+                    let top = self.stack.last().expect("operand stack underflow").clone();
+                    self.stack.push(top);
+                }
+                Op::Jump(target) => pc = target as usize,
+                Op::JumpIfFalse(target) => {
+                    if matches!(self.pop(), Value::Bool(false)) {
+                        pc = target as usize;
+                    }
+                }
+                Op::Case(data, target) => {
+                    let key = self.stack.last().expect("operand stack underflow");
+                    if let Value::List(data) = &closure.proto.constants[data as usize]
+                        && data.iter().any(|datum| eqv(datum, key))
+                    {
+                        pc = target as usize;
+                    }
+                }
+                Op::Call(argc, name) | Op::TailCall(argc, name) => {
+                    let tail = matches!(op, Op::TailCall(..));
+                    self.frames.last_mut().expect("running code has a frame").pc = pc;
+                    let argc = argc as usize;
+                    let proc = self.stack[self.stack.len() - argc - 1].clone();
+                    let op_name = match name {
+                        NO_NAME => proc.clone(),
+                        name => closure.proto.constants[name as usize].clone(),
+                    };
+                    match &proc {
+                        Value::Closure(callee) if eval::traced_name(&proc).is_none() => {
+                            self.call_closure(Rc::clone(callee), argc, Some(op_name), tail)?;
+                        }
+                        _ => {
+                            let args = self.stack.split_off(self.stack.len() - argc);
+                            self.pop(); // The procedure
+                            let result = match &proc {
+                                Value::Builtin(func, _) if eval::traced_name(&proc).is_none() => {
+                                    let result = eval::call_builtin(*func, &args, Rc::clone(&closure.env));
+                                    if result.is_err() {
+                                        eval::record_call(|| eval::call_form(&op_name, args));
+                                    }
+                                    result?
+                                }
+                                // Traced procedures, and tree-walker lambdas made by `eval` elsewhere
+                                _ => eval::apply(&proc, args, Rc::clone(&closure.env))?,
+                            };
+                            self.stack.push(result);
+                            if !tail {
+                                continue;
+                            }
+                            if let Some(value) = self.return_from_frame() {
+                                return Ok(value);
+                            }
+                        }
+                    }
+                    (closure, pc, base) = self.current();
+                }
+                Op::Return => {
+                    if let Some(value) = self.return_from_frame() {
+                        return Ok(value);
+                    }
+                    (closure, pc, base) = self.current();
+                }
+                Op::PushHandler(target) => self.handlers.push(Handler {
+                    frames: self.frames.len(),
+                    stack: self.stack.len(),
+                    locals: self.locals.len(),
+                    target: target as usize,
+                }),
+                Op::PopHandler => {
+                    self.handlers.pop();
+                }
+                Op::Raise => match &self.pop() {
+                    Value::Error(err) => return Err((**err).clone()),
+                    other => unreachable!("raised a non-error {:?}", other),
+                },
+            }
+        }
+    }
+
+    // Pops the current frame, passing the value on top of the stack to its
+    // caller. Returns the value if that was the first frame.
+    fn return_from_frame(&mut self) -> Option<Value> {
+        let value = self.pop();
+        let frame = self.frames.pop().expect("running code has a frame");
+        self.locals.truncate(frame.base);
+        self.stack.truncate(frame.stack_base);
+        if self.frames.is_empty() {
+            return Some(value);
+        }
+        self.stack.push(value);
+        None
+    }
+
+    // Calls `callee` with the `argc` arguments on top of the stack, above the
+    // procedure itself. A tail call replaces the current frame.
+    fn call_closure(&mut self, callee: Rc<Closure>, argc: usize, op: Option<Value>, tail: bool) -> Result<()> {
+        interrupt::check()?; // Ctrl-C in the REPL
+        limits::step()?;
+        let proto = Rc::clone(&callee.proto);
+        if proto.params.len() != argc {
+            if let Some(op) = &op {
+                eval::record_call(|| eval::call_form(op, self.stack[self.stack.len() - argc..].iter().cloned()));
+            }
+            return Err(SchemeError::Arity { expected: format!("{}", proto.params.len()), got: argc });
+        }
+        let (nested, stack_base) = match tail {
+            true => {
+                let frame = self.frames.pop().expect("a tail call replaces a frame");
+                self.locals.truncate(frame.base);
+                (frame._nested, frame.stack_base)
+            }
+            false => (limits::enter()?, self.stack.len() - argc - 1),
+        };
+        let base = self.locals.len();
+        let args_start = self.stack.len() - argc;
+        self.locals.extend(self.stack.drain(args_start..).map(Local::Value));
+        self.locals.resize(base + proto.local_names.len(), Local::Unassigned);
+        self.stack.truncate(stack_base);
+        self.frames.push(Frame { closure: callee, pc: 0, base, stack_base, op, _nested: nested });
+        Ok(())
+    }
+
+    // Records the calls still on the stack, innermost first, for the error unwinding them.
+    fn record_backtrace(&self) {
+        for frame in self.frames.iter().rev() {
+            if let Some(op) = &frame.op {
+                let params = frame.closure.proto.params.len();
+                let args =
+                    self.locals[frame.base..frame.base + params].iter().map(|local| local.get().unwrap_or(Value::Nil));
+                eval::record_call(|| eval::call_form(op, args));
+            }
+        }
+    }
+}
+
+// --- Disassembler ---
+// `,disasm` lists a procedure's instructions, then those of the lambdas
+// nested in it.
+
+pub fn disassemble(proto: &Proto) -> String {
+    let mut out = String::new();
+    write_proto(&mut out, proto, "");
+    out
+}
+
+fn write_proto(out: &mut String, proto: &Proto, label: &str) {
+    let _ = writeln!(
+        out,
+        "{}(lambda ({}) ...): {} locals ({}), {} free ({})",
+        label,
+        proto.params.join(" "),
+        proto.local_names.len(),
+        proto.local_names.join(" "),
+        proto.free_names.len(),
+        proto.free_names.join(" ")
+    );
+    for (pc, op) in proto.code.iter().enumerate() {
+        let _ = writeln!(out, "  {:4}  {}", pc, instruction(proto, *op));
+    }
+    for (i, nested) in proto.protos.iter().enumerate() {
+        let _ = writeln!(out);
+        write_proto(out, nested, &format!("{}{}: ", label, i));
+    }
+}
+
+fn instruction(proto: &Proto, op: Op) -> String {
+    let constant = |i: u32| printer::to_string(&proto.constants[i as usize], Style::Write);
+    let local = |slot: u32| format!("{} ; {}", slot, proto.local_names[slot as usize]);
+    let name = |i: u32| {
+        if i == NO_NAME {
+            String::new()
+        } else {
+            format!(" ; {}", constant(i))
+        }
+    };
+    let (mnemonic, operands) = match op {
+        Op::Const(i) => ("const", format!("{} ; {}", i, constant(i))),
+        Op::Local(slot) => ("local", local(slot)),
+        Op::SetLocal(slot) => ("set-local", local(slot)),
+        Op::BindLocal(slot) => ("bind-local", local(slot)),
+        Op::BoxLocal(slot) => ("box-local", local(slot)),
+        Op::NewBox(slot) => ("new-box", local(slot)),
+        Op::Free(i) => ("free", format!("{} ; {}", i, proto.free_names[i as usize])),
+        Op::SetFree(i) => ("set-free", format!("{} ; {}", i, proto.free_names[i as usize])),
+        Op::Global(i) => ("global", constant(i)),
+        Op::SetGlobal(i) => ("set-global", constant(i)),
+        Op::DefineGlobal(i) => ("define-global", constant(i)),
+        Op::Closure(i) => ("closure", format!("{} ; (lambda ({}) ...)", i, proto.protos[i as usize].params.join(" "))),
+        Op::Pop => ("pop", String::new()),
+        Op::Dup => ("dup", String::new()),
+        Op::Jump(target) => ("jump", target.to_string()),
+        Op::JumpIfFalse(target) => ("jump-if-false", target.to_string()),
+        Op::Case(data, target) => ("case", format!("{} {}", constant(data), target)),
+        Op::Call(argc, i) => ("call", format!("{}{}", argc, name(i))),
+        Op::TailCall(argc, i) => ("tail-call", format!("{}{}", argc, name(i))),
+        Op::Return => ("return", String::new()),
+        Op::PushHandler(target) => ("push-handler", target.to_string()),
+        Op::PopHandler => ("pop-handler", String::new()),
+        Op::Raise => ("raise", String::new()),
+    };
+    format!("{:<14}{}", mnemonic, operands).trim_end().to_string()
+}
@@ -128,10 +128,35 @@
     (lambda (x) ; Parameter shadows global x
       (define y 20) ; Local define
       (+ x y)))
-  (assert-equal? 15 (lambda-scope-test 5) "lambda local scope")
+  (assert-equal? 25 (lambda-scope-test 5) "lambda local scope")
   (assert-equal? 100 x "lambda does not change global x")
   ;; TODO: Test if y is accessible globally (it shouldn't be) - needs error catching
 
+  ;; Internal defines and variables of enclosing procedures
+  (define (parity n)
+    (define (ev? n) (if (= n 0) 'even (od? (- n 1))))
+    (define (od? n) (if (= n 0) 'odd (ev? (- n 1))))
+    (ev? n))
+  (assert-equal? 'odd (parity 7) "internal defines refer to each other")
+  (define (make-counter)
+    (define count 0)
+    (lambda () (set! count (+ count 1)) count))
+  (define counter (make-counter))
+  (counter)
+  (assert-equal? 2 (counter) "set! of an enclosing procedure's variable")
+  (define (nested a) (lambda (b) (lambda (c) (list a b c))))
+  (assert-equal? '(1 2 3) (((nested 1) 2) 3) "variables two frames up")
+  (define (when-define flag) (when flag (define inner 'set)) (if flag inner 'unset))
+  (assert-equal? 'set (when-define #t) "define nested in a body")
+  (assert-equal? '(2 1) (guard (e (#t (define pair (list 2 1)) pair)) (car '())) "define in a guard clause")
+  (assert-equal? 9 (eval '((lambda (a) (define b (* a 2)) (+ a b)) 3)) "eval of a lambda with an internal define")
+  (define (eval-in-body eval-probe) (eval 'eval-probe))
+  (define eval-probe 'global)
+  (assert-equal? 'global (eval-in-body 7) "eval sees globals, not the caller's variables")
+  (guard (e (#t (define handler-only e))) (car '()))
+  (assert-equal? 'undefined (guard (e (#t 'undefined)) handler-only) "a define in a top-level guard clause stays in the handler")
+  (guard (e (#t #f)) (define guard-body-global 'defined))
+  (assert-equal? 'defined guard-body-global "a define in a top-level guard body is global")
 )
 
 ;; --- Tail Call Optimization (TCO) ---
@@ -186,14 +211,29 @@
   (assert-equal? 3 (array-length arr-made) "make-array length")
   (assert-equal? 'fill (array-ref arr-made 0) "make-array fill value 1")
   (assert-equal? 'fill (array-ref arr-made 2) "make-array fill value 2")
+
+  (define arr-ends [1, 2, 3])
+  (assert-equal? 3 (array-ref/from-end arr-ends -1) "array-ref/from-end last")
+  (assert-equal? 1 (array-ref/from-end arr-ends -3) "array-ref/from-end first")
+  (assert-equal? 2 (array-ref/from-end arr-ends 1) "array-ref/from-end non-negative")
+  (array-set!/from-end arr-ends -1 30)
+  (assert-equal? 30 (array-ref arr-ends 2) "array-set!/from-end last")
+  (assert-equal? 0 (array-length (make-array 0)) "make-array zero length")
 )
 
+;; --- Need a list-length helper for map tests ---
+(define list-length
+  (lambda (lst)
+    (if (null? lst)
+        0
+        (+ 1 (list-length (cdr lst))))))
+
 ;; --- Map Literals and Functions ---
 (begin
   (display "Testing Maps...") (newline)
   (define map1 { name: "Alice", age: 30, active: #t })
   (assert-equal? #t (map? map1) "map? true")
-  (assert-equal? #f (map? [1 2]) "map? false")
+  (assert-equal? #f (map? [1, 2]) "map? false")
   (assert-equal? #t (equal? map1 { name: "Alice", age: 30, active: #t }) "map literal equality")
   (assert-equal? #t (equal? map1 { age: 30, active: #t, name: "Alice" }) "map literal equality (order)") ; Rc<RefCell<HashMap>> equality handles order
   (assert-equal? #f (equal? map1 { name: "Alice", age: 31, active: #t }) "map literal inequality")
@@ -211,7 +251,6 @@
   (define map-keys-list (map-keys map1))
   (assert-equal? #t (list? map-keys-list) "map-keys returns list")
   (assert-equal? 4 (list-length map-keys-list) "map-keys correct number") ; Need list-length helper or builtin
-  ;; Checking exact keys is tricky due to order, just check type and count for now
 
   (define map-empty {})
   (assert-equal? 0 (list-length (map-keys map-empty)) "map empty keys") ; Needs list-length
@@ -227,6 +266,426 @@
   (assert-equal? 'value (map-ref map-made 'key) "make-map set/ref")
 )
 
+;; --- Map API ---
+(begin
+  (display "Testing Map API...") (newline)
+  (define m (make-map 'a 1 'b 2))
+  (assert-equal? 2 (map-count m) "make-map with key/value pairs")
+  (assert-equal? 2 (map-count (make-map '((x 1) (y 2)))) "make-map with alist")
+  (define ordered-keys { name: "Alice", age: 30, active: #t })
+  (map-set! ordered-keys 'city "Paris")
+  (assert-equal? '(name age active city) (map-keys ordered-keys) "map-keys insertion order")
+  (assert-equal? #t (map-has? m 'a) "map-has? present")
+  (assert-equal? #f (map-has? m 'zz) "map-has? missing")
+  (map-set! m 'nil-val '())
+  (assert-equal? #t (map-has? m 'nil-val) "map-has? distinguishes stored ()")
+  (assert-equal? 'dflt (map-ref m 'zz 'dflt) "map-ref fallback value")
+  (assert-equal? 42 (map-ref m 'zz (lambda () 42)) "map-ref fallback thunk")
+  (assert-equal? 1 (map-ref m 'a 'dflt) "map-ref fallback unused")
+  (map-delete! m 'nil-val)
+  (assert-equal? #f (map-has? m 'nil-val) "map-delete!")
+  (map-update! m 'a (lambda (v) (+ v 10)))
+  (assert-equal? 11 (map-ref m 'a) "map-update! existing")
+  (map-update! m 'c (lambda (v) (+ v 1)) 0)
+  (assert-equal? 1 (map-ref m 'c) "map-update! default")
+  (assert-equal? '((k 5)) (map->alist { k: 5 }) "map->alist")
+  (assert-equal? #t (equal? { k: 5 } (alist->map '((k 5)))) "alist->map")
+  (assert-equal? '(5) (map-values { k: 5 }) "map-values")
+  (assert-equal? #t (equal? { a: 10 } (map-map (lambda (k v) (* v 10)) { a: 1 })) "map-map")
+  (assert-equal? #t (equal? { b: 2 } (map-filter (lambda (k v) (> v 1)) { a: 1, b: 2 })) "map-filter")
+  (assert-equal? #t (equal? { a: 1, b: 3 } (map-merge { a: 1, b: 2 } { b: 3 })) "map-merge later wins")
+  (define walked 0)
+  (map-for-each (lambda (k v) (set! walked (+ walked v))) { a: 1, b: 2 })
+  (assert-equal? 3 walked "map-for-each")
+)
+
+;; --- Map Keys and Hash Tables ---
+(begin
+  (display "Testing Map Keys and Hash Tables...") (newline)
+  (define keyed { a: 1, "a": 2, 3: three, #\x: ex }) ; literal values are not evaluated
+  (assert-equal? 1 (map-ref keyed 'a) "symbol key")
+  (assert-equal? 2 (map-ref keyed "a") "string key distinct from symbol")
+  (assert-equal? 'three (map-ref keyed 3) "integer key")
+  (assert-equal? 'ex (map-ref keyed #\x) "char key")
+  (map-set! keyed '(1 2) 'list-key)
+  (assert-equal? 'list-key (map-ref keyed (list 1 2)) "list key compared structurally")
+  (map-set! keyed car 'proc-key)
+  (assert-equal? 'proc-key (map-ref keyed car) "procedure key by identity")
+  (assert-equal? #t (string? (car (map-keys { "s": 1 }))) "map-keys keeps string keys")
+
+  (define ht (make-hash-table))
+  (hash-table-set! ht '(a b) 1)
+  (assert-equal? 1 (hash-table-ref ht (list 'a 'b)) "hash-table default equal?")
+  (assert-equal? 0 (hash-table-ref/default ht 'missing 0) "hash-table-ref/default")
+  (assert-equal? 'none (hash-table-ref ht 'missing (lambda () 'none)) "hash-table-ref thunk")
+  (hash-table-update!/default ht 'count (lambda (n) (+ n 1)) 0)
+  (assert-equal? 1 (hash-table-ref ht 'count) "hash-table-update!/default")
+  (assert-equal? 2 (hash-table-size ht) "hash-table-size")
+  (hash-table-delete! ht 'count)
+  (assert-equal? #f (hash-table-contains? ht 'count) "hash-table-delete!")
+
+  (define ht-custom (make-hash-table string=? (lambda (s) 0)))
+  (hash-table-set! ht-custom "k" 1)
+  (hash-table-set! ht-custom "k" 2)
+  (hash-table-set! ht-custom "j" 3)
+  (assert-equal? 2 (hash-table-size ht-custom) "custom equality and hash")
+  (assert-equal? 2 (hash-table-ref ht-custom "k") "custom equality lookup")
+  (assert-equal? #t (hash-table? ht-custom) "hash-table?")
+
+  (assert-equal? 65 (char->integer #\A) "char->integer")
+  (assert-equal? #\space (integer->char 32) "integer->char named char")
+  (assert-equal? #t (char? #\newline) "char?")
+)
+
+;; --- Ordered and Sorted Maps ---
+(begin
+  (display "Testing Ordered and Sorted Maps...") (newline)
+  (define ordered { z: 1, a: 2, m: 3 })
+  (assert-equal? '(z a m) (map-keys ordered) "literal keeps insertion order")
+  (map-set! ordered 'a 20)
+  (map-set! ordered 'b 4)
+  (map-delete! ordered 'z)
+  (assert-equal? '((a 20) (m 3) (b 4)) (map->alist ordered) "update keeps position, delete keeps order")
+
+  (define sorted (make-sorted-map 'z 1 'a 2 'm 3))
+  (assert-equal? #t (sorted-map? sorted) "sorted-map?")
+  (assert-equal? #f (sorted-map? ordered) "sorted-map? on ordered map")
+  (assert-equal? '(a m z) (map-keys sorted) "sorted map key order")
+  (map-set! sorted 'b 0)
+  (assert-equal? '(a b m z) (map-keys sorted) "sorted map insert")
+  (assert-equal? '(b m) (map-keys (map-range sorted 'b 'z)) "map-range half-open")
+  (assert-equal? '(a b) (map-keys (map-range sorted '() 'm)) "map-range open lower bound")
+  (assert-equal? '() (map-keys (map-range sorted 'z 'a)) "map-range empty when lo > hi")
+  (assert-equal? '(a 2) (map-min sorted) "map-min")
+  (assert-equal? '(z 1) (map-max sorted) "map-max")
+  (assert-equal? '(a 20) (map-min ordered) "map-min on ordered map")
+  (assert-equal? #t (null? (map-max {})) "map-max empty")
+  (assert-equal? #t (equal? sorted (make-map 'z 1 'a 2 'm 3 'b 0)) "sorted and ordered maps compare by entries")
+  (assert-equal? '(1 2 10) (map-keys (make-sorted-map 10 'x 2 'y 1 'z)) "sorted integer keys")
+  (assert-equal? 2 (map-count (make-sorted-map car 'builtin (lambda (x) x) 'lambda)) "procedures are distinct sorted keys")
+)
+
+;; --- Sets ---
+(begin
+  (display "Testing Sets...") (newline)
+  (define set1 #{1 2 3})
+  (assert-equal? #t (set? set1) "set? true")
+  (assert-equal? #f (set? [1, 2]) "set? false")
+  (assert-equal? 3 (set-size set1) "set-size")
+  (assert-equal? #t (equal? #{1, 2, 3} set1) "set literal with commas")
+  (assert-equal? #t (equal? #{3 2 1} set1) "set equality ignores order")
+  (assert-equal? 2 (set-size #{1 1 2}) "set literal drops duplicates")
+  (set-add! set1 4 1)
+  (assert-equal? '(1 2 3 4) (set->list set1) "set-add! keeps insertion order")
+  (set-remove! set1 1)
+  (assert-equal? #f (set-contains? set1 1) "set-remove!")
+  (assert-equal? #t (set-contains? set1 4) "set-contains?")
+  (assert-equal? '(1 2 3) (set->list (set-union #{1 2} #{2 3})) "set-union")
+  (assert-equal? '(2) (set->list (set-intersection #{1 2} #{2 3})) "set-intersection")
+  (assert-equal? '(1) (set->list (set-difference #{1 2} #{2 3})) "set-difference")
+  (assert-equal? #t (equal? #{a b} (list->set '(b a b))) "list->set")
+  (assert-equal? #t (equal? #{"x" 1} (make-set 1 "x")) "make-set")
+  (assert-equal? #t (set-contains? #{(1 2)} (list 1 2)) "set elements compared structurally")
+)
+
+;; --- Equivalence Predicates ---
+(begin
+  (display "Testing Equivalence Predicates...") (newline)
+  (assert-equal? #t (eq? 'sym 'sym) "eq? symbols")
+  (assert-equal? #t (eqv? 42 42) "eqv? integers")
+  (assert-equal? #t (eqv? #\a #\a) "eqv? chars")
+  (assert-equal? #t (eq? car car) "eq? same builtin")
+  (assert-equal? #t (equal? car car) "equal? same builtin")
+  (define same-proc (lambda (x) x))
+  (assert-equal? #t (eq? same-proc same-proc) "eq? same lambda")
+  (assert-equal? #f (eq? same-proc (lambda (x) x)) "eq? distinct lambdas")
+  (define shared-arr [1, 2])
+  (assert-equal? #t (eq? shared-arr shared-arr) "eq? same array")
+  (assert-equal? #f (eq? shared-arr [1, 2]) "eq? distinct arrays")
+  (assert-equal? #f (eqv? { a: 1 } { a: 1 }) "eqv? distinct maps")
+  (assert-equal? #t (equal? shared-arr [1, 2]) "equal? arrays structurally")
+  (assert-equal? #t (equal? '() (list)) "equal? empty lists")
+  (assert-equal? #t (eq? '() (cdr '(1))) "eq? empty lists")
+  (assert-equal? '(c d) (memq 'c '(a b c d)) "memq")
+  (assert-equal? #f (memv 5 '(1 2)) "memv missing")
+  (assert-equal? '((1) (2)) (member '(1) '((0) (1) (2))) "member structural")
+  (assert-equal? '(b 2) (assq 'b '((a 1) (b 2))) "assq")
+  (assert-equal? '("b" 2) (assoc "b" '(("a" 1) ("b" 2))) "assoc")
+  (assert-equal? '(2 3) (member 2 '(1 2 3) (lambda (a b) (= a b))) "member custom compare")
+  (assert-equal? 'two (case (+ 1 1) ((1) 'one) ((2 3) 'two) (else 'other)) "case match")
+  (assert-equal? 'other (case 'x ((a b) 'ab) (else 'other)) "case else")
+  (assert-equal? #f (eq? (list 1 2) (list 1 2)) "eq? fresh lists")
+  (assert-equal? #t (equal? (list 1 2) (list 1 2)) "equal? fresh lists")
+  (assert-equal? #f (eqv? "ab" "ab") "eqv? strings")
+  (assert-equal? #t (equal? "ab" "ab") "equal? strings")
+  (assert-equal? #f (memq (list 1) '((0) (1))) "memq does not compare lists by content")
+  (assert-equal? #f (assv "b" '(("a" 1) ("b" 2))) "assv does not compare strings by content")
+  (assert-equal? 'other (case "a" (("a") 'string) (else 'other)) "case does not compare strings by content")
+)
+
+;; --- Cyclic Data ---
+(begin
+  (display "Testing Cyclic Data...") (newline)
+  (define cyc-arr [1, 2])
+  (array-set! cyc-arr 1 cyc-arr)
+  (assert-equal? #t (eq? cyc-arr (array-ref cyc-arr 1)) "array contains itself")
+  (assert-equal? #t (equal? cyc-arr '#0=[1, #0#]) "equal? cyclic arrays")
+  (assert-equal? #f (equal? cyc-arr '#0=[2, #0#]) "equal? cyclic arrays differ")
+  (define cyc-map '#0={x: 1, self: #0#})
+  (assert-equal? #t (eq? cyc-map (map-ref cyc-map 'self)) "datum label map")
+  (assert-equal? #t (equal? cyc-map '#5={x: 1, self: #5#}) "equal? cyclic maps")
+  (define cyc-set (make-set 1))
+  (set-add! cyc-set cyc-set)
+  (assert-equal? 2 (set-size cyc-set) "set contains itself")
+  (define nested-set (make-set (make-set)))
+  (set-add! nested-set nested-set)
+  (assert-equal? 2 (set-size nested-set) "set of sets contains itself")
+  (assert-equal? #t (set-contains? nested-set nested-set) "set-contains? itself")
+  (define self-keyed (make-map))
+  (map-set! self-keyed (make-map) 1)
+  (map-set! self-keyed self-keyed 2)
+  (assert-equal? 2 (map-ref self-keyed self-keyed) "map is its own key")
+  (define self-sorted (make-sorted-map))
+  (map-set! self-sorted (make-map) 1)
+  (map-set! self-sorted self-sorted 2)
+  (assert-equal? 2 (map-ref self-sorted self-sorted) "sorted map is its own key")
+  (assert-equal? '((a) (a)) '(#1=(a) #1#) "datum label sharing")
+)
+
+;; --- Printer Round-Tripping ---
+(begin
+  (display "Testing Printer Syntax...") (newline)
+  (assert-equal? "hello world" (symbol->string '|hello world|) "bar-quoted symbol")
+  (assert-equal? 'abc '|abc| "bar-quoted plain symbol")
+  (assert-equal? (string->symbol "a|b") '|a\|b| "bar-quoted symbol escape")
+  (assert-equal? (string->symbol "12") '|12| "numeric-looking symbol")
+  (assert-equal? #t (string=? "\x3bb;" "λ") "string hex escape")
+  (assert-equal? #t (string=? "a\"b" "a\x22;b") "string quote escapes")
+)
+
+;; --- Pretty Printer ---
+(begin
+  (display "Testing Pretty Printer...") (newline)
+  (assert-equal? 80 (pretty-print-width) "pretty-print-width default")
+  (assert-equal? 40 (pretty-print-width 40) "pretty-print-width set")
+  (assert-equal? 40 (pretty-print-width) "pretty-print-width get")
+  (pretty-print-width 80)
+  (assert-equal? "(define (f x)\n  (* x x))\n"
+                 (with-output-to-string (lambda () (pp '(define (f x) (* x x)) 16)))
+                 "pp indents define body")
+  (assert-equal? "[1, 2,\n 3, 4,\n 5]\n"
+                 (with-output-to-string (lambda () (pp [1, 2, 3, 4, 5] 8)))
+                 "pp fills atoms")
+)
+
+;; --- Format ---
+(begin
+  (display "Testing Format...") (newline)
+  (assert-equal? "Hi Bob, \"Bob\"" (format "Hi ~a, ~s" "Bob" "Bob") "format ~a ~s")
+  (assert-equal? "255 ff 11111111" (format #f "~d ~x ~b" 255 255 255) "format integers")
+  (assert-equal? "[   42][00042][00ff]" (format "[~5d][~5,'0d][~.4x]" 42 42 255) "format number padding")
+  (assert-equal? "[ab  ][  ab][tru]" (format "[~4a][~4@a][~.3a]" 'ab 'ab "truncate") "format text padding")
+  (assert-equal? "a\nb~" (format "a~%b~~") "format ~% ~~")
+  (assert-equal? (format "1~16777215a" "") (format "~16777216a" 1) "format padding up to the sequence limit")
+  (assert-equal? "Format Error at offset 2: parameter 100000000000 exceeds the maximum of 16777216"
+                 (error-object-message (guard (e (#t e)) (format "a ~100000000000a" 1)))
+                 "format rejects padding over the sequence limit")
+)
+
+;; --- Ports ---
+(begin
+  (display "Testing Ports...") (newline)
+  (define out-port (open-output-string))
+  (write "a\"b" out-port)
+  (display #\space out-port)
+  (display 'sym out-port)
+  (newline out-port)
+  (format out-port "~a=~d" 'x 1)
+  (assert-equal? "\"a\\\"b\" sym\nx=1" (get-output-string out-port) "string output port")
+  (assert-equal? "hi [1, 2]" (with-output-to-string (lambda () (display "hi ") (write [1, 2]))) "with-output-to-string")
+  (define in-port (open-input-string "ab"))
+  (assert-equal? #\a (peek-char in-port) "peek-char")
+  (assert-equal? #\a (read-char in-port) "read-char")
+  (assert-equal? #\b (read-char in-port) "read-char second")
+  (assert-equal? #t (eof-object? (read-char in-port)) "read-char at eof")
+  (assert-equal? #t (input-port? in-port) "input-port?")
+  (assert-equal? #f (input-port? out-port) "input-port? output port")
+  (assert-equal? #\a (call-with-port (open-input-string "a") read-char) "call-with-port")
+  (define lines-port (open-input-string "one\ntwo\r\nthree"))
+  (assert-equal? "one" (read-line lines-port) "read-line")
+  (assert-equal? "two" (read-line lines-port) "read-line crlf")
+  (assert-equal? "th" (read-string 2 lines-port) "read-string")
+  (assert-equal? "ree" (read-line lines-port) "read-line last line")
+  (assert-equal? #t (eof-object? (read-line lines-port)) "read-line at eof")
+  (define data-port (open-input-string "(a \"b\") [1, 2] ; comment\n sym"))
+  (assert-equal? '(a "b") (read data-port) "read list")
+  (assert-equal? [1, 2] (read data-port) "read array")
+  (assert-equal? 'sym (read data-port) "read after comment")
+  (assert-equal? #t (eof-object? (read data-port)) "read at eof")
+  (assert-equal? #t (char-ready? data-port) "char-ready? at eof")
+  (close-port in-port)
+  (assert-equal? #f (input-port-open? in-port) "close-port")
+)
+;; --- File System ---
+(begin
+  (display "Testing File System...") (newline)
+  (define tmp (make-temporary-file "fs-test"))
+  (assert-equal? #t (file-exists? tmp) "make-temporary-file creates the file")
+  (call-with-port (open-output-file tmp) (lambda (p) (display "hello" p)))
+  (assert-equal? 5 (file-size tmp) "file-size")
+  (assert-equal? #t (> (file-modification-time tmp) 0) "file-modification-time")
+  (assert-equal? "tmp" (path-extension tmp) "path-extension")
+  (assert-equal? #f (path-extension "/a/b") "path-extension none")
+  (assert-equal? "a/b/c.txt" (path-join "a" "b" "c.txt") "path-join")
+  (assert-equal? "/a/b" (path-dirname "/a/b/c.txt") "path-dirname")
+  (assert-equal? "." (path-dirname "c.txt") "path-dirname bare name")
+  (assert-equal? "c.txt" (path-basename "/a/b/c.txt") "path-basename")
+  (define dir (format "~a.d" tmp))
+  (make-directory (path-join dir "sub") #t)
+  (assert-equal? #t (file-directory? dir) "make-directory with parents")
+  (rename-file tmp (path-join dir "moved.txt"))
+  (assert-equal? #f (file-exists? tmp) "rename-file removes the old name")
+  (assert-equal? '("moved.txt" "sub") (directory-list dir) "directory-list")
+  (define err (guard (e (#t e)) (delete-directory dir)))
+  (assert-equal? #t (file-error? err) "file-error?")
+  (assert-equal? 'directory-not-empty (io-error-kind err) "io-error-kind")
+  (assert-equal? 'delete-directory (io-error-operation err) "io-error-operation")
+  (assert-equal? dir (io-error-path err) "io-error-path")
+  (delete-file (path-join dir "moved.txt"))
+  (delete-directory (path-join dir "sub"))
+  (delete-directory dir)
+  (assert-equal? #f (file-exists? dir) "delete-directory")
+  (assert-equal? 'not-found
+                 (guard (e ((file-error? e) (io-error-kind e))) (open-input-file dir))
+                 "open-input-file missing file")
+  (assert-equal? "caught" (guard (e ((string? e) e) (else "caught")) (car 1)) "guard else clause")
+  (assert-equal? #f (file-error? (guard (e (#t e)) (car 1))) "file-error? on other errors")
+)
+
+;; --- Bytevectors ---
+(begin
+  (display "Testing Bytevectors...") (newline)
+  (define bv (bytevector 1 2 3))
+  (assert-equal? #u8(1 2 3) bv "bytevector literal equals bytevector")
+  (assert-equal? 3 (bytevector-length bv) "bytevector-length")
+  (bytevector-u8-set! bv 0 255)
+  (assert-equal? 255 (bytevector-u8-ref bv 0) "bytevector-u8-set!")
+  (assert-equal? #u8(0 0) (make-bytevector 2) "make-bytevector default fill")
+  (assert-equal? #u8(2 3) (bytevector-copy bv 1) "bytevector-copy")
+  (assert-equal? #u8(255 2 3 9) (bytevector-append bv #u8() #u8(9)) "bytevector-append")
+  (define buf (make-bytevector 4 0))
+  (bytevector-copy! buf 1 #u8(7 8 9) 1 3)
+  (assert-equal? #u8(0 8 9 0) buf "bytevector-copy!")
+  (assert-equal? "#u8(0 8 9 0)" (with-output-to-string (lambda () (write buf))) "write bytevector")
+  (assert-equal? #u8(206 187) (string->utf8 "λ") "string->utf8")
+  (assert-equal? "aλ" (utf8->string #u8(97 206 187)) "utf8->string")
+  (define words (make-bytevector 8 0))
+  (bytevector-u32-le-set! words 0 305419896)
+  (assert-equal? #u8(120 86 52 18) (bytevector-copy words 0 4) "bytevector-u32-le-set!")
+  (assert-equal? 2018915346 (bytevector-u32-be-ref words 0) "bytevector-u32-be-ref")
+  (bytevector-s16-be-set! words 4 -2)
+  (assert-equal? -2 (bytevector-s16-be-ref words 4) "bytevector-s16-be-ref")
+  (assert-equal? 65534 (bytevector-u16-be-ref words 4) "bytevector-u16-be-ref")
+  (assert-equal? #t (error-object? (guard (e (#t e)) (bytevector-u16-le-set! words 0 65536))) "u16 out of range")
+  (assert-equal? #t (error-object? (guard (e (#t e)) (bytevector-u32-le-ref words 6))) "u32 past the end")
+  (define out (open-output-bytevector))
+  (write-u8 1 out)
+  (write-bytevector #u8(2 3 4) out 1)
+  (assert-equal? #u8(1 3 4) (get-output-bytevector out) "binary output port")
+  (assert-equal? #t (binary-port? out) "binary-port?")
+  (assert-equal? #f (textual-port? out) "textual-port?")
+  (define in (open-input-bytevector #u8(5 6 7)))
+  (assert-equal? 5 (peek-u8 in) "peek-u8")
+  (assert-equal? 5 (read-u8 in) "read-u8")
+  (assert-equal? #u8(6 7) (read-bytevector 10 in) "read-bytevector")
+  (assert-equal? #t (eof-object? (read-u8 in)) "read-u8 at eof")
+  (define file (make-temporary-file "bv-test"))
+  (call-with-port (open-binary-output-file file) (lambda (p) (write-bytevector #u8(0 1 2 254) p)))
+  (assert-equal? #u8(0 1 2 254) (call-with-port (open-binary-input-file file) (lambda (p) (read-bytevector 100 p))) "binary file round trip")
+  (delete-file file)
+  (assert-equal? #f (file-exists? file) "binary file deleted")
+)
+
+;; --- Process and OS ---
+(begin
+  (display "Testing Process and OS...") (newline)
+  (assert-equal? #t (string? (car (command-line))) "command-line")
+  (assert-equal? #f (get-environment-variable "RUSTY_SCHEME_SURELY_UNSET") "get-environment-variable unset")
+  (assert-equal? #t (list? (get-environment-variables)) "get-environment-variables")
+  (assert-equal? 1000000 (jiffies-per-second) "jiffies-per-second")
+  (define t0 (current-jiffy))
+  (assert-equal? #f (< (current-jiffy) t0) "current-jiffy is monotonic")
+  (assert-equal? #t (> (current-second) 0) "current-second")
+  (define result (run-process "sh" '("-c" "echo out; echo err >&2; exit 3")))
+  (assert-equal? 3 (map-ref result 'status) "run-process status")
+  (assert-equal? "out\n" (map-ref result 'stdout) "run-process stdout")
+  (assert-equal? "err\n" (map-ref result 'stderr) "run-process stderr")
+  (assert-equal? "fed in" (map-ref (run-process "cat" '() "fed in") 'stdout) "run-process input")
+  (assert-equal? "a b\n" (process-output "echo" '("a" "b")) "process-output")
+  (assert-equal? 'not-found (guard (e ((file-error? e) (io-error-kind e))) (run-process "/no/such/program")) "run-process missing program")
+  (assert-equal? #t (error-object? (guard (e (#t e)) (process-output "false"))) "process-output failure")
+)
+
+;; --- Derived Forms ---
+(begin
+  (display "Testing Derived Forms...") (newline)
+  (assert-equal? 3 (let ((x 1) (y 2)) (+ x y)) "let")
+  (assert-equal? 2 (let ((x 1)) (let ((x 2) (y x)) (* x y))) "let inits see the outer scope")
+  (assert-equal? 4 (let* ((x 1) (y (+ x 1))) (* y 2)) "let*")
+  (assert-equal? 120 (let loop ((n 5) (acc 1)) (if (= n 0) acc (loop (- n 1) (* acc n)))) "named let")
+  (assert-equal? 'done (let loop ((n 100000)) (if (= n 0) 'done (loop (- n 1)))) "named let loops in constant space")
+  (assert-equal? #t (letrec ((ev? (lambda (n) (if (= n 0) #t (od? (- n 1)))))
+                             (od? (lambda (n) (if (= n 0) #f (ev? (- n 1))))))
+                      (ev? 10)) "letrec mutual recursion")
+  (assert-equal? 'b (cond ((= 1 2) 'a) ((= 1 1) 'b) (else 'c)) "cond")
+  (assert-equal? 'c (cond ((= 1 2) 'a) (else 'c)) "cond else")
+  (assert-equal? 6 (cond ((car '(5)) => (lambda (v) (+ v 1))) (else 0)) "cond =>")
+  (assert-equal? 7 (cond (#f 1) (7)) "cond test only")
+  (assert-equal? 3 (and 1 2 3) "and")
+  (assert-equal? #f (and 1 #f (car '())) "and short-circuits")
+  (assert-equal? #t (and) "empty and")
+  (assert-equal? 2 (or #f 2 (car '())) "or short-circuits")
+  (assert-equal? #f (or) "empty or")
+  (assert-equal? 'yes (when (= 1 1) 'no 'yes) "when")
+  (assert-equal? 'no (unless (= 1 2) 'no) "unless")
+  (define (square x) (* x x))
+  (assert-equal? 49 (square 7) "define shorthand")
+)
+
+;; --- Syntax Errors ---
+(begin
+  (display "Testing Syntax Errors...") (newline)
+  (assert-equal? 'ok (if #t 'ok (lambda)) "a malformed form is only an error when evaluated")
+  (assert-equal? 'caught (guard (e ((error-object? e) 'caught)) (let ((x)) x)) "guard catches a syntax error")
+  (define (uses-bad-syntax flag) (if flag (quote 1 2) 'fine))
+  (assert-equal? 'fine (uses-bad-syntax #f) "procedure body with an unreached malformed form")
+)
+
+;; --- Resource Limits ---
+(begin
+  (display "Testing Resource Limits...") (newline)
+  (define (count-down n) (if (= n 0) 0 (+ 1 (count-down (- n 1)))))
+  (assert-equal? 5000 (count-down 5000) "non-tail recursion within the default depth limit")
+  (define (count-down/eval n) (if (= n 0) 0 (+ 1 (eval (list 'count-down/eval (- n 1))))))
+  (assert-equal? 3000 (count-down/eval 3000) "deep recursion through a builtin grows the stack")
+  (assert-equal? 'done (let loop ((n 200000)) (if (= n 0) 'done (loop (- n 1)))) "tail calls do not count toward the depth limit")
+  ;; Text of `n` nested copies of `open` ... `close` around `inner`.
+  (define (nested-text n open inner close)
+    (let ((port (open-output-string)))
+      (define (repeat s i) (if (> i 0) (begin (display s port) (repeat s (- i 1)))))
+      (repeat open n)
+      (display inner port)
+      (repeat close n)
+      (get-output-string port)))
+  (define deep-text (nested-text 20000 "(" "" ")"))
+  (define deep (read (open-input-string deep-text)))
+  (assert-equal? deep-text (with-output-to-string (lambda () (display deep))) "printing a 20000-deep nested list")
+  (assert-equal? #t (equal? deep (read (open-input-string deep-text))) "comparing 20000-deep nested lists")
+  (set! deep '())
+  (assert-equal? '() deep "dropping a 20000-deep nested list")
+  (assert-equal? 8000 (eval (read (open-input-string (nested-text 8000 "(+ 1 " 0 ")")))) "evaluating 8000-deep nested code")
+)
+
 ;; --- Type Predicates ---
 (begin
   (display "Testing Type Predicates...") (newline)
@@ -244,13 +703,6 @@
   ;; list?, null?, array?, map? tested in their respective sections
 )
 
-;; --- Need a list-length helper for map tests ---
-(define list-length
-  (lambda (lst)
-    (if (null? lst)
-        0
-        (+ 1 (list-length (cdr lst))))))
-
 ;; --- Re-run Map tests requiring list-length ---
 (begin
  (display "Re-Testing Maps with list-length...")(newline)
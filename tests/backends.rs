@@ -0,0 +1,32 @@
+// Runs tests.scm on the tree-walking evaluator and on the bytecode VM. Both
+// must pass every test, and print the same thing: the tree-walker is the
+// reference for the VM.
+
+use std::path::Path;
+use std::process::Command;
+
+fn run_suite(options: &[&str]) -> String {
+    let suite = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests.scm");
+    let output = Command::new(env!("CARGO_BIN_EXE_rust-async"))
+        .args(options)
+        .arg(suite)
+        .output()
+        .expect("failed to run the interpreter");
+    let printed = String::from_utf8_lossy(&output.stdout).into_owned() + &String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "the suite stopped with an error ({:?}):\n{}", options, printed);
+    printed
+}
+
+#[test]
+fn vm_matches_tree_walker() {
+    let tree = run_suite(&[]);
+    let vm = run_suite(&["--vm"]);
+    for output in [&tree, &vm] {
+        assert!(output.contains("Test Suite Summary"), "the suite did not finish:\n{}", output);
+        assert!(output.lines().any(|line| line == "Failed: 0"), "some tests failed:\n{}", output);
+    }
+    for (line, (expected, got)) in tree.lines().zip(vm.lines()).enumerate() {
+        assert_eq!(expected, got, "line {} of the output differs", line + 1);
+    }
+    assert_eq!(tree.lines().count(), vm.lines().count(), "the outputs differ in length");
+}
@@ -0,0 +1,32 @@
+// Runs small scripts under the resource limit options. A limit error cannot
+// be caught by `guard`, so tests.scm cannot check them itself.
+
+use std::fs;
+use std::process::{Command, Output};
+
+fn run_script(name: &str, source: &str, options: &[&str]) -> Output {
+    let script = std::env::temp_dir().join(format!("rust-async-limits-{}-{}.scm", name, std::process::id()));
+    fs::write(&script, source).expect("failed to write the script");
+    let output = Command::new(env!("CARGO_BIN_EXE_rust-async"))
+        .args(options)
+        .arg(&script)
+        .output()
+        .expect("failed to run the interpreter");
+    let _ = fs::remove_file(&script);
+    output
+}
+
+#[test]
+fn format_padding_counts_against_max_elements() {
+    // Printed rather than returned, so only format itself can count the padding
+    let source = "(format #t \"~1000000a\" 1)";
+    let unlimited = run_script("padding", source, &[]);
+    assert!(unlimited.status.success(), "{}", String::from_utf8_lossy(&unlimited.stderr));
+    assert_eq!(unlimited.stdout.len(), 1_000_000);
+
+    let limited = run_script("padding-limited", source, &["--max-elements", "100000"]);
+    let stderr = String::from_utf8_lossy(&limited.stderr);
+    assert!(!limited.status.success(), "the padding was built under --max-elements");
+    assert!(stderr.contains("Memory limit exceeded"), "unexpected error: {}", stderr);
+    assert!(limited.stdout.is_empty());
+}